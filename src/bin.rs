@@ -1,26 +1,311 @@
-use common::FormattedItems;
+use common::{error::SerializableError, AbsolutePath, FormattedItems, Item, SerializableItem};
 use lib::*;
+use std::{
+    collections::HashMap,
+    env,
+    io::{self, Read},
+};
 
-fn go() -> Result<(), failure::Error> {
-    let config = config::Config::get()?;
-    verbose_println!();
-    let items = FormattedItems::from_items(resolver::get_items(&config)?);
+/// Exit code used when `status` finds conflicting or broken links, `diff` finds
+/// differences, or `verify` finds problems
+const EXIT_UNHEALTHY: i32 = 2;
+/// Exit code used when linking leaves any conflicts unresolved, whether the
+/// user declined to overwrite them or the conflict policy skipped them
+const EXIT_INCOMPLETE: i32 = 3;
+
+fn go(cli_config: config::cli::Config) -> Result<i32, failure::Error> {
+    // Setting up a dotfiles folder doesn't need (or want) any existing
+    // configuration to be resolved
+    if let config::cli::Command::Init { path, force } = &cli_config.command {
+        init::init(path, *force)?;
+        return Ok(0);
+    }
+
+    let print_config = cli_config.print_config;
+    let config = config::Config::from_cli(cli_config)?;
+    if print_config {
+        println!("{}", config);
+        return Ok(0);
+    }
     verbose_println!();
 
+    // Not every command needs the active dotfiles, so only resolve them on demand.
+    //
+    // Commands which inspect or modify links need templates to be replaced by their
+    // rendered versions, which are only written to disk if `write_templates` is
+    // set.
+    //
+    // Only items satisfying `filter` are kept. They're filtered before templates
+    // are rendered, so that `filter` sees the templates themselves.
+    let resolve_items = |filter: &dyn Fn(&Item) -> bool| -> Result<Vec<Item>, failure::Error> {
+        let mut items = resolver::cache::get_items(&config)?;
+        items.retain(|item| filter(item));
+
+        Ok(items)
+    };
+    let get_filtered_items = |render_templates: bool,
+                              write_templates: bool,
+                              filter: &dyn Fn(&Item) -> bool|
+     -> Result<FormattedItems, failure::Error> {
+        let mut items = resolve_items(filter)?;
+        if render_templates {
+            items = template::render_items(items, &config, write_templates)?;
+        }
+        verbose_println!();
+
+        Ok(FormattedItems::from_items(items))
+    };
+    let get_items = |render_templates: bool, write_templates: bool| {
+        get_filtered_items(render_templates, write_templates, &|_| true)
+    };
+
+    let pull_all = || -> Result<(), failure::Error> {
+        for dotfiles_path in &config.dotfiles_paths {
+            git::pull(dotfiles_path)?;
+        }
+
+        Ok(())
+    };
+    // The modes of `items` (see `resolver::mode_of`), by destination. They need
+    // finding before templates are rendered, so that the templates themselves are
+    // matched.
+    let modes_of = |items: &[Item]| -> HashMap<AbsolutePath, u32> {
+        items
+            .iter()
+            .filter_map(|item| {
+                resolver::mode_of(item, &config).map(|mode| (item.dest.clone(), mode))
+            })
+            .collect()
+    };
+    // Links `items`, running the hooks around them, then gives those placed the
+    // modes in `modes`.
+    //
+    // Gives whether every item was linked.
+    let link_items = |items: FormattedItems,
+                      modes: &HashMap<AbsolutePath, u32>,
+                      options: linker::Options|
+     -> Result<bool, failure::Error> {
+        // Hooks are run from the first dotfiles folder
+        let hook_dir = &config.dotfiles_paths[0];
+        hook::run_hooks(&config.pre_link_hooks, hook_dir, options.dry_run)?;
+        let mut modes: Vec<_> = (&items)
+            .into_iter()
+            .filter_map(|item| {
+                let item = item.item();
+                modes.get(&item.dest).map(|mode| (item.clone(), *mode))
+            })
+            .collect();
+        let summary = linker::link_items(items, &config.dotfiles_paths, options)?;
+        modes.retain(|(item, _)| summary.is_placed(&item.dest));
+        linker::set_modes(&modes, options.strategy, options.dry_run)?;
+        info_println!("{}", summary);
+        hook::run_hooks(&config.post_link_hooks, hook_dir, options.dry_run)?;
+
+        Ok(summary.is_complete())
+    };
+    // A conflict policy from the CLI takes precedence over the dotrc's
+    let with_conflict_policy = |options: linker::Options| linker::Options {
+        on_conflict: options.on_conflict.or(config.conflict_policy),
+        ..options
+    };
+    // Only items satisfying `filter` are linked. If `interactive` is set, the user
+    // picks which of them to link first.
+    let link = |options: linker::Options,
+                interactive: bool,
+                filter: &dyn Fn(&Item) -> bool|
+     -> Result<bool, failure::Error> {
+        let options = with_conflict_policy(options);
+        let items = resolve_items(filter)?;
+        let modes = modes_of(&items);
+        let items = template::render_items(items, &config, !options.dry_run)?;
+        verbose_println!();
+        let mut items = FormattedItems::from_items(items);
+        if interactive {
+            items = items.select_from_cli()?;
+        }
+
+        link_items(items, &modes, options)
+    };
+
+    // Commands which modify links hold the lock until they finish
+    let _lock = if config.command.modifies_links() {
+        Some(lock::Lock::acquire(&common::util::cache_dir())?)
+    } else {
+        None
+    };
+
     use config::cli::Command;
-    match config.command {
-        Command::Link { dry_run } => linker::link_items(items, dry_run)?,
-        Command::Ls => println!("{}", items),
+    match &config.command {
+        Command::Link {
+            options,
+            from_stdin: true,
+            ..
+        } => {
+            let mut input = String::new();
+            io::stdin().read_to_string(&mut input)?;
+            let items = common::parse_items(&input)?;
+            // Nothing is left on stdin to answer prompts with
+            let options = linker::Options {
+                on_conflict: with_conflict_policy(*options)
+                    .on_conflict
+                    .or(Some(linker::ConflictPolicy::Skip)),
+                ..*options
+            };
+            let modes = modes_of(&items);
+            let items = template::render_items(items, &config, !options.dry_run)?;
+            if !link_items(FormattedItems::from_items(items), &modes, options)? {
+                return Ok(EXIT_INCOMPLETE);
+            }
+        },
+        Command::Link {
+            options,
+            pull,
+            interactive,
+            only,
+            from_stdin: false,
+            prune,
+        } => {
+            if *pull || config.auto_pull {
+                pull_all()?;
+            } else {
+                // Clones of remote repositories are always kept up to date
+                for clone in &config.remote_clones {
+                    git::pull(clone)?;
+                }
+            }
+            let only = {
+                let mut builder = globset::GlobSetBuilder::new();
+                for glob in only {
+                    builder.add(glob.clone());
+                }
+
+                builder.build()?
+            };
+            let is_linked = |item: &Item| {
+                only.is_empty() || resolver::source_matches(item, &only, &config.dotfiles_paths)
+            };
+            let complete = link(*options, *interactive, &is_linked)?;
+            // Every active dotfile counts, whether or not it was just linked
+            if *prune {
+                let items = resolver::cache::get_items(&config)?;
+                linker::prune(
+                    &config.target,
+                    &config.dotfiles_paths,
+                    &items,
+                    options.dry_run,
+                )?;
+            }
+            if !complete {
+                return Ok(EXIT_INCOMPLETE);
+            }
+        },
+        Command::Sync { push, message } => {
+            pull_all()?;
+            let complete = link(linker::Options::default(), false, &|_| true)?;
+            if *push {
+                for dotfiles_path in &config.dotfiles_paths {
+                    git::commit_and_push(dotfiles_path, message)?;
+                }
+            }
+            if !complete {
+                return Ok(EXIT_INCOMPLETE);
+            }
+        },
+        Command::Diff => {
+            if linker::diff_items(&get_items(true, true)?)? {
+                return Ok(EXIT_UNHEALTHY);
+            }
+        },
+        Command::Doctor => println!("{}", doctor::Report::get(&config)?),
+        Command::Explain { path, format } => {
+            let path = AbsolutePath::from(env::current_dir()?.join(path));
+            let report = explain::Report::get(&config, &path)?;
+            match format {
+                config::cli::Format::Human => println!("{}", report),
+                config::cli::Format::Json => println!(
+                    "{}",
+                    serde_json::to_string_pretty(&explain::SerializableReport::new(&report))?
+                ),
+            }
+        },
+        Command::Tags => println!("{}", tags::Report::get(&config)?),
+        Command::Verify => {
+            let report = verify::Report::get(&config)?;
+            println!("{}", report);
+            if report.has_problems() {
+                return Ok(EXIT_UNHEALTHY);
+            }
+        },
+        &Command::Clean { dry_run } => {
+            linker::clean(&config.target, &config.dotfiles_paths, dry_run)?
+        },
+        &Command::Unlink { dry_run } => {
+            println!(
+                "{}",
+                linker::unlink_items(get_items(true, false)?, dry_run)?
+            )
+        },
+        Command::Ls {
+            format,
+            full_paths,
+            only_from,
+        } => {
+            let is_listed = |item: &Item| {
+                only_from.is_empty() || only_from.iter().any(|name| item.is_from(name))
+            };
+            let items = get_filtered_items(false, false, &is_listed)?;
+            match format {
+                config::cli::Format::Human => println!("{}", items),
+                config::cli::Format::Json => {
+                    let items: Vec<_> = items
+                        .into_iter()
+                        .map(|item| SerializableItem::new(item.item(), *full_paths))
+                        .collect();
+                    println!("{}", serde_json::to_string_pretty(&items)?);
+                },
+            }
+        },
+        Command::Add { path, dry_run } => {
+            let dest = AbsolutePath::from(env::current_dir()?.join(path));
+            let source = resolver::default_source(
+                &dest,
+                &config.target,
+                &config.dotfiles_paths[0],
+                &config.no_dot,
+            )?;
+            linker::add(&dest, &source, *dry_run)?;
+        },
+        Command::Init { .. } => unreachable!("init is handled before loading the configuration"),
+        &Command::Completions { shell } => {
+            config::cli::Config::write_completions(shell, &mut io::stdout())
+        },
+        Command::Status => {
+            let status = status::Status::get(get_items(true, false)?)?;
+            println!("{}", status);
+            if status.has_problems() {
+                return Ok(EXIT_UNHEALTHY);
+            }
+        },
     }
 
-    Ok(())
+    Ok(0)
 }
 
 fn main() {
-    match go() {
-        Ok(()) => (),
+    let cli_config = config::cli::Config::get();
+    let json_errors = cli_config.json_errors;
+    match go(cli_config) {
+        Ok(code) => std::process::exit(code),
         Err(err) => {
-            eprintln!("Error: {}", err);
+            if json_errors {
+                let err = SerializableError::new(&err);
+                eprintln!(
+                    "{}",
+                    serde_json::to_string(&err).expect("errors must be serializable")
+                );
+            } else {
+                eprintln!("Error: {}", err);
+            }
             std::process::exit(1);
         },
     };
@@ -0,0 +1,54 @@
+//! Machine-readable errors, for `--json-errors`
+
+use failure::Fail;
+use serde::Serialize;
+use std::io;
+
+/// A serializable view of an error, along with the chain of errors which caused
+/// it (outermost first)
+#[derive(Debug, Serialize)]
+pub struct SerializableError {
+    pub kind: String,
+    pub message: String,
+    pub causes: Vec<SerializableCause>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct SerializableCause {
+    pub kind: String,
+    pub message: String,
+}
+
+impl SerializableError {
+    pub fn new(err: &failure::Error) -> Self {
+        let causes = err
+            .iter_causes()
+            .map(|cause| SerializableCause {
+                kind: kind(cause),
+                message: cause.to_string(),
+            })
+            .collect();
+
+        SerializableError {
+            kind: kind(err.as_fail()),
+            message: err.to_string(),
+            causes,
+        }
+    }
+}
+
+/// A stable name for the kind of error `fail` is: the name of its variant for
+/// error enums (as in `InvalidPlatform`), or of its type otherwise. I/O errors
+/// are named by their `io::ErrorKind` instead (as in `NotFound`).
+pub fn kind(fail: &dyn Fail) -> String {
+    if let Some(err) = fail.downcast_ref::<io::Error>() {
+        return format!("{:?}", err.kind());
+    }
+
+    // Error enums and structs derive `Debug`, which starts with the variant or
+    // type name
+    format!("{:?}", fail)
+        .chars()
+        .take_while(|c| c.is_alphanumeric() || *c == '_')
+        .collect()
+}
@@ -1,13 +1,17 @@
+pub mod error;
+pub mod style;
 pub mod util;
 
+use crate::info_println;
 use contracts::*;
-use derive_more::{AsRef, Deref, IntoIterator};
+use derive_more::{AsRef, Deref, From, IntoIterator};
 use failure::Fail;
 use itertools::Itertools;
+use serde::{Deserialize, Serialize};
 use std::{
     convert::From,
     fmt::{self, Display},
-    io::{self, Write},
+    io::{self, BufRead, Write},
     path::{Path, PathBuf},
     str::FromStr,
 };
@@ -18,51 +22,179 @@ use strum_macros::EnumIter;
 ///
 /// Note that `Linux` and `Wsl` are distinct - WSL platforms
 /// are not considred Linux by `dotman`.
+///
+/// `Unix` covers any Unix-like platform not otherwise listed.
 #[derive(Debug, Clone, Copy, PartialEq, Eq, EnumIter)]
 pub enum Platform {
     Windows,
     Macos,
     Linux,
     Wsl,
+    FreeBsd,
+    Unix,
 }
 use Platform::*;
 
 impl Platform {
-    /// Returns the valid strings corresponding to `self`
-    pub fn strs(&self) -> &[&'static str] {
+    /// Returns the other strings which parse as `self` (see `from_str`).
+    ///
+    /// These only apply to `--platform` and the dotrc, other than the
+    /// `folder_aliases`. Several distributions are aliases of `linux`, so
+    /// `platform-` folders named after them have to stay distinct.
+    fn aliases(&self) -> &[&'static str] {
+        match self {
+            Windows => &["win", "win32", "win64"],
+            Macos => &["mac", "osx", "macosx", "darwin"],
+            Linux => &["gnu/linux", "ubuntu", "debian", "fedora"],
+            Wsl => &[],
+            FreeBsd => &["bsd"],
+            Unix => &[],
+        }
+    }
+
+    /// The aliases which `platform-` folders may be named with too, as they
+    /// always could be
+    fn folder_aliases(&self) -> &[&'static str] {
         match self {
-            Windows => &["win", "windows"],
-            Macos => &["mac", "macos"],
-            Linux => &["linux"],
-            Wsl => &["wsl"],
+            Windows => &["win"],
+            Macos => &["mac"],
+            _ => &[],
         }
     }
+
+    /// Checks whether `name` (as in a `platform-` folder) stands for `self`:
+    /// only `self`'s own name and `folder_aliases` do, except that `unix`
+    /// stands for every platform other than Windows.
+    pub fn is_named(&self, name: &str) -> bool {
+        name == self.to_string()
+            || self.folder_aliases().contains(&name)
+            || (name == "unix" && *self != Windows)
+    }
+}
+
+impl Display for Platform {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let s = match self {
+            Windows => "windows",
+            Macos => "macos",
+            Linux => "linux",
+            Wsl => "wsl",
+            FreeBsd => "freebsd",
+            Unix => "unix",
+        };
+
+        f.pad(s)
+    }
 }
 
 #[derive(Debug, Fail)]
-#[fail(display = "unsupported platform \"{}\"", input)]
+#[fail(
+    display = "unsupported platform \"{}\" (valid platforms are {})",
+    input, valid
+)]
 pub struct PlatformParseError {
     input: String,
+    /// The platforms as displayed, since `Fail` can't format them itself
+    valid: String,
 }
 
 impl FromStr for Platform {
     type Err = PlatformParseError;
 
+    /// Parses a platform's name or any of its aliases (see
+    /// `Platform::aliases`), ignoring case and whitespace (so `Mac OS X` is
+    /// `macosx`)
     fn from_str(s: &str) -> Result<Platform, Self::Err> {
-        let s = s.trim().to_lowercase();
+        let normalized: String = s.split_whitespace().collect::<String>().to_lowercase();
 
         for platform in Platform::iter() {
-            if platform.strs().contains(&s.as_str()) {
+            if normalized == platform.to_string()
+                || platform.aliases().contains(&normalized.as_str())
+            {
                 return Ok(platform);
             }
         }
 
-        Err(PlatformParseError { input: s })
+        Err(PlatformParseError {
+            input: String::from(s.trim()),
+            valid: Platform::iter().join(", "),
+        })
+    }
+}
+
+/// Repeatedly prompts the user with `prompt` (followed by `choices`) until
+/// `parse` accepts the (trimmed, lowercased) input.
+///
+/// Empty input gives `default`, or prompts again if there's no default.
+///
+/// With `--yes`, gives `yes` without asking.
+fn read_answer_from_cli<T>(
+    prompt: &str,
+    choices: &str,
+    default: Option<T>,
+    yes: T,
+    parse: impl Fn(&str) -> Option<T>,
+) -> io::Result<T> {
+    if util::get_assume_yes() {
+        info_println!("{} ({}) y", prompt, choices);
+        return Ok(yes);
+    }
+
+    read_answer(
+        &mut io::stdin().lock(),
+        &mut io::stdout(),
+        prompt,
+        choices,
+        default,
+        parse,
+    )
+}
+
+/// Does the work of `read_answer_from_cli`, reading from `input` and prompting
+/// on `output`
+fn read_answer<T>(
+    input: &mut impl BufRead,
+    output: &mut impl Write,
+    prompt: &str,
+    choices: &str,
+    default: Option<T>,
+    parse: impl Fn(&str) -> Option<T>,
+) -> io::Result<T> {
+    let mut buf = String::new();
+    loop {
+        write!(output, "{} ({}) ", prompt, choices)?;
+        output.flush()?;
+
+        buf.clear();
+        if input.read_line(&mut buf)? == 0 {
+            return Err(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                "no answer given",
+            ));
+        }
+        let answer = buf.trim().to_lowercase();
+
+        if answer.is_empty() {
+            match default {
+                Some(default) => return Ok(default),
+                None => continue,
+            }
+        }
+
+        if let Some(answer) = parse(&answer) {
+            return Ok(answer);
+        }
     }
 }
 
+/// Checks whether `input` resembles `word` (i.e. `input` is a prefix of `word`,
+/// or vice versa).
+fn resembles(input: &str, word: &str) -> bool {
+    input.starts_with(word) || word.starts_with(input)
+}
+
 /// Represents a yes/no value
-#[derive(Clone, Copy, Debug)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub enum YN {
     Yes,
     No,
@@ -73,37 +205,131 @@ impl YN {
     /// Prompts the user with `prompt` and asks for a yes/no answer.
     /// Will continue asking until input resembling yes/no is given.
     pub fn read_from_cli(prompt: &str) -> io::Result<Self> {
-        let mut buf = String::new();
-        loop {
-            print!("{} (y/n) ", prompt);
-            io::stdout().flush()?;
+        read_answer_from_cli(prompt, "y/n", None, Yes, YN::parse)
+    }
 
-            io::stdin().read_line(&mut buf)?;
-            buf = buf.trim().to_lowercase();
+    /// Like `read_from_cli`, but answers `default` (shown capitalized in the
+    /// prompt) when nothing is entered.
+    pub fn read_from_cli_default(prompt: &str, default: YN) -> io::Result<Self> {
+        read_answer_from_cli(prompt, default.choices(), Some(default), Yes, YN::parse)
+    }
 
-            if buf.is_empty() {
-                continue;
-            }
+    fn parse(input: &str) -> Option<Self> {
+        if resembles(input, "yes") {
+            Some(Yes)
+        } else if resembles(input, "no") {
+            Some(No)
+        } else {
+            None
+        }
+    }
 
-            if buf.starts_with("yes") || "yes".starts_with(&buf) {
-                return Ok(Yes);
-            } else if buf.starts_with("no") || "no".starts_with(&buf) {
-                return Ok(No);
-            } else {
-                buf.clear();
-                continue;
-            }
+    /// The choices shown when `self` is the default
+    fn choices(self) -> &'static str {
+        match self {
+            Yes => "Y/n",
+            No => "y/N",
         }
     }
 }
 
-/// Represents an (owned) path which must be absolute
+/// Represents a yes/no value which may also be applied to every subsequent
+/// question
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum YNAll {
+    Yes,
+    No,
+    YesToAll,
+    NoToAll,
+}
+
+impl YNAll {
+    /// Prompts the user with `prompt` and asks for a yes/no answer, or one of
+    /// "all" (yes to all) or "quit" (no to all).
+    /// Will continue asking until input resembling one of these is given.
+    pub fn read_from_cli(prompt: &str) -> io::Result<Self> {
+        read_answer_from_cli(prompt, "y/n/a/q", None, YNAll::Yes, YNAll::parse)
+    }
+
+    /// Like `read_from_cli`, but answers `default` (shown capitalized in the
+    /// prompt) when nothing is entered.
+    pub fn read_from_cli_default(prompt: &str, default: YN) -> io::Result<Self> {
+        let (choices, default) = YNAll::with_default(default);
+
+        read_answer_from_cli(prompt, choices, Some(default), YNAll::Yes, YNAll::parse)
+    }
+
+    /// Like `read_from_cli_default`, but reads the answer from `input` (and
+    /// prompts on `output`) instead of the terminal. `--yes` doesn't apply.
+    pub fn read_default_from(
+        input: &mut impl BufRead,
+        output: &mut impl Write,
+        prompt: &str,
+        default: YN,
+    ) -> io::Result<Self> {
+        let (choices, default) = YNAll::with_default(default);
+
+        read_answer(input, output, prompt, choices, Some(default), YNAll::parse)
+    }
+
+    /// The choices to show when `default` is the default, and the answer it
+    /// stands for
+    fn with_default(default: YN) -> (&'static str, Self) {
+        match default {
+            Yes => ("Y/n/a/q", YNAll::Yes),
+            No => ("y/N/a/q", YNAll::No),
+        }
+    }
+
+    fn parse(input: &str) -> Option<Self> {
+        if resembles(input, "yes") {
+            Some(YNAll::Yes)
+        } else if resembles(input, "no") {
+            Some(YNAll::No)
+        } else if resembles(input, "all") {
+            Some(YNAll::YesToAll)
+        } else if resembles(input, "quit") {
+            Some(YNAll::NoToAll)
+        } else {
+            None
+        }
+    }
+
+    /// The single-shot answer corresponding to `self`
+    pub fn answer(self) -> YN {
+        match self {
+            YNAll::Yes | YNAll::YesToAll => Yes,
+            YNAll::No | YNAll::NoToAll => No,
+        }
+    }
+
+    /// Whether `self` should apply to all subsequent questions
+    pub fn applies_to_all(self) -> bool {
+        match self {
+            YNAll::Yes | YNAll::No => false,
+            YNAll::YesToAll | YNAll::NoToAll => true,
+        }
+    }
+}
+
+/// Represents an (owned) path which must be absolute.
+///
+/// The path is lexically normalized (see `util::normalize`), so different
+/// spellings of the same path compare equal.
 #[derive(AsRef, Clone, Debug, Deref, Eq, Hash, PartialEq)]
 #[as_ref(forward)]
 pub struct AbsolutePath {
     path: PathBuf,
 }
 
+impl AbsolutePath {
+    /// Resolves every symlink in the path, which (unlike normalization) needs
+    /// the path to exist
+    pub fn canonicalize(&self) -> io::Result<AbsolutePath> {
+        Ok(AbsolutePath::from(self.path.canonicalize()?))
+    }
+}
+
 impl Display for AbsolutePath {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         f.pad(&util::home_to_tilde(&self.path).display().to_string())
@@ -120,7 +346,9 @@ impl Display for AbsolutePath {
 impl From<PathBuf> for AbsolutePath {
     #[pre(path.is_absolute())]
     fn from(path: PathBuf) -> Self {
-        AbsolutePath { path }
+        AbsolutePath {
+            path: util::normalize(path),
+        }
     }
 }
 
@@ -128,7 +356,7 @@ impl From<&Path> for AbsolutePath {
     #[pre(path.is_absolute())]
     fn from(path: &Path) -> Self {
         AbsolutePath {
-            path: path.to_path_buf(),
+            path: util::normalize(path),
         }
     }
 }
@@ -142,10 +370,13 @@ impl From<&str> for AbsolutePath {
 /// Represents the location of a dotfile (the source) and the
 /// location of the symlink pointing to the source (the destination) as a pair
 /// of absolute paths to the two files.
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct Item {
     pub source: AbsolutePath,
     pub dest: AbsolutePath,
+    /// The innermost prefixed folder (relative to its dotfiles folder) the
+    /// source was found in, if any
+    pub origin: Option<PathBuf>,
 }
 
 impl Item {
@@ -153,8 +384,118 @@ impl Item {
         Item {
             source: source.into(),
             dest: dest.into(),
+            origin: None,
         }
     }
+
+    /// Checks whether the item was found (at any depth) within a prefixed
+    /// folder named `name`
+    pub fn is_from(&self, name: &str) -> bool {
+        match &self.origin {
+            Some(origin) => origin.iter().any(|component| component == name),
+            None => false,
+        }
+    }
+}
+
+/// A serializable view of an `Item`, for machine-readable output
+#[derive(Debug, Serialize)]
+pub struct SerializableItem {
+    pub source: String,
+    pub dest: String,
+}
+
+impl SerializableItem {
+    /// Creates a view of `item`. Unless `full_paths` is set, paths within the
+    /// home directory are abbreviated with a tilde.
+    pub fn new(item: &Item, full_paths: bool) -> Self {
+        let path_string = |path: &AbsolutePath| {
+            if full_paths {
+                path.display().to_string()
+            } else {
+                path.to_string()
+            }
+        };
+
+        SerializableItem {
+            source: path_string(&item.source),
+            dest: path_string(&item.dest),
+        }
+    }
+}
+
+/// An item as read from elsewhere (see `parse_items`), before its paths are
+/// checked
+#[derive(Debug, Deserialize)]
+struct DeserializableItem {
+    source: PathBuf,
+    dest: PathBuf,
+}
+
+/// Reads a list of items, given either as the JSON array `ls --format json`
+/// prints, or as lines each holding a source and a destination separated by a
+/// tab. Blank lines are skipped.
+///
+/// Paths may start with a tilde (for the home directory), but must otherwise be
+/// absolute, and each source must exist.
+pub fn parse_items(input: &str) -> Result<Vec<Item>, ItemsParseError> {
+    let items: Vec<DeserializableItem> = if input.trim_start().starts_with('[') {
+        serde_json::from_str(input)?
+    } else {
+        input
+            .lines()
+            .enumerate()
+            .filter(|(_, line)| !line.trim().is_empty())
+            .map(|(i, line)| match line.split_once('\t') {
+                Some((source, dest)) => Ok(DeserializableItem {
+                    source: PathBuf::from(source),
+                    dest: PathBuf::from(dest),
+                }),
+                None => Err(ItemsParseError::InvalidLine {
+                    number: i + 1,
+                    line: String::from(line),
+                }),
+            })
+            .collect::<Result<_, _>>()?
+    };
+
+    items
+        .into_iter()
+        .map(|item| {
+            let absolute = |path: PathBuf| {
+                let path = util::tilde_to_home(path);
+                if path.is_absolute() {
+                    Ok(AbsolutePath::from(path))
+                } else {
+                    Err(ItemsParseError::RelativePath(path.display().to_string()))
+                }
+            };
+            let (source, dest) = (absolute(item.source)?, absolute(item.dest)?);
+            if util::file_type(&source).is_err() {
+                return Err(ItemsParseError::MissingSource(source));
+            }
+
+            Ok(Item::new(source, dest))
+        })
+        .collect()
+}
+
+#[derive(Debug, From, Fail)]
+pub enum ItemsParseError {
+    #[fail(display = "invalid JSON ({})", _0)]
+    InvalidJson(#[fail(cause)] serde_json::Error),
+
+    #[fail(
+        display = "line {} isn't a source and a destination separated by a tab: \"{}\"",
+        number, line
+    )]
+    InvalidLine { number: usize, line: String },
+
+    #[fail(display = "{} isn't an absolute path", _0)]
+    RelativePath(String),
+
+    #[fail(display = "source {} doesn't exist", _0)]
+    MissingSource(AbsolutePath),
 }
 
 /// Just a wrapper for pretty-printing `Item`s
@@ -178,8 +519,9 @@ impl FormattedItem {
 impl Display for FormattedItem {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         f.pad(&format!(
-            "{:width$}  ->    {}",
+            "{:width$}  {}    {}",
             self.item.source,
+            style::paint("->", style::arrow()),
             self.item.dest,
             width = self.width
         ))
@@ -269,6 +611,78 @@ impl FormattedItems {
 
         FormattedItems { formatted_items }
     }
+
+    pub fn len(&self) -> usize {
+        self.formatted_items.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.formatted_items.is_empty()
+    }
+
+    /// Lists the items, numbered, and asks which of them to keep. Everything
+    /// is kept if nothing is entered (or with `--yes`).
+    pub fn select_from_cli(self) -> io::Result<Self> {
+        if self.is_empty() {
+            return Ok(self);
+        }
+
+        let len = self.len();
+        let width = len.to_string().len();
+        for (i, item) in self.formatted_items.iter().enumerate() {
+            println!("{:>width$}) {}", i + 1, item, width = width);
+        }
+
+        let all = vec![true; len];
+        let selected = read_answer_from_cli(
+            "Which should be linked (e.g. 1 3-5)?",
+            "All/none",
+            Some(all.clone()),
+            all,
+            |input| parse_selection(input, len),
+        )?;
+
+        let formatted_items = self
+            .formatted_items
+            .into_iter()
+            .zip(selected)
+            .filter_map(|(item, selected)| if selected { Some(item) } else { None })
+            .collect();
+
+        Ok(FormattedItems { formatted_items })
+    }
+}
+
+/// Parses a selection of some of `len` items, as in `1 3-5` (numbered from 1),
+/// `all`, or `none`. Each item is marked with whether it was selected.
+fn parse_selection(input: &str, len: usize) -> Option<Vec<bool>> {
+    if resembles(input, "all") {
+        return Some(vec![true; len]);
+    }
+    let mut res = vec![false; len];
+    if resembles(input, "none") {
+        return Some(res);
+    }
+
+    let parse_number = |s: &str| s.trim().parse().ok().filter(|n| (1..=len).contains(n));
+    for part in input
+        .split(|c: char| c == ',' || c.is_whitespace())
+        .filter(|part| !part.is_empty())
+    {
+        let (first, last) = match part.split_once('-') {
+            Some((first, last)) => (parse_number(first)?, parse_number(last)?),
+            None => (parse_number(part)?, parse_number(part)?),
+        };
+        if first > last {
+            return None;
+        }
+
+        for selected in &mut res[first - 1..last] {
+            *selected = true;
+        }
+    }
+
+    Some(res)
 }
 
 impl Display for FormattedItems {
@@ -276,3 +690,170 @@ impl Display for FormattedItems {
         f.pad(&self.formatted_items.iter().join("\n"))
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        parse_items, parse_selection, read_answer, util, AbsolutePath, ItemsParseError, Platform,
+        YNAll, YN,
+    };
+    use pretty_assertions::assert_eq;
+    use std::fs;
+    use tempfile::TempDir;
+
+    #[test]
+    fn item_lists() {
+        let dir = TempDir::new().unwrap();
+        let source = AbsolutePath::from(dir.path().join("vimrc"));
+        fs::write(&source, "").unwrap();
+        let dest = AbsolutePath::from(dir.path().join("home/.vimrc"));
+        let pairs = |input: &str| -> Vec<_> {
+            parse_items(input)
+                .unwrap()
+                .into_iter()
+                .map(|item| (item.source, item.dest))
+                .collect()
+        };
+
+        let json = serde_json::json!([{ "source": *source, "dest": *dest }]).to_string();
+        assert_eq!(pairs(&json), vec![(source.clone(), dest.clone())]);
+        let lines = format!("\n{}\t{}\n\n", source.display(), dest.display());
+        assert_eq!(pairs(&lines), vec![(source.clone(), dest.clone())]);
+
+        match parse_items(&format!(
+            "{}\t{}\n{}",
+            source.display(),
+            dest.display(),
+            source.display()
+        )) {
+            Err(ItemsParseError::InvalidLine { number, .. }) => assert_eq!(number, 2),
+            res => panic!("unexpected result {:?}", res),
+        }
+        match parse_items(&format!("{}\t.vimrc", source.display())) {
+            Err(ItemsParseError::RelativePath(path)) => assert_eq!(path, ".vimrc"),
+            res => panic!("unexpected result {:?}", res),
+        }
+        let missing = AbsolutePath::from(dir.path().join("gvimrc"));
+        match parse_items(&format!("{}\t{}", missing.display(), dest.display())) {
+            Err(ItemsParseError::MissingSource(path)) => assert_eq!(path, missing),
+            res => panic!("unexpected result {:?}", res),
+        }
+        assert!(matches!(
+            parse_items("[{\"source\": 1}]"),
+            Err(ItemsParseError::InvalidJson(_))
+        ));
+    }
+
+    #[test]
+    fn platform_aliases() {
+        let cases = [
+            ("osx", Platform::Macos),
+            ("darwin", Platform::Macos),
+            ("Mac OS X", Platform::Macos),
+            ("win32", Platform::Windows),
+            ("WIN64", Platform::Windows),
+            ("gnu/linux", Platform::Linux),
+            ("  Ubuntu ", Platform::Linux),
+            ("debian", Platform::Linux),
+            ("fedora", Platform::Linux),
+            ("bsd", Platform::FreeBsd),
+        ];
+        for (s, platform) in &cases {
+            assert_eq!(s.parse::<Platform>().unwrap(), *platform, "parsing {:?}", s);
+        }
+    }
+
+    #[test]
+    fn unknown_platform() {
+        let err = " Plan9 ".parse::<Platform>().unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "unsupported platform \"Plan9\" (valid platforms are windows, macos, linux, wsl, \
+             freebsd, unix)"
+        );
+    }
+
+    /// Answers the prompt `read_answer` shows with `input`, returning the
+    /// answer and everything which was prompted
+    fn answer(input: &str, default: Option<YN>) -> (YN, String) {
+        let mut output = vec![];
+        let answer = read_answer(
+            &mut input.as_bytes(),
+            &mut output,
+            "Overwrite?",
+            "y/n",
+            default,
+            YN::parse,
+        )
+        .unwrap();
+
+        (answer, String::from_utf8(output).unwrap())
+    }
+
+    #[test]
+    fn default_answer() {
+        assert_eq!(answer("\n", Some(YN::No)).0, YN::No);
+        assert_eq!(answer("yes\n", Some(YN::No)).0, YN::Yes);
+    }
+
+    #[test]
+    fn no_default_answer() {
+        let (answer, output) = answer("\nmaybe\nn\n", None);
+
+        assert_eq!(answer, YN::No);
+        assert_eq!(output, "Overwrite? (y/n) ".repeat(3));
+    }
+
+    #[test]
+    fn assume_yes() {
+        // Nothing else reads the flag in tests, so setting it can't race them
+        util::set_assume_yes(true);
+        let answers = (
+            YN::read_from_cli("Overwrite?"),
+            YNAll::read_from_cli_default("Overwrite?", YN::No),
+        );
+        util::set_assume_yes(false);
+
+        assert_eq!(answers.0.unwrap(), YN::Yes);
+        assert_eq!(answers.1.unwrap(), YNAll::Yes);
+    }
+
+    #[test]
+    fn default_choices() {
+        assert_eq!(YN::Yes.choices(), "Y/n");
+        assert_eq!(YN::No.choices(), "y/N");
+    }
+
+    #[test]
+    fn missing_answer() {
+        let mut output = vec![];
+        let res = read_answer(
+            &mut "".as_bytes(),
+            &mut output,
+            "Overwrite?",
+            "y/n/a/q",
+            Some(YNAll::No),
+            YNAll::parse,
+        );
+
+        assert!(res.is_err());
+    }
+
+    #[test]
+    fn selections() {
+        assert_eq!(
+            parse_selection("1 3-4", 5),
+            Some(vec![true, false, true, true, false])
+        );
+        assert_eq!(
+            parse_selection("2,2, 1-1", 3),
+            Some(vec![true, true, false])
+        );
+        assert_eq!(parse_selection("all", 2), Some(vec![true, true]));
+        assert_eq!(parse_selection("n", 2), Some(vec![false, false]));
+
+        for invalid in &["0", "4", "3-1", "1-", "x", "1 two"] {
+            assert_eq!(parse_selection(invalid, 3), None, "{}", invalid);
+        }
+    }
+}
@@ -0,0 +1,46 @@
+//! Terminal colors, which are only used when stdout is a terminal and
+//! `NO_COLOR` isn't set
+
+use lazy_static::lazy_static;
+use owo_colors::{OwoColorize, Style};
+use std::{
+    env,
+    fmt::Display,
+    io::{self, IsTerminal},
+};
+
+lazy_static! {
+    static ref ENABLED: bool = io::stdout().is_terminal()
+        && env::var_os("NO_COLOR")
+            .filter(|no_color| !no_color.is_empty())
+            .is_none();
+}
+
+/// Styles `x` with `style`, if colors are enabled
+pub fn paint(x: impl Display, style: Style) -> String {
+    if *ENABLED {
+        x.style(style).to_string()
+    } else {
+        x.to_string()
+    }
+}
+
+/// For the arrow between an item's source and destination
+pub fn arrow() -> Style {
+    Style::new().cyan().bold()
+}
+
+/// For actions being taken, like linking
+pub fn action() -> Style {
+    Style::new().green()
+}
+
+/// For things being left alone
+pub fn skip() -> Style {
+    Style::new().dimmed()
+}
+
+/// For prompts about overwriting something
+pub fn conflict() -> Style {
+    Style::new().yellow().bold()
+}
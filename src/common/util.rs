@@ -1,12 +1,17 @@
 use crate::common::Platform;
+use failure::Fail;
 use lazy_static::lazy_static;
 use std::{
-    collections::HashSet,
+    env,
     ffi::OsStr,
-    hash::Hash,
+    fs,
+    hash::Hasher,
     io,
-    path::{Path, PathBuf},
-    sync::atomic::{AtomicBool, Ordering},
+    path::{Component, Path, PathBuf},
+    sync::{
+        atomic::{AtomicBool, AtomicU8, Ordering},
+        OnceLock,
+    },
 };
 
 #[cfg(target_os = "macos")]
@@ -18,6 +23,15 @@ const BASIC_PLATFORM: Platform = Platform::Linux;
 #[cfg(target_os = "windows")]
 const BASIC_PLATFORM: Platform = Platform::Windows;
 
+#[cfg(target_os = "freebsd")]
+const BASIC_PLATFORM: Platform = Platform::FreeBsd;
+
+#[cfg(all(
+    unix,
+    not(any(target_os = "macos", target_os = "linux", target_os = "freebsd"))
+))]
+const BASIC_PLATFORM: Platform = Platform::Unix;
+
 lazy_static! {
     static ref WSL: bool = wsl::is_wsl();
 }
@@ -40,40 +54,50 @@ pub fn append_vecs<T>(x: Vec<T>, mut y: Vec<T>) -> Vec<T> {
     res
 }
 
-/// Searches for an element of the iterator which appears more than once.
-///
-/// If no such element exists, `find_duplicate()` returns `None`.
-/// Otherwise, `find_duplicate()` returns `Some(element)`, where `element` is
-/// the first one which appears more than once.
-pub fn find_duplicate<I>(iter: I) -> Option<I::Item>
-where
-    I: IntoIterator,
-    I::Item: Clone + Eq + Hash,
-{
-    let mut seen = HashSet::new();
-    for x in iter {
-        if seen.contains(&x) {
-            return Some(x);
+/// The environment variable which overrides the home directory
+const HOME_VAR: &str = "DOTMAN_HOME";
+
+/// The home directory, resolved the first time it's needed
+static HOME_DIR: OnceLock<PathBuf> = OnceLock::new();
+
+/// The home directory, which is `$DOTMAN_HOME` if set (or whatever was passed
+/// to `set_home_dir`), or else the user's actual home directory
+pub fn home_dir() -> &'static Path {
+    HOME_DIR.get_or_init(|| {
+        if let Some(home_dir) = env::var_os(HOME_VAR).filter(|home_dir| !home_dir.is_empty()) {
+            return PathBuf::from(home_dir);
         }
 
-        seen.insert(x);
-    }
+        match dirs::home_dir() {
+            Some(home_dir) => home_dir,
+            None => {
+                eprintln!("Error: couldn't find home directory");
+                std::process::exit(1);
+            },
+        }
+    })
+}
 
-    None
+/// Overrides the home directory, e.g. to link into a sandbox in tests.
+///
+/// This only works before the home directory is first used, so otherwise the
+/// home directory already in use is returned as an error.
+pub fn set_home_dir(home_dir: impl Into<PathBuf>) -> Result<(), &'static Path> {
+    HOME_DIR.set(home_dir.into()).map_err(|_| self::home_dir())
 }
 
-lazy_static! {
-    static ref HOME_DIR: PathBuf = match dirs::home_dir() {
-        Some(home_dir) => home_dir,
-        None => {
-            eprintln!("Error: couldn't find home directory");
-            std::process::exit(1);
-        },
+/// The directory in which `dotman` keeps generated files. While the home
+/// directory is overridden (see `home_dir`), that's `.cache/dotman` within it,
+/// so that nothing is written to the user's actual cache.
+pub fn cache_dir() -> PathBuf {
+    let cache_dir = match dirs::home_dir() {
+        Some(actual_home_dir) if actual_home_dir == home_dir() => dirs::cache_dir(),
+        _ => None,
     };
-}
 
-pub fn home_dir() -> &'static Path {
-    HOME_DIR.as_path()
+    cache_dir
+        .unwrap_or_else(|| home_dir().join(".cache"))
+        .join("dotman")
 }
 
 /// If `path` begins with the absolute path of the home directory, replaces it
@@ -99,16 +123,191 @@ pub fn tilde_to_home(path: impl AsRef<Path>) -> PathBuf {
     }
 }
 
-/// Checks if a filename is prefixed by a '.' character.
-/// If the path cannot be read as UTF-8, assume it isn't hidden.
+#[derive(Debug, Fail)]
+pub enum ExpandError {
+    #[fail(display = "undefined environment variable ${} in \"{}\"", name, input)]
+    UndefinedVariable { name: String, input: String },
+
+    #[fail(display = "unterminated \"${{\" in \"{}\"", input)]
+    Unterminated { input: String },
+}
+
+/// Expands the environment variables (written as `$VAR` or `${VAR}`) in `s`.
+///
+/// A `$` not followed by a variable name is left as is.
+pub fn expand_env_vars(s: &str) -> Result<String, ExpandError> {
+    expand_vars_with(s, |name| env::var(name).ok())
+}
+
+/// Expands variables as in `expand_env_vars`, looking their values up with
+/// `lookup`
+fn expand_vars_with(
+    s: &str,
+    lookup: impl Fn(&str) -> Option<String>,
+) -> Result<String, ExpandError> {
+    let is_name_char = |c: char| c.is_ascii_alphanumeric() || c == '_';
+
+    let mut res = String::new();
+    let mut rest = s;
+    while let Some(start) = rest.find('$') {
+        res.push_str(&rest[..start]);
+        let after = &rest[start + 1..];
+
+        let (name, remaining) = if let Some(braced) = after.strip_prefix('{') {
+            match braced.find('}') {
+                Some(end) => (&braced[..end], &braced[end + 1..]),
+                None => {
+                    return Err(ExpandError::Unterminated {
+                        input: s.to_owned(),
+                    })
+                },
+            }
+        } else {
+            let end = after.find(|c| !is_name_char(c)).unwrap_or(after.len());
+            (&after[..end], &after[end..])
+        };
+
+        if name.is_empty() || name.starts_with(|c: char| c.is_ascii_digit()) {
+            res.push('$');
+            rest = after;
+            continue;
+        }
+
+        match lookup(name) {
+            Some(value) => res.push_str(&value),
+            None => {
+                return Err(ExpandError::UndefinedVariable {
+                    name: name.to_owned(),
+                    input: s.to_owned(),
+                })
+            },
+        }
+        rest = remaining;
+    }
+    res.push_str(rest);
+
+    Ok(res)
+}
+
+/// Lexically normalizes `path`, removing `.` components and resolving `..`
+/// components against their parents. The filesystem is never touched, so
+/// symlinks aren't resolved.
+pub fn normalize(path: impl AsRef<Path>) -> PathBuf {
+    let mut res = PathBuf::new();
+    for component in path.as_ref().components() {
+        match component {
+            Component::CurDir => (),
+            Component::ParentDir if res.file_name().is_some() => {
+                res.pop();
+            },
+            // `..` of the root is just the root
+            Component::ParentDir if res.has_root() => (),
+            component => res.push(component),
+        }
+    }
+
+    res
+}
+
+/// Computes a relative path leading from the directory `from` to `to`. Both
+/// paths should be absolute.
+///
+/// If the paths share no ancestor besides the root, returns `None`.
+pub fn relative_path(from: impl AsRef<Path>, to: impl AsRef<Path>) -> Option<PathBuf> {
+    let from: Vec<_> = from.as_ref().components().collect();
+    let to: Vec<_> = to.as_ref().components().collect();
+
+    let common = from.iter().zip(&to).take_while(|(x, y)| x == y).count();
+    let shares_ancestor = from[..common]
+        .iter()
+        .any(|component| matches!(component, Component::Normal(_)));
+    if !shares_ancestor {
+        return None;
+    }
+
+    let mut res = PathBuf::new();
+    for _ in common..from.len() {
+        res.push(Component::ParentDir);
+    }
+    res.extend(&to[common..]);
+
+    if res.as_os_str().is_empty() {
+        res.push(Component::CurDir);
+    }
+
+    Some(res)
+}
+
+/// Reads the target of the symlink at `link`, making it absolute if it was
+/// relative to the directory containing `link`.
+pub fn read_link_absolute(link: impl AsRef<Path>) -> io::Result<PathBuf> {
+    let link = link.as_ref();
+    let target = fs::read_link(link)?;
+
+    Ok(match link.parent() {
+        Some(parent) if target.is_relative() => normalize(parent.join(target)),
+        _ => target,
+    })
+}
+
+/// Parses a list of patterns (such as a dotignore), one per line. Blank lines
+/// and lines starting with `#` are ignored.
+pub fn parse_pattern_list(contents: &str) -> Vec<PathBuf> {
+    contents
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(PathBuf::from)
+        .collect()
+}
+
+/// Checks if a filename is prefixed by a '.' character, whether or not the rest
+/// of it is valid Unicode.
 pub fn is_hidden(filename: &OsStr) -> bool {
-    filename
-        .to_str()
-        .map(|s| s.starts_with('.'))
-        .unwrap_or(false)
+    filename.as_encoded_bytes().starts_with(b".")
+}
+
+/// Strips `prefix` from the start of `s`. On Unix, the rest of `s` needn't be
+/// valid Unicode. Elsewhere, `s` is assumed not to start with `prefix` if it
+/// isn't.
+pub fn strip_os_prefix<'a>(s: &'a OsStr, prefix: &str) -> Option<&'a OsStr> {
+    #[cfg(unix)]
+    {
+        use std::os::unix::ffi::OsStrExt;
+        s.as_bytes()
+            .strip_prefix(prefix.as_bytes())
+            .map(OsStr::from_bytes)
+    }
+    #[cfg(not(unix))]
+    {
+        s.to_str()?.strip_prefix(prefix).map(OsStr::new)
+    }
 }
 
+/// An FNV-1a hasher. Unlike the hashers in `std`, which aren't guaranteed to be
+/// stable between releases, its hashes can be stored and compared later.
 #[derive(Debug, Clone, Copy)]
+pub struct StableHasher(u64);
+
+impl Default for StableHasher {
+    fn default() -> Self {
+        StableHasher(0xcbf2_9ce4_8422_2325)
+    }
+}
+
+impl Hasher for StableHasher {
+    fn write(&mut self, bytes: &[u8]) {
+        for byte in bytes {
+            self.0 = (self.0 ^ u64::from(*byte)).wrapping_mul(0x0100_0000_01b3);
+        }
+    }
+
+    fn finish(&self) -> u64 {
+        self.0
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum FileType {
     File,
     Directory,
@@ -130,21 +329,66 @@ pub fn file_type(path: impl AsRef<Path>) -> io::Result<FileType> {
     })
 }
 
-static VERBOSE: AtomicBool = AtomicBool::new(false);
+/// How much output to print. Errors are printed regardless.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Verbosity {
+    Quiet,
+    Normal,
+    Verbose,
+    Debug,
+}
+
+static VERBOSITY: AtomicU8 = AtomicU8::new(Verbosity::Normal as u8);
+
+pub fn set_verbosity(verbosity: Verbosity) {
+    VERBOSITY.store(verbosity as u8, Ordering::SeqCst);
+}
+
+pub fn get_verbosity() -> Verbosity {
+    match VERBOSITY.load(Ordering::SeqCst) {
+        x if x == Verbosity::Quiet as u8 => Verbosity::Quiet,
+        x if x == Verbosity::Normal as u8 => Verbosity::Normal,
+        x if x == Verbosity::Verbose as u8 => Verbosity::Verbose,
+        _ => Verbosity::Debug,
+    }
+}
 
-pub fn set_verbosity(verbosity: bool) {
-    VERBOSE.store(verbosity, Ordering::SeqCst);
+/// Whether prompts should be answered "yes" without asking
+static ASSUME_YES: AtomicBool = AtomicBool::new(false);
+
+pub fn set_assume_yes(assume_yes: bool) {
+    ASSUME_YES.store(assume_yes, Ordering::SeqCst);
 }
 
-pub fn get_verbosity() -> bool {
-    VERBOSE.load(Ordering::SeqCst)
+pub fn get_assume_yes() -> bool {
+    ASSUME_YES.load(Ordering::SeqCst)
+}
+
+/// Print if the quiet flag hasn't been set.
+#[macro_export]
+macro_rules! info_print {
+     ($($args:tt)*) => {
+         if $crate::common::util::get_verbosity() >= $crate::common::util::Verbosity::Normal {
+             print!($($args)*);
+         }
+     }
+}
+
+/// Print (with a newline) if the quiet flag hasn't been set.
+#[macro_export]
+macro_rules! info_println {
+     ($($args:tt)*) => {
+         if $crate::common::util::get_verbosity() >= $crate::common::util::Verbosity::Normal {
+             println!($($args)*);
+         }
+     }
 }
 
 /// Print if the verbose flag has been set.
 #[macro_export]
 macro_rules! verbose_print {
      ($($args:tt)*) => {
-         if crate::common::util::get_verbosity() {
+         if $crate::common::util::get_verbosity() >= $crate::common::util::Verbosity::Verbose {
              print!($($args)*);
          }
      }
@@ -154,8 +398,140 @@ macro_rules! verbose_print {
 #[macro_export]
 macro_rules! verbose_println {
      ($($args:tt)*) => {
-         if crate::common::util::get_verbosity() {
+         if $crate::common::util::get_verbosity() >= $crate::common::util::Verbosity::Verbose {
+             println!($($args)*);
+         }
+     }
+}
+
+/// Print (with a newline) if the verbose flag has been given more than once.
+#[macro_export]
+macro_rules! debug_println {
+     ($($args:tt)*) => {
+         if $crate::common::util::get_verbosity() >= $crate::common::util::Verbosity::Debug {
              println!($($args)*);
          }
      }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::{expand_vars_with, normalize, parse_pattern_list, relative_path, ExpandError};
+    use pretty_assertions::assert_eq;
+    use std::path::{Path, PathBuf};
+
+    #[test]
+    #[cfg(unix)]
+    fn non_unicode_names() {
+        use super::{is_hidden, strip_os_prefix};
+        use std::{ffi::OsStr, os::unix::ffi::OsStrExt};
+
+        // "été" in Latin-1
+        let name = OsStr::from_bytes(b".\xe9t\xe9");
+        assert!(is_hidden(name));
+        assert!(!is_hidden(OsStr::from_bytes(b"\xe9t\xe9")));
+        assert_eq!(
+            strip_os_prefix(name, "."),
+            Some(OsStr::from_bytes(b"\xe9t\xe9"))
+        );
+        assert_eq!(strip_os_prefix(name, "!"), None);
+    }
+
+    #[test]
+    fn stable_hashes() {
+        use super::StableHasher;
+        use std::hash::Hasher;
+
+        let hash = |bytes: &[u8]| {
+            let mut hasher = StableHasher::default();
+            hasher.write(bytes);
+            hasher.finish()
+        };
+
+        // The published FNV-1a test vectors
+        assert_eq!(hash(b""), 0xcbf2_9ce4_8422_2325);
+        assert_eq!(hash(b"a"), 0xaf63_dc4c_8601_ec8c);
+        assert_eq!(hash(b"foobar"), 0x8594_4171_f739_67e8);
+    }
+
+    #[test]
+    fn normalize_removes_dots() {
+        assert_eq!(
+            normalize("/home/me/./.config/../.vimrc"),
+            PathBuf::from("/home/me/.vimrc")
+        );
+        assert_eq!(normalize("/../.vimrc"), PathBuf::from("/.vimrc"));
+    }
+
+    #[test]
+    fn pattern_list_skips_comments_and_blanks() {
+        let contents = "
+            # Editor junk
+            *.swp
+
+            secrets/*
+        ";
+
+        assert_eq!(
+            parse_pattern_list(contents),
+            vec![PathBuf::from("*.swp"), PathBuf::from("secrets/*")]
+        );
+    }
+
+    #[test]
+    fn relative_path_siblings() {
+        assert_eq!(
+            relative_path("/home/me", "/home/me/.dotfiles/vimrc"),
+            Some(PathBuf::from(".dotfiles/vimrc"))
+        );
+    }
+
+    #[test]
+    fn relative_path_nested() {
+        assert_eq!(
+            relative_path(
+                "/home/me/.config/nvim/lua",
+                "/home/me/.dotfiles/config/nvim/lua/init.lua"
+            ),
+            Some(Path::new("../../..").join(".dotfiles/config/nvim/lua/init.lua"))
+        );
+    }
+
+    #[test]
+    fn relative_path_no_common_ancestor() {
+        assert_eq!(relative_path("/home/me", "/opt/dotfiles/vimrc"), None);
+    }
+
+    fn lookup(name: &str) -> Option<String> {
+        match name {
+            "XDG_DATA_HOME" => Some(String::from("/home/me/.local/share")),
+            "USER" => Some(String::from("me")),
+            _ => None,
+        }
+    }
+
+    #[test]
+    fn expand_vars() {
+        assert_eq!(
+            expand_vars_with("$XDG_DATA_HOME/dotfiles", lookup).unwrap(),
+            "/home/me/.local/share/dotfiles"
+        );
+        assert_eq!(
+            expand_vars_with("/home/${USER}_files/$USER", lookup).unwrap(),
+            "/home/me_files/me"
+        );
+        assert_eq!(expand_vars_with("cost$ $5", lookup).unwrap(), "cost$ $5");
+    }
+
+    #[test]
+    fn expand_vars_undefined() {
+        match expand_vars_with("$NOPE/dotfiles", lookup) {
+            Err(ExpandError::UndefinedVariable { name, .. }) => assert_eq!(name, "NOPE"),
+            res => panic!("unexpected result {:?}", res),
+        }
+        match expand_vars_with("${USER", lookup) {
+            Err(ExpandError::Unterminated { .. }) => (),
+            res => panic!("unexpected result {:?}", res),
+        }
+    }
+}
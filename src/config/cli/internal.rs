@@ -1,4 +1,7 @@
-use crate::common::Platform;
+use super::Format;
+use crate::{common::Platform, linker::ConflictPolicy};
+use clap::Shell;
+use globset::Glob;
 use std::path::PathBuf;
 use structopt::StructOpt;
 
@@ -20,14 +23,229 @@ pub(super) struct RawConfig {
 pub(super) enum Command {
     /// Lists the active dotfiles
     Ls {
+        /// The output format. Valid values are human and json.
+        #[structopt(long, default_value = "human", parse(try_from_str))]
+        format: Format,
+
+        /// Prints full paths rather than abbreviating the home directory with
+        /// a tilde. Only applies to JSON output.
+        #[structopt(long)]
+        full_paths: bool,
+
+        /// Only lists dotfiles from within a prefixed folder with this name
+        /// (e.g. `tag-work` or `platform-macos`), at any depth. Can be given
+        /// more than once to list dotfiles from any of the folders.
+        #[structopt(long, number_of_values = 1)]
+        only_from: Vec<String>,
+
         #[structopt(flatten)]
         options: Options,
     },
 
-    /// Links all active dotfiles
+    /// Links all active dotfiles. Exits with status 3 if any conflicts are
+    /// left unresolved.
     Link {
-        /// Skips the actual linking step. Everything else (e.g. errors and
-        /// prompts) remains unchanged.
+        /// Reports what linking would change, without prompting or changing
+        /// anything
+        #[structopt(long)]
+        dry_run: bool,
+
+        /// Copies each dotfile to its destination instead of symlinking it.
+        #[structopt(long)]
+        copy: bool,
+
+        /// Backs up existing files to <file>.dotman-bak before overwriting
+        /// them.
+        #[structopt(long)]
+        backup: bool,
+
+        /// Overwrites conflicting files without asking, as with --on-conflict
+        /// overwrite. Directories will still not be overwritten.
+        #[structopt(short, long)]
+        force: bool,
+
+        /// What to do with conflicting files: prompt, skip, overwrite, or
+        /// backup (back up, then overwrite). The default is to prompt, unless
+        /// your dotrc sets a conflict-policy. Directories will still not be
+        /// overwritten.
+        #[structopt(long, parse(try_from_str), conflicts_with = "force")]
+        on_conflict: Option<ConflictPolicy>,
+
+        /// Creates symlinks using paths relative to the link's location rather
+        /// than absolute paths.
+        #[structopt(long)]
+        relative: bool,
+
+        /// Links non-conflicting items concurrently. Conflicts are still
+        /// resolved one at a time.
+        #[structopt(long)]
+        parallel: bool,
+
+        /// Shows a diff between the existing file and the dotfile before asking
+        /// whether to overwrite it.
+        #[structopt(long)]
+        diff: bool,
+
+        /// Undoes every change made so far if linking fails partway through.
+        #[structopt(long)]
+        atomic: bool,
+
+        /// Shows a progress bar while linking. It's only shown in a terminal,
+        /// and not with --quiet or --verbose.
+        #[structopt(long)]
+        progress: bool,
+
+        /// Runs `git pull` in each dotfiles folder before linking.
+        #[structopt(long)]
+        pull: bool,
+
+        /// Lists the active dotfiles and asks which of them to link.
+        #[structopt(long)]
+        interactive: bool,
+
+        /// Only links dotfiles matching this glob (relative to the dotfiles
+        /// folder, as for excludes), or within a folder matching it. Can be
+        /// given more than once to link dotfiles matching any of the globs.
+        #[structopt(long, number_of_values = 1, parse(try_from_str = Glob::new))]
+        only: Vec<Glob>,
+
+        /// Links the dotfiles listed on stdin instead of the active ones, as
+        /// either the JSON `ls --format json` prints or lines of a source and
+        /// a destination separated by a tab. Conflicts are skipped unless
+        /// --on-conflict (or your dotrc) says otherwise, since stdin can't
+        /// answer prompts.
+        #[structopt(long, conflicts_with_all = &["pull", "interactive", "only"])]
+        from_stdin: bool,
+
+        /// Afterwards, removes the links in the target folder which point into
+        /// the dotfiles folders, but not at any active dotfile (as when one was
+        /// deleted). Asks first, and only lists them with --dry-run.
+        #[structopt(long, conflicts_with = "from-stdin")]
+        prune: bool,
+
+        #[structopt(flatten)]
+        options: Options,
+    },
+
+    /// Pulls the latest changes to the dotfiles folders, then links all active
+    /// dotfiles. Exits with status 3 if any conflicts are left unresolved.
+    Sync {
+        /// Afterwards, commits any local changes to the dotfiles folders and
+        /// pushes them.
+        #[structopt(long)]
+        push: bool,
+
+        /// The message to commit local changes with.
+        #[structopt(short, long, default_value = "Update dotfiles")]
+        message: String,
+
+        #[structopt(flatten)]
+        options: Options,
+    },
+
+    /// Creates a dotfiles folder with a starter dotrc to fill in
+    Init {
+        /// Where to create the dotfiles folder. The default is ~/.dotfiles.
+        #[structopt(parse(from_os_str))]
+        path: Option<PathBuf>,
+
+        /// Uses the folder even if it already exists and isn't empty.
+        #[structopt(short, long)]
+        force: bool,
+
+        #[structopt(flatten)]
+        options: Options,
+    },
+
+    /// Moves an existing file (or directory) in the home directory into the
+    /// first dotfiles folder, then links it back
+    Add {
+        /// The file to add, such as ~/.vimrc.
+        #[structopt(parse(from_os_str))]
+        path: PathBuf,
+
+        /// Skips the actual moving and linking steps. Everything else (e.g.
+        /// errors) remains unchanged.
+        #[structopt(long)]
+        dry_run: bool,
+
+        #[structopt(flatten)]
+        options: Options,
+    },
+
+    /// Prints a completion script for the given shell
+    Completions {
+        /// The shell to generate completions for. Valid values are bash, zsh,
+        /// fish, powershell, and elvish.
+        #[structopt(possible_values = &Shell::variants(), case_insensitive = true)]
+        shell: Shell,
+    },
+
+    /// Shows the state of the links to all active dotfiles
+    Status {
+        #[structopt(flatten)]
+        options: Options,
+    },
+
+    /// Shows how the destination of each active dotfile differs from the
+    /// dotfile itself, e.g. after editing a copy in place. Exits with status 2
+    /// if there are any differences.
+    Diff {
+        #[structopt(flatten)]
+        options: Options,
+    },
+
+    /// Explains how the configuration was resolved and which folders are
+    /// searched for dotfiles
+    Doctor {
+        #[structopt(flatten)]
+        options: Options,
+    },
+
+    /// Explains why a dotfile is or isn't linked, given either the dotfile or
+    /// its destination (such as ~/.vimrc)
+    Explain {
+        /// The dotfile or destination to explain.
+        #[structopt(parse(from_os_str))]
+        path: PathBuf,
+
+        /// The output format. Valid values are human and json.
+        #[structopt(long, default_value = "human", parse(try_from_str))]
+        format: Format,
+
+        #[structopt(flatten)]
+        options: Options,
+    },
+
+    /// Lists the host-, tag-, platform-, and arch- folders in the dotfiles
+    /// folders, and which of them are active
+    Tags {
+        #[structopt(flatten)]
+        options: Options,
+    },
+
+    /// Checks that the dotfiles resolve cleanly, without linking anything.
+    /// Exits with code 2 if any problems are found, for use in CI.
+    Verify {
+        #[structopt(flatten)]
+        options: Options,
+    },
+
+    /// Removes broken symlinks in the home directory which point into the
+    /// dotfiles folders, e.g. after renaming a dotfile
+    Clean {
+        /// Only lists the broken links, without asking to remove them.
+        #[structopt(long)]
+        dry_run: bool,
+
+        #[structopt(flatten)]
+        options: Options,
+    },
+
+    /// Removes the links to all active dotfiles
+    Unlink {
+        /// Skips the actual unlinking step. Everything else (e.g. errors)
+        /// remains unchanged.
         #[structopt(long)]
         dry_run: bool,
 
@@ -36,33 +254,95 @@ pub(super) enum Command {
     },
 }
 
-#[derive(Debug, Clone, StructOpt)]
+#[derive(Debug, Clone, Default, StructOpt)]
 pub(super) struct Options {
-    /// Enables verbose output.
+    /// Enables verbose output. Give this twice (`-vv`) for even more output.
+    #[structopt(short, long, parse(from_occurrences))]
+    pub(super) verbose: u8,
+
+    /// Suppresses all output other than errors, results, and prompts.
+    #[structopt(short, long, conflicts_with = "verbose")]
+    pub(super) quiet: bool,
+
+    /// Answers "yes" to every prompt without asking. Directories still won't
+    /// be overwritten.
     #[structopt(short, long)]
-    pub(super) verbose: bool,
+    pub(super) yes: bool,
+
+    /// Prints the resolved configuration, and where each option came from,
+    /// instead of running the command.
+    #[structopt(long)]
+    pub(super) print_config: bool,
 
     /// Paths (relative to the dotfiles folder) of items to be excluded.
     /// This is in addition to any excludes defined in your dotrc.
     /// Globs are accepted - just make sure to enclose them in single quotes to
-    /// avoid your shell trying to expand them.
+    /// avoid your shell trying to expand them. Prefixing an exclude with `!`
+    /// re-includes anything it matches which earlier excludes matched.
     #[structopt(short, long = "exclude", number_of_values = 1, parse(from_os_str))]
     pub(super) excludes: Vec<PathBuf>,
 
-    /// Tags to enable. This is in addition to any tags enabled in your dotrc.
+    /// A file listing excludes, one per line, applied after those given with
+    /// --exclude. Blank lines and lines starting with `#` are ignored. May be
+    /// given multiple times.
+    #[structopt(long = "exclude-from", number_of_values = 1, parse(from_os_str))]
+    pub(super) exclude_files: Vec<PathBuf>,
+
+    /// Paths (relative to the dotfiles folder) of items to be included. If
+    /// any are given (here or in your dotrc), only included items are
+    /// linked. Globs are accepted, as with excludes.
+    #[structopt(short, long = "include", number_of_values = 1, parse(from_os_str))]
+    pub(super) includes: Vec<PathBuf>,
+
+    /// Tags to enable. This is in addition to any tags listed in $DOTMAN_TAGS
+    /// (separated by commas or spaces) or enabled in your dotrc.
     #[structopt(short, long = "tag", number_of_values = 1)]
     pub(super) tags: Vec<String>,
 
-    /// The folder in which to search for dotfiles. The default is ~/.dotfiles.
-    #[structopt(long, parse(from_os_str))]
-    pub(super) dotfiles_path: Option<PathBuf>,
+    /// A folder in which to search for dotfiles. May be given multiple times,
+    /// in which case the folders are searched in order. A git URL is cloned
+    /// into dotman's cache folder if it hasn't been already, and the clone is
+    /// used instead; `link` pulls it first. The default is ~/.dotfiles.
+    #[structopt(long = "dotfiles-path", number_of_values = 1, parse(from_os_str))]
+    pub(super) dotfiles_paths: Vec<PathBuf>,
 
-    /// The hostname to use. The default is the system hostname.
-    #[structopt(long)]
-    pub(super) hostname: Option<String>,
+    /// The hostname to use. The default is the system hostname. Can be given
+    /// more than once, in which case the `host-` folders for each are active.
+    #[structopt(long = "hostname", number_of_values = 1)]
+    pub(super) hostnames: Vec<String>,
 
     /// The platform to use. The default is the actual platform.
-    /// Valid values are macos, windows, linux, and wsl.
+    /// Valid values are macos, windows, linux, wsl, freebsd, and unix, along
+    /// with aliases such as osx and win32.
     #[structopt(long, parse(try_from_str))]
     pub(super) platform: Option<Platform>,
+
+    /// The CPU architecture to use (e.g. x86_64 or aarch64). The default is
+    /// the actual architecture.
+    #[structopt(long)]
+    pub(super) arch: Option<String>,
+
+    /// The folder to link dotfiles into, relative to the current folder. The
+    /// default is the home directory.
+    #[structopt(long, parse(from_os_str))]
+    pub(super) target: Option<PathBuf>,
+
+    /// Searches symlinked folders within the dotfiles folders for dotfiles
+    /// (and excludes) too. By default, they're skipped.
+    #[structopt(long)]
+    pub(super) follow_source_links: bool,
+
+    /// Ignores the dotrc, using only command-line options and defaults.
+    #[structopt(long)]
+    pub(super) no_dotrc: bool,
+
+    /// Resolves the dotfiles from scratch, even if the dotrc enables caching
+    /// them.
+    #[structopt(long)]
+    pub(super) no_cache: bool,
+
+    /// Prints errors to stderr as JSON (with their kind, message, and
+    /// causes) rather than as text.
+    #[structopt(long)]
+    pub(super) json_errors: bool,
 }
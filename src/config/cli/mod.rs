@@ -1,14 +1,33 @@
 mod internal;
 
-use crate::common::{util, Platform};
-use std::{ffi::OsString, iter, path::PathBuf};
+use crate::{
+    common::{
+        util::{self, Verbosity},
+        Platform,
+    },
+    linker,
+};
+use clap::Shell;
+use failure::Fail;
+use globset::Glob;
+use std::{ffi::OsString, io::Write, iter, path::PathBuf, str::FromStr};
 use structopt::StructOpt;
 
+/// The name of the `dotman` executable
+const BIN_NAME: &str = "dot";
+
 /// The portion of the configuration read from CLI arguments
 #[derive(Debug, Clone)]
 pub struct Config {
-    /// Enables verbose output.
-    pub verbose: bool,
+    /// How much output to print
+    pub verbosity: Verbosity,
+
+    /// Whether to answer "yes" to every prompt without asking
+    pub assume_yes: bool,
+
+    /// Whether to print the resolved configuration instead of running the
+    /// command
+    pub print_config: bool,
 
     /// Paths (relative to the dotfiles folder) of items to be excluded.
     /// This is in addition to any excludes defined in your dotrc.
@@ -16,19 +35,49 @@ pub struct Config {
     /// avoid your shell trying to expand them.
     pub excludes: Vec<PathBuf>,
 
-    /// Tags to enable. This is in addition to any tags enabled in your dotrc.
+    /// Files listing more excludes, one per line
+    pub exclude_files: Vec<PathBuf>,
+
+    /// Paths (relative to the dotfiles folder) of items to be included. If
+    /// any are given (here or in your dotrc), only included items are
+    /// linked. Globs are accepted, as with excludes.
+    pub includes: Vec<PathBuf>,
+
+    /// Tags to enable. This is in addition to any tags enabled in
+    /// `$DOTMAN_TAGS` or your dotrc.
     pub tags: Vec<String>,
 
-    /// The folder in which to search for dotfiles. The default is ~/.dotfiles.
-    pub dotfiles_path: Option<PathBuf>,
+    /// The folders in which to search for dotfiles, in order. The default is
+    /// ~/.dotfiles.
+    pub dotfiles_paths: Vec<PathBuf>,
 
-    /// The hostname to use. The default is the system hostname.
-    pub hostname: Option<String>,
+    /// The hostnames to use. If empty, the system hostname is used.
+    pub hostnames: Vec<String>,
 
     /// The platform to use. The default is the actual platform.
-    /// Valid values are macos, windows, linux, and wsl.
+    /// Valid values are macos, windows, linux, wsl, freebsd, and unix.
     pub platform: Option<Platform>,
 
+    /// The CPU architecture to use (e.g. x86_64 or aarch64). The default is
+    /// the actual architecture.
+    pub arch: Option<String>,
+
+    /// The folder to link dotfiles into. The default is the home directory.
+    pub target: Option<PathBuf>,
+
+    /// Whether to search symlinked folders within the dotfiles folders
+    pub follow_source_links: bool,
+
+    /// Whether to ignore the dotrc, using only CLI arguments and defaults
+    pub no_dotrc: bool,
+
+    /// Whether to resolve the dotfiles from scratch, even if the dotrc enables
+    /// caching them
+    pub no_cache: bool,
+
+    /// Whether to print errors as JSON (see `common::error`)
+    pub json_errors: bool,
+
     pub command: Command,
 }
 
@@ -38,13 +87,134 @@ impl Config {
         let raw_config = internal::RawConfig::from_clap(&app.get_matches());
 
         let (command, command_options) = match raw_config.command {
-            internal::Command::Ls { options } => (Ls, options),
-            internal::Command::Link { dry_run, options } => (Link { dry_run }, options),
+            // Completions don't take any options of their own
+            internal::Command::Completions { shell } => {
+                (Completions { shell }, internal::Options::default())
+            },
+            internal::Command::Init {
+                path,
+                force,
+                options,
+            } => {
+                let path =
+                    path.unwrap_or_else(|| util::home_dir().join(super::DEFAULT_DOTFILES_DIR));
+
+                (Init { path, force }, options)
+            },
+            internal::Command::Add {
+                path,
+                dry_run,
+                options,
+            } => (Add { path, dry_run }, options),
+            internal::Command::Ls {
+                format,
+                full_paths,
+                only_from,
+                options,
+            } => (
+                Ls {
+                    format,
+                    full_paths,
+                    only_from,
+                },
+                options,
+            ),
+            internal::Command::Link {
+                dry_run,
+                copy,
+                backup,
+                force,
+                on_conflict,
+                relative,
+                parallel,
+                diff,
+                atomic,
+                progress,
+                pull,
+                interactive,
+                only,
+                from_stdin,
+                prune,
+                options,
+            } => {
+                let strategy = if copy {
+                    linker::Strategy::Copy
+                } else {
+                    linker::Strategy::Symlink
+                };
+
+                (
+                    Link {
+                        options: linker::Options {
+                            dry_run,
+                            strategy,
+                            backup,
+                            on_conflict: if force {
+                                Some(linker::ConflictPolicy::Overwrite)
+                            } else {
+                                on_conflict
+                            },
+                            relative,
+                            parallel,
+                            diff,
+                            atomic,
+                            progress,
+                            answer: None,
+                        },
+                        pull,
+                        interactive,
+                        only,
+                        from_stdin,
+                        prune,
+                    },
+                    options,
+                )
+            },
+            internal::Command::Sync {
+                push,
+                message,
+                options,
+            } => (Sync { push, message }, options),
+            internal::Command::Status { options } => (Status, options),
+            internal::Command::Diff { options } => (Diff, options),
+            internal::Command::Doctor { options } => (Doctor, options),
+            internal::Command::Explain {
+                path,
+                format,
+                options,
+            } => (Explain { path, format }, options),
+            internal::Command::Tags { options } => (Tags, options),
+            internal::Command::Verify { options } => (Verify, options),
+            internal::Command::Clean { dry_run, options } => (Clean { dry_run }, options),
+            internal::Command::Unlink { dry_run, options } => (Unlink { dry_run }, options),
         };
 
-        let verbose = raw_config.options.verbose || command_options.verbose;
+        let quiet = raw_config.options.quiet || command_options.quiet;
+        let verbosity = match raw_config.options.verbose + command_options.verbose {
+            0 if quiet => Verbosity::Quiet,
+            0 => Verbosity::Normal,
+            1 => Verbosity::Verbose,
+            _ => Verbosity::Debug,
+        };
+        let assume_yes = raw_config.options.yes || command_options.yes;
+        let print_config = raw_config.options.print_config || command_options.print_config;
+        let follow_source_links =
+            raw_config.options.follow_source_links || command_options.follow_source_links;
+        let no_dotrc = raw_config.options.no_dotrc || command_options.no_dotrc;
+        let no_cache = raw_config.options.no_cache || command_options.no_cache;
+        let json_errors = raw_config.options.json_errors || command_options.json_errors;
         let excludes = util::append_vecs(raw_config.options.excludes, command_options.excludes);
+        let exclude_files = util::append_vecs(
+            raw_config.options.exclude_files,
+            command_options.exclude_files,
+        );
+        let includes = util::append_vecs(raw_config.options.includes, command_options.includes);
         let tags = util::append_vecs(raw_config.options.tags, command_options.tags);
+        let dotfiles_paths = util::append_vecs(
+            raw_config.options.dotfiles_paths,
+            command_options.dotfiles_paths,
+        );
+        let hostnames = util::append_vecs(raw_config.options.hostnames, command_options.hostnames);
 
         /// Given the name of an argument which should be unique, tries to get
         /// it from either the main command or a subcommand. If it is
@@ -77,36 +247,190 @@ impl Config {
             };
         }
 
-        let dotfiles_path = get_unique_arg!(dotfiles_path);
-        let hostname = get_unique_arg!(hostname);
         let platform = get_unique_arg!(platform);
+        let arch = get_unique_arg!(arch);
+        let target = get_unique_arg!(target);
 
         let res = Config {
-            verbose,
+            verbosity,
+            assume_yes,
+            print_config,
             excludes,
+            exclude_files,
+            includes,
             tags,
-            dotfiles_path,
-            hostname,
+            dotfiles_paths,
+            hostnames,
             platform,
+            arch,
+            target,
+            follow_source_links,
+            no_dotrc,
+            no_cache,
+            json_errors,
             command,
         };
 
-        util::set_verbosity(res.verbose);
+        util::set_verbosity(res.verbosity);
+        util::set_assume_yes(res.assume_yes);
 
         res
     }
+
+    /// Writes a completion script for `shell` to `out`
+    pub fn write_completions(shell: Shell, out: &mut impl Write) {
+        internal::RawConfig::clap().gen_completions_to(BIN_NAME, shell, out);
+    }
 }
 
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone)]
 pub enum Command {
     /// Lists the active dotfiles
-    Ls,
+    Ls {
+        /// The output format
+        format: Format,
+
+        /// Prints full paths rather than abbreviating the home directory with
+        /// a tilde. Only applies to JSON output.
+        full_paths: bool,
+
+        /// Names of prefixed folders to list dotfiles from. If empty, all
+        /// dotfiles are listed.
+        only_from: Vec<String>,
+    },
 
     /// Links all active dotfiles
     Link {
-        /// Skips the actual linking step. Everything else (e.g. errors and
-        /// prompts) remains unchanged.
+        options: linker::Options,
+
+        /// Pulls the latest changes to the dotfiles folders before linking
+        pull: bool,
+
+        /// Asks which of the active dotfiles to link first
+        interactive: bool,
+
+        /// If not empty, only dotfiles matching one of these (see
+        /// `resolver::source_matches`) are linked
+        only: Vec<Glob>,
+
+        /// Whether to link the dotfiles listed on stdin (see
+        /// `common::parse_items`) instead of the active ones
+        from_stdin: bool,
+
+        /// Afterwards, removes stale links to the dotfiles folders (see
+        /// `linker::prune`)
+        prune: bool,
+    },
+
+    /// Pulls the latest changes to the dotfiles folders, then links all active
+    /// dotfiles
+    Sync {
+        /// Afterwards, commits any local changes and pushes them
+        push: bool,
+
+        /// The message to commit local changes with
+        message: String,
+    },
+
+    /// Creates a dotfiles folder with a starter dotrc
+    Init {
+        path: PathBuf,
+
+        /// Uses the folder even if it already exists and isn't empty
+        force: bool,
+    },
+
+    /// Moves an existing file into the first dotfiles folder and links it back
+    Add {
+        path: PathBuf,
+
+        /// Skips the actual moving and linking steps. Everything else (e.g.
+        /// errors) remains unchanged.
+        dry_run: bool,
+    },
+
+    /// Prints a completion script for the given shell
+    Completions { shell: Shell },
+
+    /// Shows the state of the links to all active dotfiles
+    Status,
+
+    /// Shows how the destination of each active dotfile differs from the
+    /// dotfile itself
+    Diff,
+
+    /// Explains how the configuration was resolved and which folders are
+    /// searched for dotfiles
+    Doctor,
+
+    /// Explains why a dotfile (or the dotfiles for a destination) is or isn't
+    /// linked
+    Explain {
+        path: PathBuf,
+
+        /// The output format
+        format: Format,
+    },
+
+    /// Lists the prefixed folders in the dotfiles folders, and which are active
+    Tags,
+
+    /// Checks that the dotfiles resolve cleanly, without linking anything
+    Verify,
+
+    /// Removes broken symlinks in the home directory which point into the
+    /// dotfiles folders
+    Clean {
+        /// Only lists the broken links, without prompting or removing them
+        dry_run: bool,
+    },
+
+    /// Removes the links to all active dotfiles
+    Unlink {
+        /// Skips the actual unlinking step. Everything else (e.g. errors)
+        /// remains unchanged.
         dry_run: bool,
     },
 }
 use Command::*;
+
+impl Command {
+    /// Checks whether this command creates or removes links, and so needs to
+    /// hold the lock (see `lock::Lock`)
+    pub fn modifies_links(&self) -> bool {
+        matches!(
+            self,
+            Link { .. } | Sync { .. } | Add { .. } | Clean { .. } | Unlink { .. }
+        )
+    }
+}
+
+/// Output formats for listing (or explaining) dotfiles
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    /// Aligned, human-readable text
+    Human,
+
+    /// JSON, such as an array of source/destination pairs
+    Json,
+}
+
+#[derive(Debug, Fail)]
+#[fail(display = "unsupported format \"{}\"", input)]
+pub struct FormatParseError {
+    input: String,
+}
+
+impl FromStr for Format {
+    type Err = FormatParseError;
+
+    fn from_str(s: &str) -> Result<Format, Self::Err> {
+        match s.trim().to_lowercase().as_str() {
+            "human" => Ok(Format::Human),
+            "json" => Ok(Format::Json),
+            s => Err(FormatParseError {
+                input: s.to_owned(),
+            }),
+        }
+    }
+}
@@ -1,27 +1,286 @@
 use derive_more::From;
 use failure::Fail;
-use serde::Deserialize;
+use serde::{
+    de::{MapAccess, Visitor},
+    Deserialize, Deserializer,
+};
 use std::{
-    fs,
+    ffi::OsStr,
+    fmt, fs,
     io::{self, Read},
+    marker::PhantomData,
     path::Path,
 };
 
+/// A dotrc with every option commented out, for new dotfiles folders
+pub const STARTER: &str = r#"# dotman configuration. Uncomment and edit any options you need.
+
+# The folders to search for dotfiles, in order
+# dotfiles-path: ~/.dotfiles
+
+# Paths (relative to the dotfiles folder) to leave out. Globs are accepted, and
+# an exclude starting with `!` re-includes what earlier excludes matched.
+# excludes:
+#   - README.md
+#   - "!secrets/public.asc"
+
+# Destinations (relative to the target folder) to never link anything to,
+# including anything within them
+# dest-excludes:
+#   - .ssh
+
+# Excludes which only apply on the given platforms, or with the given tags
+# platform-excludes:
+#   macos:
+#     - linux-only
+# tag-excludes:
+#   work:
+#     - games
+
+# Whether to also exclude whatever the dotfiles folder's .gitignore files ignore
+# gitignore: true
+
+# Whether to search symlinked folders within the dotfiles folder, rather than
+# skipping them
+# follow-source-links: true
+
+# If given, only dotfiles within these paths are linked
+# includes:
+#   - vim
+
+# Tags enabling the matching `tag-` folders
+# tags:
+#   - work
+
+# Overrides for the detected hostname (or hostnames), platform, and
+# CPU architecture
+# hostname: my-laptop
+# platform: linux
+# arch: x86_64
+
+# The folder dotfiles are linked into (an absolute path, or one starting with ~)
+# target: ~/sandbox
+
+# Custom destinations for matching dotfiles
+# links:
+#   "bin/*": ~/.local/bin/
+
+# Folders (other than the target folder) to link the contents of prefixed
+# folders into. Dotfiles aren't given a leading dot there unless `hidden` is set.
+# prefix-roots:
+#   tag-bin: ~/bin
+#   tag-work:
+#     root: ~/work
+#     hidden: true
+
+# Prefixes for prefixed folders other than the defaults of host-, tag-,
+# platform-, and arch-
+# prefixes:
+#   tag: tag_
+#   host: "@"
+
+# Dotfiles which are only linked if a shell command succeeds
+# requires:
+#   "config/nvim/**": command -v nvim
+
+# Permissions (in octal) to give dotfiles after linking them, on Unix. The
+# first glob a dotfile matches wins.
+# modes:
+#   "ssh/config": "600"
+#   "bin/*": "755"
+
+# Top-level files and folders to link without a leading dot
+# no-dot:
+#   - bin
+
+# Dotfiles (matched as for `links`) to link before or after everything else,
+# e.g. for hooks which need them
+# link-first:
+#   - profile
+# link-last:
+#   - "config/autostart/*"
+
+# Shell commands to run (from the dotfiles folder) before and after linking
+# pre-link:
+#   - mkdir -p ~/.cache
+# post-link:
+#   - fc-cache
+
+# Whether `link` runs `git pull` in the dotfiles folders first
+# auto-pull: true
+
+# What to do with existing files in the way of dotfiles: prompt, skip,
+# overwrite, or backup (back up, then overwrite)
+# conflict-policy: backup
+
+# Whether to reuse the dotfiles found by the last run until the dotfiles folders
+# or configuration change, which speeds up large dotfiles folders. Pass
+# --no-cache to search from scratch anyway.
+# cache: true
+"#;
+
 /// Configuration options available in dotrc
 #[derive(Debug, Default, Deserialize, PartialEq, Eq)]
 #[serde(deny_unknown_fields)]
 pub(super) struct Config {
     pub(super) excludes: Option<Vec<String>>,
+    /// Globs for destinations which nothing is linked to
+    #[serde(rename = "dest-excludes")]
+    pub(super) dest_excludes: Option<Vec<String>>,
+    /// Platforms mapped to excludes which only apply on them, in order
+    #[serde(rename = "platform-excludes", default, deserialize_with = "entries")]
+    pub(super) platform_excludes: Option<Vec<(String, Vec<String>)>>,
+    /// Tags mapped to excludes which only apply when they're active, in order
+    #[serde(rename = "tag-excludes", default, deserialize_with = "entries")]
+    pub(super) tag_excludes: Option<Vec<(String, Vec<String>)>>,
+    pub(super) includes: Option<Vec<String>>,
     pub(super) tags: Option<Vec<String>>,
-    #[serde(rename = "dotfiles-path")]
-    pub(super) dotfiles_path: Option<String>,
-    pub(super) hostname: Option<String>,
+    #[serde(rename = "dotfiles-path", default, deserialize_with = "one_or_many")]
+    pub(super) dotfiles_path: Option<Vec<String>>,
+    #[serde(default, deserialize_with = "one_or_many")]
+    pub(super) hostname: Option<Vec<String>>,
+    pub(super) platform: Option<String>,
+    pub(super) arch: Option<String>,
+    pub(super) target: Option<String>,
+    #[serde(rename = "pre-link")]
+    pub(super) pre_link: Option<Vec<String>>,
+    #[serde(rename = "post-link")]
+    pub(super) post_link: Option<Vec<String>>,
+    /// Whether to pull the dotfiles folders before linking
+    #[serde(rename = "auto-pull")]
+    pub(super) auto_pull: Option<bool>,
+    /// How to handle files in the way of dotfiles when linking
+    #[serde(rename = "conflict-policy")]
+    pub(super) conflict_policy: Option<String>,
+    /// Whether to exclude what the dotfiles folders' `.gitignore` files ignore
+    pub(super) gitignore: Option<bool>,
+    /// Whether to search symlinked folders within the dotfiles folders
+    #[serde(rename = "follow-source-links")]
+    pub(super) follow_source_links: Option<bool>,
+    /// Whether to cache the resolved dotfiles between runs
+    pub(super) cache: Option<bool>,
+    /// Globs for top-level dotfiles linked without a leading dot
+    #[serde(rename = "no-dot")]
+    pub(super) no_dot: Option<Vec<String>>,
+    /// Globs for dotfiles linked before everything else, in order
+    #[serde(rename = "link-first")]
+    pub(super) link_first: Option<Vec<String>>,
+    /// Globs for dotfiles linked after everything else, in order
+    #[serde(rename = "link-last")]
+    pub(super) link_last: Option<Vec<String>>,
+    /// Source globs mapped to custom destinations, in order
+    #[serde(default, deserialize_with = "entries")]
+    pub(super) links: Option<Vec<(String, String)>>,
+    /// Prefixed folder names mapped to where their contents are linked, in
+    /// order
+    #[serde(rename = "prefix-roots", default, deserialize_with = "entries")]
+    pub(super) prefix_roots: Option<Vec<(String, PrefixRoot)>>,
+    /// Overrides for each kind of prefixed folder's prefix
+    pub(super) prefixes: Option<Prefixes>,
+    /// Source globs mapped to shell commands which must succeed for them to
+    /// be linked
+    #[serde(default, deserialize_with = "entries")]
+    pub(super) requires: Option<Vec<(String, String)>>,
+    /// Source globs mapped to the (octal) modes they're given, in order
+    #[serde(default, deserialize_with = "entries")]
+    pub(super) modes: Option<Vec<(String, String)>>,
+}
+
+/// Where the contents of a prefixed folder are linked, given as either just the
+/// folder or the folder along with whether they're hidden there
+#[derive(Debug, Deserialize, PartialEq, Eq)]
+#[serde(untagged)]
+pub(super) enum PrefixRoot {
+    Root(String),
+    Full {
+        root: String,
+        #[serde(default)]
+        hidden: bool,
+    },
+}
+
+impl PrefixRoot {
+    pub(super) fn root(&self) -> &str {
+        match self {
+            PrefixRoot::Root(root) | PrefixRoot::Full { root, .. } => root,
+        }
+    }
+
+    pub(super) fn hidden(&self) -> bool {
+        match self {
+            PrefixRoot::Root(_) => false,
+            PrefixRoot::Full { hidden, .. } => *hidden,
+        }
+    }
+}
+
+/// The prefixes of the kinds of prefixed folders, where they differ from the
+/// defaults
+#[derive(Debug, Default, Deserialize, PartialEq, Eq)]
+#[serde(deny_unknown_fields)]
+pub(super) struct Prefixes {
+    pub(super) host: Option<String>,
+    pub(super) tag: Option<String>,
     pub(super) platform: Option<String>,
+    pub(super) arch: Option<String>,
+}
+
+/// Deserializes a map as a list of its entries, preserving their order
+fn entries<'de, D, V>(deserializer: D) -> Result<Option<Vec<(String, V)>>, D::Error>
+where
+    D: Deserializer<'de>,
+    V: Deserialize<'de>,
+{
+    struct EntriesVisitor<V>(PhantomData<V>);
+
+    impl<'de, V: Deserialize<'de>> Visitor<'de> for EntriesVisitor<V> {
+        type Value = Vec<(String, V)>;
+
+        fn expecting(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            f.write_str("a map")
+        }
+
+        fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<Self::Value, A::Error> {
+            let mut res = vec![];
+            while let Some(entry) = map.next_entry()? {
+                res.push(entry);
+            }
+
+            Ok(res)
+        }
+    }
+
+    deserializer
+        .deserialize_map(EntriesVisitor(PhantomData))
+        .map(Some)
+}
+
+/// Deserializes either a single value or a list of values as a list
+fn one_or_many<'de, D, T>(deserializer: D) -> Result<Option<Vec<T>>, D::Error>
+where
+    D: Deserializer<'de>,
+    T: Deserialize<'de>,
+{
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum OneOrMany<T> {
+        One(T),
+        Many(Vec<T>),
+    }
+
+    let value = Option::<OneOrMany<T>>::deserialize(deserializer)?;
+
+    Ok(value.map(|value| match value {
+        OneOrMany::One(x) => vec![x],
+        OneOrMany::Many(xs) => xs,
+    }))
 }
 
 impl Config {
     /// Gets configuration options from the dotrc file.
     ///
+    /// A dotrc ending in `.toml` is parsed as TOML, and anything else as YAML.
+    ///
     /// The dotrc file not existing is _not_ considered an error,
     /// and will return an empty config. Failure to read the dotrc
     /// file or a malformed dotrc, on the other hand, _is_ considered
@@ -32,6 +291,7 @@ impl Config {
             None => return Ok(Config::default()),
         };
 
+        let is_toml = path.as_ref().extension() == Some(OsStr::new("toml"));
         let file = match fs::File::open(path) {
             Ok(file) => file,
             Err(_) => return Ok(Config::default()),
@@ -45,12 +305,18 @@ impl Config {
             contents
         };
 
-        // serde_yaml errors on empty input, so handle that case manually
-        if contents.is_empty() {
+        // serde_yaml errors on empty input (including input which is only
+        // comments), so handle that case manually
+        let is_blank = |line: &str| line.trim().is_empty() || line.trim().starts_with('#');
+        if contents.lines().all(is_blank) {
             return Ok(Config::default());
         }
 
-        let config = serde_yaml::from_str(&contents)?;
+        let config = if is_toml {
+            toml::from_str(&contents)?
+        } else {
+            serde_yaml::from_str(&contents)?
+        };
 
         Ok(config)
     }
@@ -59,18 +325,21 @@ impl Config {
 #[derive(Debug, From, Fail)]
 pub enum Error {
     #[fail(display = "error parsing .dotrc ({})", _0)]
-    ParseError(#[fail(cause)] serde_yaml::Error),
+    InvalidYaml(#[fail(cause)] serde_yaml::Error),
+
+    #[fail(display = "error parsing .dotrc ({})", _0)]
+    InvalidToml(#[fail(cause)] toml::de::Error),
 
     #[fail(display = "error reading .dotrc ({})", _0)]
-    IoError(#[fail(cause)] io::Error),
+    Unreadable(#[fail(cause)] io::Error),
 }
 
 #[cfg(test)]
 mod tests {
-    use super::Config;
+    use super::{Config, PrefixRoot, Prefixes};
     use pretty_assertions::assert_eq;
     use std::io::Write;
-    use tempfile::NamedTempFile;
+    use tempfile::{Builder, NamedTempFile};
 
     fn mock_dotrc(contents: &str) -> Config {
         let mut dotrc = NamedTempFile::new().unwrap();
@@ -79,6 +348,13 @@ mod tests {
         super::Config::get(Some(dotrc.path())).unwrap()
     }
 
+    fn mock_toml_dotrc(contents: &str) -> Config {
+        let mut dotrc = Builder::new().suffix(".toml").tempfile().unwrap();
+        write!(dotrc, "{}", contents).unwrap();
+
+        super::Config::get(Some(dotrc.path())).unwrap()
+    }
+
     #[test]
     fn empty_dotrc() {
         let config = mock_dotrc("");
@@ -87,6 +363,37 @@ mod tests {
         assert_eq!(config, expected);
     }
 
+    #[test]
+    fn comments_only_dotrc() {
+        let config = mock_dotrc("# excludes:\n#   - python\n");
+        let expected = Config::default();
+
+        assert_eq!(config, expected);
+    }
+
+    #[test]
+    fn starter_dotrc() {
+        assert_eq!(mock_dotrc(super::STARTER), Config::default());
+
+        // Every commented-out option should be valid once uncommented
+        let is_option = |text: &str| {
+            let key = text.split(':').next().unwrap_or_default();
+            text.starts_with(' ')
+                || (text.contains(':') && key.chars().all(|c| c.is_ascii_lowercase() || c == '-'))
+        };
+        let uncommented: String = super::STARTER
+            .lines()
+            .filter_map(|line| line.strip_prefix("# "))
+            .filter(|text| is_option(text))
+            .map(|option| format!("{}\n", option))
+            .collect();
+        let config = mock_dotrc(&uncommented);
+
+        assert_eq!(config.tags, Some(vec![String::from("work")]));
+        assert_eq!(config.auto_pull, Some(true));
+        assert!(config.platform_excludes.is_some());
+    }
+
     #[test]
     fn excludes() {
         let contents = r#"
@@ -104,6 +411,76 @@ mod tests {
         assert_eq!(config, expected);
     }
 
+    #[test]
+    fn grouped_excludes() {
+        let contents = r#"
+            platform-excludes:
+                macos:
+                    - linux-only
+                linux:
+                    - Library
+            tag-excludes:
+                work:
+                    - games
+                    - "!games/chess"
+        "#;
+        let config = mock_dotrc(contents);
+
+        let expected = Config {
+            platform_excludes: Some(vec![
+                (String::from("macos"), vec![String::from("linux-only")]),
+                (String::from("linux"), vec![String::from("Library")]),
+            ]),
+            tag_excludes: Some(vec![(
+                String::from("work"),
+                vec![String::from("games"), String::from("!games/chess")],
+            )]),
+            ..Config::default()
+        };
+
+        assert_eq!(config, expected);
+    }
+
+    #[test]
+    fn toml_grouped_excludes() {
+        let contents = r#"
+            [platform-excludes]
+            macos = ["linux-only"]
+
+            [tag-excludes]
+            work = ["games"]
+        "#;
+        let config = mock_toml_dotrc(contents);
+
+        let expected = Config {
+            platform_excludes: Some(vec![(
+                String::from("macos"),
+                vec![String::from("linux-only")],
+            )]),
+            tag_excludes: Some(vec![(String::from("work"), vec![String::from("games")])]),
+            ..Config::default()
+        };
+
+        assert_eq!(config, expected);
+    }
+
+    #[test]
+    fn includes() {
+        let contents = r#"
+            includes:
+                - vim
+                - zsh/*
+        "#;
+        let config = mock_dotrc(contents);
+
+        let expected = Config {
+            includes: Some(vec![String::from("vim"), String::from("zsh/*")]),
+            ..Config::default()
+        };
+
+        assert_eq!(config, expected);
+    }
+
     #[test]
     fn tags() {
         let contents = r#"
@@ -134,7 +511,27 @@ mod tests {
         let config = mock_dotrc(contents);
 
         let expected = Config {
-            dotfiles_path: Some(String::from("~/.top_secret")),
+            dotfiles_path: Some(vec![String::from("~/.top_secret")]),
+            ..Config::default()
+        };
+
+        assert_eq!(config, expected);
+    }
+
+    #[test]
+    fn multiple_dotfiles_paths() {
+        let contents = r#"
+            dotfiles-path:
+                - ~/.dotfiles
+                - ~/.work-dotfiles
+        "#;
+        let config = mock_dotrc(contents);
+
+        let expected = Config {
+            dotfiles_path: Some(vec![
+                String::from("~/.dotfiles"),
+                String::from("~/.work-dotfiles"),
+            ]),
             ..Config::default()
         };
 
@@ -149,7 +546,24 @@ mod tests {
         let config = mock_dotrc(contents);
 
         let expected = Config {
-            hostname: Some(String::from("my-amazing-computer")),
+            hostname: Some(vec![String::from("my-amazing-computer")]),
+            ..Config::default()
+        };
+
+        assert_eq!(config, expected);
+    }
+
+    #[test]
+    fn multiple_hostnames() {
+        let contents = r#"
+            hostname:
+                - laptop
+                - laptop.local
+        "#;
+        let config = mock_dotrc(contents);
+
+        let expected = Config {
+            hostname: Some(vec![String::from("laptop"), String::from("laptop.local")]),
             ..Config::default()
         };
 
@@ -170,4 +584,349 @@ mod tests {
 
         assert_eq!(config, expected);
     }
+
+    #[test]
+    fn arch() {
+        let contents = r#"
+            arch: aarch64
+        "#;
+        let config = mock_dotrc(contents);
+
+        let expected = Config {
+            arch: Some(String::from("aarch64")),
+            ..Config::default()
+        };
+
+        assert_eq!(config, expected);
+    }
+
+    #[test]
+    fn target() {
+        let contents = r#"
+            target: /mnt/container/root
+        "#;
+        let config = mock_dotrc(contents);
+
+        let expected = Config {
+            target: Some(String::from("/mnt/container/root")),
+            ..Config::default()
+        };
+
+        assert_eq!(config, expected);
+    }
+
+    #[test]
+    fn hooks() {
+        let contents = r#"
+            pre-link:
+                - mkdir -p ~/.cache/fonts
+            post-link:
+                - fc-cache
+                - systemctl --user daemon-reload
+        "#;
+        let config = mock_dotrc(contents);
+
+        let expected = Config {
+            pre_link: Some(vec![String::from("mkdir -p ~/.cache/fonts")]),
+            post_link: Some(vec![
+                String::from("fc-cache"),
+                String::from("systemctl --user daemon-reload"),
+            ]),
+            ..Config::default()
+        };
+
+        assert_eq!(config, expected);
+    }
+
+    #[test]
+    fn auto_pull() {
+        let contents = r#"
+            auto-pull: true
+        "#;
+        let config = mock_dotrc(contents);
+
+        let expected = Config {
+            auto_pull: Some(true),
+            ..Config::default()
+        };
+
+        assert_eq!(config, expected);
+    }
+
+    #[test]
+    fn conflict_policy() {
+        let contents = r#"
+            conflict-policy: backup
+        "#;
+        let config = mock_dotrc(contents);
+
+        let expected = Config {
+            conflict_policy: Some(String::from("backup")),
+            ..Config::default()
+        };
+
+        assert_eq!(config, expected);
+    }
+
+    #[test]
+    fn gitignore() {
+        let config = mock_dotrc("gitignore: true");
+
+        let expected = Config {
+            gitignore: Some(true),
+            ..Config::default()
+        };
+
+        assert_eq!(config, expected);
+    }
+
+    #[test]
+    fn no_dot() {
+        let contents = r#"
+            no-dot:
+                - bin
+                - "*.desktop"
+        "#;
+        let config = mock_dotrc(contents);
+
+        let expected = Config {
+            no_dot: Some(vec![String::from("bin"), String::from("*.desktop")]),
+            ..Config::default()
+        };
+
+        assert_eq!(config, expected);
+    }
+
+    #[test]
+    fn links() {
+        let contents = r#"
+            links:
+                foo/bar.conf: ~/.config/foo/bar.conf
+                "bin/*": ~/.local/bin/
+        "#;
+        let config = mock_dotrc(contents);
+
+        let expected = Config {
+            links: Some(vec![
+                (
+                    String::from("foo/bar.conf"),
+                    String::from("~/.config/foo/bar.conf"),
+                ),
+                (String::from("bin/*"), String::from("~/.local/bin/")),
+            ]),
+            ..Config::default()
+        };
+
+        assert_eq!(config, expected);
+    }
+
+    #[test]
+    fn prefix_roots() {
+        let contents = r#"
+            prefix-roots:
+                tag-bin: ~/bin
+                tag-work:
+                    root: ~/work
+                    hidden: true
+        "#;
+        let config = mock_dotrc(contents);
+
+        let expected = Config {
+            prefix_roots: Some(vec![
+                (
+                    String::from("tag-bin"),
+                    PrefixRoot::Root(String::from("~/bin")),
+                ),
+                (
+                    String::from("tag-work"),
+                    PrefixRoot::Full {
+                        root: String::from("~/work"),
+                        hidden: true,
+                    },
+                ),
+            ]),
+            ..Config::default()
+        };
+
+        assert_eq!(config, expected);
+    }
+
+    #[test]
+    fn prefixes() {
+        let contents = r#"
+            prefixes:
+                tag: tag_
+                host: "@"
+        "#;
+        let config = mock_dotrc(contents);
+
+        let expected = Config {
+            prefixes: Some(Prefixes {
+                tag: Some(String::from("tag_")),
+                host: Some(String::from("@")),
+                ..Prefixes::default()
+            }),
+            ..Config::default()
+        };
+
+        assert_eq!(config, expected);
+    }
+
+    #[test]
+    fn requires() {
+        let contents = r#"
+            requires:
+                "config/nvim/**": command -v nvim
+        "#;
+        let config = mock_dotrc(contents);
+
+        let expected = Config {
+            requires: Some(vec![(
+                String::from("config/nvim/**"),
+                String::from("command -v nvim"),
+            )]),
+            ..Config::default()
+        };
+
+        assert_eq!(config, expected);
+    }
+
+    #[test]
+    fn modes() {
+        let contents = r#"
+            modes:
+                "ssh/config": "600"
+                "bin/*": "0755"
+        "#;
+        let config = mock_dotrc(contents);
+
+        let expected = Config {
+            modes: Some(vec![
+                (String::from("ssh/config"), String::from("600")),
+                (String::from("bin/*"), String::from("0755")),
+            ]),
+            ..Config::default()
+        };
+
+        assert_eq!(config, expected);
+    }
+
+    #[test]
+    fn empty_toml_dotrc() {
+        let config = mock_toml_dotrc("");
+        let expected = Config::default();
+
+        assert_eq!(config, expected);
+    }
+
+    #[test]
+    fn toml_lists() {
+        let contents = r#"
+            excludes = ["python", "secrets"]
+            includes = ["vim", "zsh/*"]
+            tags = ["haskell", "rust"]
+        "#;
+        let config = mock_toml_dotrc(contents);
+
+        let expected = Config {
+            excludes: Some(vec![String::from("python"), String::from("secrets")]),
+            includes: Some(vec![String::from("vim"), String::from("zsh/*")]),
+            tags: Some(vec![String::from("haskell"), String::from("rust")]),
+            ..Config::default()
+        };
+
+        assert_eq!(config, expected);
+    }
+
+    #[test]
+    fn toml_dotfiles_path() {
+        let config = mock_toml_dotrc(r#"dotfiles-path = "~/.top_secret""#);
+        let expected = Config {
+            dotfiles_path: Some(vec![String::from("~/.top_secret")]),
+            ..Config::default()
+        };
+        assert_eq!(config, expected);
+
+        let config = mock_toml_dotrc(r#"dotfiles-path = ["~/.dotfiles", "~/.work-dotfiles"]"#);
+        let expected = Config {
+            dotfiles_path: Some(vec![
+                String::from("~/.dotfiles"),
+                String::from("~/.work-dotfiles"),
+            ]),
+            ..Config::default()
+        };
+        assert_eq!(config, expected);
+    }
+
+    #[test]
+    fn toml_scalars() {
+        let contents = r#"
+            hostname = "my-amazing-computer"
+            platform = "macos"
+            arch = "aarch64"
+            target = "/mnt/container/root"
+        "#;
+        let config = mock_toml_dotrc(contents);
+
+        let expected = Config {
+            hostname: Some(vec![String::from("my-amazing-computer")]),
+            platform: Some(String::from("macos")),
+            arch: Some(String::from("aarch64")),
+            target: Some(String::from("/mnt/container/root")),
+            ..Config::default()
+        };
+
+        assert_eq!(config, expected);
+    }
+
+    #[test]
+    fn toml_hooks() {
+        let contents = r#"
+            pre-link = ["mkdir -p ~/.cache/fonts"]
+            post-link = ["fc-cache", "systemctl --user daemon-reload"]
+        "#;
+        let config = mock_toml_dotrc(contents);
+
+        let expected = Config {
+            pre_link: Some(vec![String::from("mkdir -p ~/.cache/fonts")]),
+            post_link: Some(vec![
+                String::from("fc-cache"),
+                String::from("systemctl --user daemon-reload"),
+            ]),
+            ..Config::default()
+        };
+
+        assert_eq!(config, expected);
+    }
+
+    #[test]
+    fn toml_links() {
+        let contents = r#"
+            [links]
+            "foo/bar.conf" = "~/.config/foo/bar.conf"
+            "bin/*" = "~/.local/bin/"
+        "#;
+        let config = mock_toml_dotrc(contents);
+
+        let expected = Config {
+            links: Some(vec![
+                (
+                    String::from("foo/bar.conf"),
+                    String::from("~/.config/foo/bar.conf"),
+                ),
+                (String::from("bin/*"), String::from("~/.local/bin/")),
+            ]),
+            ..Config::default()
+        };
+
+        assert_eq!(config, expected);
+    }
+
+    #[test]
+    fn toml_unknown_field() {
+        let mut dotrc = Builder::new().suffix(".toml").tempfile().unwrap();
+        write!(dotrc, "not-an-option = true").unwrap();
+
+        assert!(super::Config::get(Some(dotrc.path())).is_err());
+    }
 }
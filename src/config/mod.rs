@@ -1,40 +1,114 @@
 pub mod cli;
 mod dotrc;
 
+pub use dotrc::STARTER as STARTER_DOTRC;
+
 use crate::{
     common::{self, util, AbsolutePath, Platform},
+    debug_println, git,
+    linker::{self, ConflictPolicy},
+    resolver::{self, PrefixKind, PrefixNames},
     verbose_println,
 };
 use derive_more::From;
 use failure::Fail;
 use gethostname::gethostname;
-use globset::Glob;
+use globset::{Glob, GlobMatcher, GlobSet, GlobSetBuilder};
+use ignore::WalkBuilder;
+use itertools::Itertools;
 use lazy_static::lazy_static;
 use std::{
-    collections::HashSet,
+    collections::{HashMap, HashSet},
+    env,
     ffi::OsStr,
+    fmt::{self, Display},
+    fs, io, iter,
     path::{Path, PathBuf},
     str::FromStr,
 };
+use strum::IntoEnumIterator;
 use walkdir::WalkDir;
 
 const DEFAULT_DOTFILES_DIR: &str = ".dotfiles";
+/// The environment variable listing extra tags to enable, separated by commas
+/// or whitespace
+const TAGS_VAR: &str = "DOTMAN_TAGS";
+/// The name of the file (in the root of the dotfiles folder) listing additional
+/// excludes
+const DOTIGNORE_NAME: &str = ".dotignore";
 lazy_static! {
-    static ref DOTRC_NAMES: [&'static OsStr; 3] = [
+    static ref DOTRC_NAMES: [&'static OsStr; 4] = [
         OsStr::new(".dotrc"),
         OsStr::new(".dotrc.yml"),
-        OsStr::new(".dotrc.yaml")
+        OsStr::new(".dotrc.yaml"),
+        OsStr::new(".dotrc.toml")
     ];
 }
 
 /// All dotman configuration options
 #[derive(Debug)]
 pub struct Config {
+    /// The excludes and includes as written, which `excludes`, `dead_excludes`,
+    /// and `includes` are expanded from (see `Config::expand_globs`)
+    pub raw_globs: RawGlobs,
     pub excludes: Vec<AbsolutePath>,
+    /// Exclude globs (as written) which didn't match anything
+    pub dead_excludes: Vec<PathBuf>,
+    /// Dotfiles whose destinations (or any folder they're in) match this
+    /// aren't linked, wherever they come from
+    pub dest_excludes: GlobSet,
+    /// If present, only items within these paths are active
+    pub includes: Option<Vec<AbsolutePath>>,
     pub tags: Vec<String>,
-    pub dotfiles_path: AbsolutePath,
-    pub hostname: String,
+    /// The folders to search for dotfiles, in order
+    pub dotfiles_paths: Vec<AbsolutePath>,
+    /// The dotfiles folders which are clones of remote repositories (see
+    /// `git::clone_if_missing`), which linking pulls first
+    pub remote_clones: Vec<AbsolutePath>,
+    /// The hostnames whose `host-` folders are active. The first is the main
+    /// one, e.g. for templates.
+    pub hostnames: Vec<String>,
     pub platform: Platform,
+    /// The CPU architecture, as in `std::env::consts::ARCH`
+    pub arch: String,
+    /// The folder dotfiles are linked into, usually the home directory
+    pub target: AbsolutePath,
+    /// Custom destinations for matching dotfiles, in order of precedence
+    pub links: Vec<Link>,
+    /// Folders other than `target` which the contents of some prefixed folders
+    /// are linked into
+    pub prefix_roots: Vec<PrefixRoot>,
+    /// What each kind of prefixed folder's name starts with
+    pub prefixes: PrefixNames,
+    /// Top-level dotfiles (or folders) matching this are linked without a
+    /// leading dot, as in `bin` to `~/bin`
+    pub no_dot: GlobSet,
+    /// Dotfiles matching these (as for `links`) are linked before everything
+    /// else, in the order of the first glob each matches
+    pub link_first: GlobSet,
+    /// Like `link_first`, but for dotfiles linked after everything else
+    pub link_last: GlobSet,
+    /// Commands which must succeed for matching dotfiles to be linked
+    pub requirements: Vec<Requirement>,
+    /// Permission bits given to matching dotfiles after linking, on Unix
+    pub modes: Vec<Mode>,
+    /// Whether symlinked folders within the dotfiles folders are searched for
+    /// dotfiles (and excludes), rather than skipped
+    pub follow_source_links: bool,
+    /// Shell commands to run (from the first dotfiles folder) before linking
+    pub pre_link_hooks: Vec<String>,
+    /// Shell commands to run (from the first dotfiles folder) after linking
+    pub post_link_hooks: Vec<String>,
+    /// Whether to pull the dotfiles folders before linking, even without
+    /// `--pull`
+    pub auto_pull: bool,
+    /// How to handle conflicts when linking, unless the CLI says otherwise
+    pub conflict_policy: Option<ConflictPolicy>,
+    /// Whether to reuse the dotfiles resolved by the last run while nothing
+    /// they depend on changes (see `resolver::cache`)
+    pub cache: bool,
+    /// Where each of the options which one source overrides came from
+    pub sources: Sources,
     pub command: cli::Command,
 }
 
@@ -44,12 +118,420 @@ impl Config {
     /// Draws from CLI arguments, the dotrc, and default values (where
     /// applicable)
     pub fn get() -> Result<Self, Error> {
-        let partial_config = PartialConfig::merge(cli::Config::get(), DefaultConfig::get()?);
-        let dotrc_config = dotrc::Config::get(find_dotrc(&partial_config))?;
-        let config = merge_dotrc(partial_config, dotrc_config)?;
+        Config::from_cli(cli::Config::get())
+    }
+
+    /// Loads the configuration, given the already-parsed CLI arguments.
+    ///
+    /// With `--no-dotrc`, only the CLI arguments and default values are used.
+    pub fn from_cli(cli: cli::Config) -> Result<Self, Error> {
+        let no_dotrc = cli.no_dotrc;
+        let partial_config = PartialConfig::merge(cli, DefaultConfig::get()?)?;
+        let mut config = if no_dotrc {
+            verbose_println!("Ignoring the dotrc");
+            partial_config.to_config()?
+        } else {
+            let dotrc_config = dotrc::Config::get(find_dotrc(&partial_config))?;
+            merge_dotrc(partial_config, dotrc_config)?
+        };
+        config.expand_globs()?;
+        // A typo in an exclude would otherwise silently link what it was meant to
+        // exclude
+        for exclude in &config.dead_excludes {
+            eprintln!(
+                "Warning: exclude {} doesn't match anything",
+                exclude.display()
+            );
+        }
+
+        if let Some(warning) = config.platform_warning() {
+            eprintln!("Warning: {}", warning);
+        }
 
         Ok(config)
     }
+
+    /// Glob-expands `raw_globs` within the dotfiles folders. If `cache` is set,
+    /// the expansions from an earlier run are reused while nothing they depend
+    /// on has changed (see `resolver::cache::get_expansions`).
+    fn expand_globs(&mut self) -> Result<(), Error> {
+        let expansions = resolver::cache::get_expansions(self)?;
+        self.excludes = expansions.excludes;
+        self.dead_excludes = expansions.dead_excludes;
+        self.includes = expansions.includes;
+
+        Ok(())
+    }
+
+    /// Notes when the platform was overridden with one other than the platform
+    /// actually detected. That's sometimes intentional (as when trying out
+    /// another platform's dotfiles), but often a mistake.
+    fn platform_warning(&self) -> Option<String> {
+        let detected = util::platform();
+        if self.sources.platform == Source::Default || self.platform == detected {
+            return None;
+        }
+
+        Some(format!(
+            "using platform {} ({}), but this is {}",
+            self.platform, self.sources.platform, detected
+        ))
+    }
+}
+
+/// Shows the resolved options, along with where each came from (for
+/// `--print-config`)
+impl Display for Config {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "Dotfiles folders ({}):", self.sources.dotfiles_paths)?;
+        for path in &self.dotfiles_paths {
+            writeln!(f, "    {}", path)?;
+        }
+        writeln!(f, "Target: {} ({})", self.target, self.sources.target)?;
+        writeln!(
+            f,
+            "Hostnames: {} ({})",
+            self.hostnames.join(", "),
+            self.sources.hostnames
+        )?;
+        writeln!(f, "Platform: {} ({})", self.platform, self.sources.platform)?;
+        writeln!(f, "Arch: {} ({})", self.arch, self.sources.arch)?;
+
+        // Tags and excludes are combined from every source, so none of them wins
+        if self.tags.is_empty() {
+            writeln!(f, "Tags: (none)")?;
+        } else {
+            writeln!(f, "Tags: {} (combined)", self.tags.join(", "))?;
+        }
+        if self.excludes.is_empty() {
+            write!(f, "Excludes: (none)")?;
+        } else {
+            write!(f, "Excludes (combined):")?;
+            for exclude in &self.excludes {
+                write!(f, "\n    {}", exclude)?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Excludes and includes as written, which may be globs (relative to each
+/// dotfiles folder)
+#[derive(Debug, Clone, Default)]
+pub struct RawGlobs {
+    pub excludes: Vec<PathBuf>,
+    pub includes: Vec<PathBuf>,
+    /// Whether whatever the dotfiles folders' `.gitignore` files ignore is
+    /// excluded too
+    pub gitignore: bool,
+}
+
+/// What a configuration's `RawGlobs` expand to
+#[derive(Debug, Clone, PartialEq)]
+pub struct Expansions {
+    pub excludes: Vec<AbsolutePath>,
+    pub dead_excludes: Vec<PathBuf>,
+    pub includes: Option<Vec<AbsolutePath>>,
+}
+
+impl Expansions {
+    /// Expands `config.raw_globs` within each of `config.dotfiles_paths` (see
+    /// `expand_excludes` and `expand_includes`)
+    pub fn of(config: &Config) -> Result<Self, Error> {
+        let raw_globs = &config.raw_globs;
+        let (excludes, dead_excludes) = expand_excludes(
+            &raw_globs.excludes,
+            &config.dotfiles_paths,
+            raw_globs.gitignore,
+            config.follow_source_links,
+        )?;
+        let includes = expand_includes(
+            &raw_globs.includes,
+            &config.dotfiles_paths,
+            config.follow_source_links,
+        )?;
+
+        Ok(Expansions {
+            excludes,
+            dead_excludes,
+            includes,
+        })
+    }
+}
+
+/// Where a configuration option came from
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Source {
+    Cli,
+    Dotrc,
+    Default,
+}
+
+impl Display for Source {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let s = match self {
+            Source::Cli => "from the CLI",
+            Source::Dotrc => "from the dotrc",
+            Source::Default => "default",
+        };
+
+        f.pad(s)
+    }
+}
+
+impl From<&PartialSource> for Source {
+    fn from(source: &PartialSource) -> Self {
+        match source {
+            PartialSource::Cli => Source::Cli,
+            PartialSource::Default => Source::Default,
+        }
+    }
+}
+
+/// The source of each option which is taken from whichever of the CLI, the
+/// dotrc, and the default comes first
+#[derive(Debug, Clone, Copy)]
+pub struct Sources {
+    pub dotfiles_paths: Source,
+    pub hostnames: Source,
+    pub platform: Source,
+    pub arch: Source,
+    pub target: Source,
+}
+
+impl Default for Sources {
+    fn default() -> Self {
+        Sources {
+            dotfiles_paths: Source::Default,
+            hostnames: Source::Default,
+            platform: Source::Default,
+            arch: Source::Default,
+            target: Source::Default,
+        }
+    }
+}
+
+/// Expands the environment variables and leading tilde in `path`. Paths which
+/// aren't valid Unicode only have their tilde expanded.
+fn expand_cli_path(path: PathBuf) -> Result<PathBuf, Error> {
+    let path = match path.to_str() {
+        Some(s) => PathBuf::from(util::expand_env_vars(s)?),
+        None => path,
+    };
+
+    Ok(util::tilde_to_home(path))
+}
+
+/// Resolves `path` (from the CLI) against the current folder, as for the paths
+/// `add` and `explain` take, unless it's already absolute
+fn absolute_cli_path(path: PathBuf) -> Result<PathBuf, Error> {
+    if path.is_absolute() {
+        return Ok(path);
+    }
+
+    Ok(env::current_dir().map_err(CurrentDirError)?.join(path))
+}
+
+/// Checks that the tags, hostnames, and architecture, which become part of the
+/// names of prefixed folders, can't reach outside the folder they're searched
+/// in. The platform is always one of a fixed list, so it doesn't need
+/// checking.
+fn check_names(tags: &[String], hostnames: &[String], arch: &str) -> Result<(), Error> {
+    let names = tags
+        .iter()
+        .map(|tag| ("tag", tag.as_str()))
+        .chain(
+            hostnames
+                .iter()
+                .map(|hostname| ("hostname", hostname.as_str())),
+        )
+        .chain(iter::once(("arch", arch)));
+    for (kind, name) in names {
+        // Both separators are rejected everywhere, so a dotrc works the same on
+        // every platform
+        if name.contains(['/', '\\']) || name.contains("..") {
+            return Err(InvalidName {
+                kind,
+                name: String::from(name),
+            });
+        }
+    }
+
+    Ok(())
+}
+
+/// Checks that each kind of prefixed folder has a prefix of its own, which can
+/// be part of a folder's name
+fn check_prefixes(prefixes: &PrefixNames) -> Result<(), Error> {
+    let mut seen = HashSet::new();
+    for kind in PrefixKind::iter() {
+        let prefix = prefixes.get(kind);
+        if prefix.contains(['/', '\\']) || prefix.contains("..") {
+            return Err(InvalidName {
+                kind: "prefix",
+                name: String::from(prefix),
+            });
+        }
+        if prefix.is_empty() || !seen.insert(prefix) {
+            return Err(AmbiguousPrefix(String::from(prefix)));
+        }
+    }
+
+    Ok(())
+}
+
+/// Makes a glob matching destinations from `glob`, which is relative to
+/// `target` unless it's absolute, as for `Link::new`
+fn dest_glob(glob: &str, target: &AbsolutePath) -> Result<Glob, Error> {
+    let glob = PathBuf::from(util::expand_env_vars(glob)?);
+    let glob = target.join(glob.strip_prefix("~").unwrap_or(&glob));
+
+    Ok(Glob::new(&glob.to_string_lossy())?)
+}
+
+/// Maps the dotfiles matching a glob to a custom destination, rather than the
+/// usual hidden path in the home directory
+#[derive(Debug)]
+pub struct Link {
+    /// Matched against the path of each dotfile relative to the folder it's
+    /// linked from, as in `vim/vimrc` for `host-foo/vim/vimrc`
+    source: GlobMatcher,
+
+    pub dest: AbsolutePath,
+
+    /// Whether `dest` is a directory which matching dotfiles get placed in,
+    /// rather than the destination itself. Written as a trailing slash.
+    pub into_dir: bool,
+}
+
+impl Link {
+    /// Creates a link from `source` to `dest`.
+    ///
+    /// `dest` may use environment variables, and is relative to `target`
+    /// unless it's absolute. A leading tilde also stands for `target`.
+    pub fn new(source: &str, dest: &str, target: &AbsolutePath) -> Result<Self, Error> {
+        let source = Glob::new(source)?.compile_matcher();
+        let into_dir = dest.ends_with('/') || dest.ends_with(std::path::MAIN_SEPARATOR);
+        let dest = target_path(dest, target)?;
+
+        Ok(Link {
+            source,
+            dest,
+            into_dir,
+        })
+    }
+
+    /// Checks whether this link applies to the dotfile at `path` (see
+    /// `source`)
+    pub fn matches(&self, path: &Path) -> bool {
+        self.source.is_match(path)
+    }
+}
+
+/// Resolves `path` from the dotrc, which may use environment variables, and is
+/// relative to `target` unless it's absolute. A leading tilde also stands for
+/// `target`.
+fn target_path(path: &str, target: &AbsolutePath) -> Result<AbsolutePath, Error> {
+    let path = PathBuf::from(util::expand_env_vars(path)?);
+
+    Ok(AbsolutePath::from(
+        target.join(path.strip_prefix("~").unwrap_or(&path)),
+    ))
+}
+
+/// Links the contents of a prefixed folder into a folder other than the target
+/// folder, as in `tag-bin/tool` to `~/bin/tool`
+#[derive(Debug)]
+pub struct PrefixRoot {
+    /// The prefixed folder's name, as in `tag-bin`. Where prefixed folders are
+    /// nested, the innermost one with a root decides.
+    pub name: String,
+
+    pub root: AbsolutePath,
+
+    /// Whether dotfiles get a leading dot in `root`, as they do in the target
+    /// folder
+    pub hidden: bool,
+}
+
+impl PrefixRoot {
+    /// Creates a root for the prefixed folder `name` (prefixed as in
+    /// `prefixes`). `root` is resolved as for `Link::new`.
+    pub fn new(
+        name: &str,
+        root: &str,
+        hidden: bool,
+        target: &AbsolutePath,
+        prefixes: &PrefixNames,
+    ) -> Result<Self, Error> {
+        if prefixes.split(Path::new(name)).is_none() {
+            return Err(NotPrefixed(String::from(name)));
+        }
+
+        Ok(PrefixRoot {
+            name: String::from(name),
+            root: target_path(root, target)?,
+            hidden,
+        })
+    }
+}
+
+/// Only links the dotfiles matching a glob if a shell command succeeds, as in
+/// only linking `nvim`'s config if `command -v nvim` does
+#[derive(Debug)]
+pub struct Requirement {
+    /// Matched as for `Link`
+    source: GlobMatcher,
+
+    pub command: String,
+}
+
+impl Requirement {
+    pub fn new(source: &str, command: &str) -> Result<Self, Error> {
+        Ok(Requirement {
+            source: Glob::new(source)?.compile_matcher(),
+            command: String::from(command),
+        })
+    }
+
+    /// Checks whether this requirement applies to the dotfile at `path` (see
+    /// `Link::matches`)
+    pub fn matches(&self, path: &Path) -> bool {
+        self.source.is_match(path)
+    }
+}
+
+/// Gives the dotfiles matching a glob fixed permission bits, as in making
+/// `ssh/config` only readable by its owner
+#[derive(Debug)]
+pub struct Mode {
+    /// Matched as for `Link`
+    source: GlobMatcher,
+
+    pub mode: u32,
+}
+
+impl Mode {
+    /// Creates a mode for `source` from `mode`, given in octal (as for
+    /// `chmod`)
+    pub fn new(source: &str, mode: &str) -> Result<Self, Error> {
+        let bits = u32::from_str_radix(mode, 8)
+            .ok()
+            .filter(|bits| *bits <= 0o7777)
+            .ok_or_else(|| InvalidMode(String::from(mode)))?;
+
+        Ok(Mode {
+            source: Glob::new(source)?.compile_matcher(),
+            mode: bits,
+        })
+    }
+
+    /// Checks whether this mode applies to the dotfile at `path` (see
+    /// `Link::matches`)
+    pub fn matches(&self, path: &Path) -> bool {
+        self.source.is_match(path)
+    }
 }
 
 #[derive(Debug)]
@@ -64,16 +546,38 @@ enum PartialSource {
 #[derive(Debug)]
 struct PartialConfig {
     excludes: Vec<PathBuf>,
+    includes: Vec<PathBuf>,
     tags: Vec<String>,
-    dotfiles_path: (PathBuf, PartialSource),
-    hostname: (String, PartialSource),
+    dotfiles_paths: (Vec<PathBuf>, PartialSource),
+    remote_clones: Vec<PathBuf>,
+    hostnames: (Vec<String>, PartialSource),
     platform: (Platform, PartialSource),
+    arch: (String, PartialSource),
+    target: (PathBuf, PartialSource),
+    follow_source_links: bool,
+    no_cache: bool,
     command: cli::Command,
 }
 
 impl PartialConfig {
-    fn merge(cli: cli::Config, default: DefaultConfig) -> Self {
-        let excludes = util::append_vecs(cli.excludes, default.excludes);
+    fn merge(cli: cli::Config, default: DefaultConfig) -> Result<Self, Error> {
+        /// Expands the environment variables and leading tilde in each of the
+        /// paths from the CLI. The shell usually does this itself, but not in
+        /// quotes or after an `=` (as in `--dotfiles-path=~/dots`).
+        fn cli_paths(paths: Vec<PathBuf>) -> Result<Vec<PathBuf>, Error> {
+            paths.into_iter().map(expand_cli_path).collect()
+        }
+
+        let mut excludes = cli_paths(cli.excludes)?;
+        for path in cli_paths(cli.exclude_files)? {
+            let contents = fs::read_to_string(&path).map_err(|err| ExcludeFileError {
+                path: path.display().to_string(),
+                err,
+            })?;
+            excludes.extend(util::parse_pattern_list(&contents));
+        }
+        let excludes = util::append_vecs(excludes, default.excludes);
+        let includes = util::append_vecs(cli.includes, default.includes);
         let tags = util::append_vecs(cli.tags, default.tags);
 
         /// Gets `$field` from `cli` if possible and `default` otherwise,
@@ -86,48 +590,140 @@ impl PartialConfig {
                 }
             };
         }
-        let dotfiles_path = merge_with_source!(dotfiles_path);
-        let hostname = merge_with_source!(hostname);
+        /// Like `cli_paths`, but with dotfiles folders given as the URLs of
+        /// remote repositories replaced by local clones of them (see
+        /// `git::clone_if_missing`), which are also listed on their own.
+        ///
+        /// Clones aren't pulled here, since most commands don't need the
+        /// latest dotfiles (or the network).
+        fn cli_dotfiles_paths(paths: Vec<PathBuf>) -> Result<(Vec<PathBuf>, Vec<PathBuf>), Error> {
+            let mut clones = vec![];
+            let paths = paths
+                .into_iter()
+                .map(|path| match path.to_str() {
+                    Some(url) if git::is_url(url) => {
+                        let clone =
+                            git::clone_if_missing(url, &util::cache_dir()).map_err(|err| {
+                                RemoteDotfilesError {
+                                    url: String::from(url),
+                                    err,
+                                }
+                            })?;
+                        clones.push(clone.to_path_buf());
+
+                        Ok(clone.to_path_buf())
+                    },
+                    _ => expand_cli_path(path),
+                })
+                .collect::<Result<_, _>>()?;
+
+            Ok((paths, clones))
+        }
+
+        let (dotfiles_paths, remote_clones) = if cli.dotfiles_paths.is_empty() {
+            ((default.dotfiles_paths, PartialSource::Default), vec![])
+        } else {
+            let (paths, clones) = cli_dotfiles_paths(cli.dotfiles_paths)?;
+            ((paths, PartialSource::Cli), clones)
+        };
+        let hostnames = if cli.hostnames.is_empty() {
+            (vec![default.hostname], PartialSource::Default)
+        } else {
+            (cli.hostnames, PartialSource::Cli)
+        };
         let platform = merge_with_source!(platform);
+        let arch = merge_with_source!(arch);
+        let target = match cli.target {
+            Some(target) => (
+                absolute_cli_path(expand_cli_path(target)?)?,
+                PartialSource::Cli,
+            ),
+            None => (default.target, PartialSource::Default),
+        };
+        // Checked before they're used to find the dotrc
+        check_names(&tags, &hostnames.0, &arch.0)?;
 
+        let follow_source_links = cli.follow_source_links;
+        let no_cache = cli.no_cache;
         let command = cli.command;
 
-        PartialConfig {
+        Ok(PartialConfig {
             excludes,
+            includes,
             tags,
-            dotfiles_path,
-            hostname,
+            dotfiles_paths,
+            remote_clones,
+            hostnames,
             platform,
+            arch,
+            target,
+            follow_source_links,
+            no_cache,
             command,
-        }
+        })
     }
 
     fn to_config(&self) -> Result<Config, Error> {
-        let dotfiles_path = AbsolutePath::from(self.dotfiles_path.0.clone());
-
-        let excludes = self
-            .excludes
+        let dotfiles_paths: Vec<_> = self
+            .dotfiles_paths
+            .0
             .iter()
-            // Glob-expand
-            .map(|exclude| expand_glob(exclude, &dotfiles_path))
-            .collect::<Result<Vec<_>, _>>()?
-            .into_iter()
-            .flatten()
-            // Make each exclude path absolute by prepending them with the dotfiles path
-            .map(|exclude| AbsolutePath::from(dotfiles_path.join(exclude)))
+            .map(|path| AbsolutePath::from(path.clone()))
             .collect();
 
+        let follow_source_links = self.follow_source_links;
+        let raw_globs = RawGlobs {
+            excludes: self.excludes.clone(),
+            includes: self.includes.clone(),
+            gitignore: false,
+        };
+
         let tags = self.tags.clone();
-        let hostname = self.hostname.0.clone();
+        let hostnames = self.hostnames.0.clone();
         let platform = self.platform.0;
-        let command = self.command;
+        let arch = self.arch.0.clone();
+        let target = AbsolutePath::from(self.target.0.clone());
+        let sources = Sources {
+            dotfiles_paths: Source::from(&self.dotfiles_paths.1),
+            hostnames: Source::from(&self.hostnames.1),
+            platform: Source::from(&self.platform.1),
+            arch: Source::from(&self.arch.1),
+            target: Source::from(&self.target.1),
+        };
+        let command = self.command.clone();
 
         Ok(Config {
-            excludes,
+            raw_globs,
+            excludes: vec![],
+            dead_excludes: vec![],
+            dest_excludes: GlobSet::empty(),
+            includes: None,
             tags,
-            dotfiles_path,
-            hostname,
+            dotfiles_paths,
+            remote_clones: self
+                .remote_clones
+                .iter()
+                .map(|path| AbsolutePath::from(path.clone()))
+                .collect(),
+            hostnames,
             platform,
+            arch,
+            target,
+            links: vec![],
+            prefix_roots: vec![],
+            prefixes: PrefixNames::default(),
+            no_dot: GlobSet::empty(),
+            link_first: GlobSet::empty(),
+            link_last: GlobSet::empty(),
+            requirements: vec![],
+            modes: vec![],
+            follow_source_links,
+            pre_link_hooks: vec![],
+            post_link_hooks: vec![],
+            auto_pull: false,
+            conflict_policy: None,
+            cache: false,
+            sources,
             command,
         })
     }
@@ -135,10 +731,13 @@ impl PartialConfig {
 
 struct DefaultConfig {
     excludes: Vec<PathBuf>,
+    includes: Vec<PathBuf>,
     tags: Vec<String>,
-    dotfiles_path: PathBuf,
+    dotfiles_paths: Vec<PathBuf>,
     hostname: String,
     platform: Platform,
+    arch: String,
+    target: PathBuf,
 }
 
 impl DefaultConfig {
@@ -146,82 +745,405 @@ impl DefaultConfig {
     /// values/sources of each configuration option.
     fn get() -> Result<Self, Error> {
         let excludes = vec![];
-        let tags = vec![];
+        let includes = vec![];
+        let tags = env_tags();
 
-        let dotfiles_path = util::home_dir().join(DEFAULT_DOTFILES_DIR);
+        let dotfiles_paths = vec![util::home_dir().join(DEFAULT_DOTFILES_DIR)];
 
         let hostname = gethostname().to_str().ok_or(NoSystemHostname)?.to_owned();
 
         let platform = util::platform();
 
+        let arch = String::from(env::consts::ARCH);
+
+        let target = util::home_dir().to_path_buf();
+
         Ok(DefaultConfig {
             excludes,
+            includes,
             tags,
-            dotfiles_path,
+            dotfiles_paths,
             hostname,
             platform,
+            arch,
+            target,
         })
     }
 }
 
-/// Tries to glob-expand `path`.
+/// Reads the tags listed in `TAGS_VAR`, if it's set
+fn env_tags() -> Vec<String> {
+    env::var(TAGS_VAR)
+        .unwrap_or_default()
+        .split(|c: char| c == ',' || c.is_whitespace())
+        .filter(|tag| !tag.is_empty())
+        .map(String::from)
+        .collect()
+}
+
+/// Reads the excludes listed in the dotignore in `dotfiles_path`.
+///
+/// A missing dotignore is treated as an empty one.
+fn read_dotignore(dotfiles_path: &AbsolutePath) -> Result<Vec<PathBuf>, Error> {
+    match fs::read_to_string(dotfiles_path.join(DOTIGNORE_NAME)) {
+        Ok(contents) => Ok(util::parse_pattern_list(&contents)),
+        Err(ref err) if err.kind() == io::ErrorKind::NotFound => Ok(vec![]),
+        Err(err) => Err(DotignoreError(err)),
+    }
+}
+
+/// Finds the paths in `dotfiles_path` which are ignored by the `.gitignore`
+/// files within it (including those in subfolders).
+///
+/// Ignore files outside of `dotfiles_path`, as well as the global gitignore,
+/// don't apply.
+fn gitignored(
+    dotfiles_path: &AbsolutePath,
+    follow_links: bool,
+) -> Result<Vec<AbsolutePath>, Error> {
+    /// Checks whether `err` is about a symlink looping back to one of its
+    /// ancestors, which `walk` skips too
+    fn is_loop(err: &ignore::Error) -> bool {
+        match err {
+            ignore::Error::Loop { .. } => true,
+            ignore::Error::WithPath { err, .. }
+            | ignore::Error::WithDepth { err, .. }
+            | ignore::Error::WithLineNumber { err, .. } => is_loop(err),
+            _ => false,
+        }
+    }
+
+    if !dotfiles_path.is_dir() {
+        return Ok(vec![]);
+    }
+
+    let mut kept = HashSet::new();
+    let entries = WalkBuilder::new(dotfiles_path)
+        .standard_filters(false)
+        .git_ignore(true)
+        .require_git(false)
+        .follow_links(follow_links)
+        .build();
+    for entry in entries {
+        match entry {
+            Ok(entry) => kept.insert(entry.into_path()),
+            Err(ref err) if is_loop(err) => continue,
+            Err(err) => return Err(err.into()),
+        };
+    }
+
+    let mut res = vec![];
+    for entry in walk(dotfiles_path, follow_links) {
+        let entry = entry?;
+        if !kept.contains(entry.path()) {
+            res.push(AbsolutePath::from(entry.into_path()));
+        }
+    }
+
+    Ok(res)
+}
+
+/// Walks everything in `dotfiles_path`, descending into symlinked folders if
+/// `follow_links` is set (as `resolver::get_items` does).
+///
+/// Symlinks which loop back to one of their ancestors can't be descended into,
+/// so they're skipped.
+fn walk(
+    dotfiles_path: &AbsolutePath,
+    follow_links: bool,
+) -> impl Iterator<Item = Result<walkdir::DirEntry, Error>> + '_ {
+    WalkDir::new(dotfiles_path)
+        .follow_links(follow_links)
+        .into_iter()
+        .filter_map(move |entry| match entry {
+            Ok(entry) => Some(Ok(entry)),
+            Err(ref err) if err.loop_ancestor().is_some() => {
+                verbose_println!(
+                    "Skipping {}, which loops back to {}",
+                    util::home_to_tilde(err.path().unwrap_or(dotfiles_path)).display(),
+                    util::home_to_tilde(err.loop_ancestor().unwrap_or(dotfiles_path)).display()
+                );
+                None
+            },
+            Err(err) => Some(Err(err.into())),
+        })
+}
+
+/// The result of glob-expanding a path
+enum Expansion {
+    /// The paths (relative to the dotfiles folder) matching the glob, if any
+    Matched(Vec<PathBuf>),
+
+    /// The path couldn't be used as a glob, so it's used as is
+    Literal(PathBuf),
+}
+
+impl Expansion {
+    /// Checks whether the expansion refers to anything within `dotfiles_path`
+    fn matched_anything(&self, dotfiles_path: &Path) -> bool {
+        match self {
+            Expansion::Matched(paths) => !paths.is_empty(),
+            // `symlink_metadata` rather than `exists` so broken symlinks count
+            Expansion::Literal(path) => dotfiles_path.join(path).symlink_metadata().is_ok(),
+        }
+    }
+
+    fn into_paths(self) -> Vec<PathBuf> {
+        match self {
+            Expansion::Matched(paths) => paths,
+            Expansion::Literal(path) => vec![path],
+        }
+    }
+}
+
+/// Tries to glob-expand each of `paths`, walking `dotfiles_path` just once for
+/// all of them (see `walk`).
 /// If `PathBuf` -> `String` conversion fails or the pattern is invalid,
-/// fall back to simply not trying to glob-expand
-fn expand_glob(path: &Path, dotfiles_path: &AbsolutePath) -> Result<Vec<PathBuf>, Error> {
+/// fall back to simply not trying to glob-expand that path.
+///
+/// The expansions are returned in the same order as `paths`.
+fn expand_glob_set(
+    paths: &[&Path],
+    dotfiles_path: &AbsolutePath,
+    follow_links: bool,
+) -> Result<Vec<Expansion>, Error> {
     // Just to improve whitespace in verbose output about glob expansion
     let mut glob_output = {
         let mut had_glob_output = false;
         move || {
             if !had_glob_output {
                 had_glob_output = true;
-                verbose_println!();
+                debug_println!();
             }
         }
     };
 
-    let glob = match path.to_str().map(Glob::new) {
-        Some(Ok(glob)) => glob.compile_matcher(),
-        None | Some(Err(_)) => {
-            glob_output();
-            verbose_println!("Could not glob-expand {}", path.display());
-            return Ok(vec![PathBuf::from(path)]);
-        },
-    };
+    // The glob set only holds the valid globs, so `globbed` maps each glob's
+    // index in the set back to its index in `paths`
+    let mut builder = GlobSetBuilder::new();
+    let mut globbed = vec![];
+    let mut res: Vec<_> = paths
+        .iter()
+        .enumerate()
+        .map(|(i, path)| match path.to_str().map(Glob::new) {
+            Some(Ok(glob)) => {
+                builder.add(glob);
+                globbed.push(i);
+                Expansion::Matched(vec![])
+            },
+            Some(Err(_)) => {
+                glob_output();
+                debug_println!("Could not glob-expand {}", path.display());
+                Expansion::Literal(PathBuf::from(path))
+            },
+            // Globs have to be valid Unicode, but the path can still be used as
+            // it is
+            None => {
+                glob_output();
+                verbose_println!(
+                    "Warning: not glob-expanding {}, since it isn't valid Unicode",
+                    path.display()
+                );
+                Expansion::Literal(PathBuf::from(path))
+            },
+        })
+        .collect();
+    let set = builder.build()?;
 
-    let entries: Vec<walkdir::DirEntry> = WalkDir::new(dotfiles_path)
-        .follow_links(true)
-        .into_iter()
-        .collect::<Result<_, _>>()?;
+    // Nothing can match in a missing folder. This is reported elsewhere (e.g. by
+    // `doctor`), so it isn't treated as an error here.
+    if globbed.is_empty() || !dotfiles_path.is_dir() {
+        return Ok(res);
+    }
+
+    // The paths matched by more than one glob, along with (the indices in `paths`
+    // of) the globs matching each
+    let mut overlaps: HashMap<PathBuf, Vec<usize>> = HashMap::new();
+
+    for entry in walk(dotfiles_path, follow_links) {
+        let entry = entry?;
+        let entry_path = entry
+            .path()
+            .strip_prefix(dotfiles_path)
+            .expect("Entry should be in the dotfiles path");
+
+        let matches = set.matches(entry_path);
+        for &matched in &matches {
+            if let Expansion::Matched(expanded_paths) = &mut res[globbed[matched]] {
+                expanded_paths.push(PathBuf::from(entry_path));
+            }
+        }
+        if matches.len() > 1 {
+            let matched_by = matches.into_iter().map(|matched| globbed[matched]);
+            overlaps.insert(PathBuf::from(entry_path), matched_by.collect());
+        }
+    }
+
+    for &i in &globbed {
+        let (path, expanded_paths) = match &res[i] {
+            Expansion::Matched(expanded_paths) => (paths[i], expanded_paths),
+            Expansion::Literal(_) => continue,
+        };
+
+        // If an entry just got expanded to itself, don't print anything about it
+        match expanded_paths.as_slice() {
+            [expanded_path] if expanded_path == path => (),
+            _ => {
+                glob_output();
+                debug_println!("Glob-expanded {} to:", path.display());
+                for expanded_path in expanded_paths {
+                    let others = overlaps
+                        .get(expanded_path)
+                        .into_iter()
+                        .flatten()
+                        .filter(|&&other| other != i)
+                        .map(|&other| paths[other].display())
+                        .join(", ");
+                    if others.is_empty() {
+                        debug_println!("\t- {}", expanded_path.display())
+                    } else {
+                        debug_println!(
+                            "\t- {} (also matched by {})",
+                            expanded_path.display(),
+                            others
+                        )
+                    }
+                }
+            },
+        }
+    }
+
+    Ok(res)
+}
 
-    let expanded_paths: Vec<_> = entries
+/// Glob-expands each of `globs` (relative to `dotfiles_path`), producing a
+/// deduplicated list of absolute paths.
+fn expand_globs(
+    globs: impl IntoIterator<Item = PathBuf>,
+    dotfiles_path: &AbsolutePath,
+    follow_links: bool,
+) -> Result<Vec<AbsolutePath>, Error> {
+    let globs: Vec<_> = globs.into_iter().collect();
+    let globs: Vec<_> = globs.iter().map(PathBuf::as_path).collect();
+    let mut paths: Vec<AbsolutePath> = expand_glob_set(&globs, dotfiles_path, follow_links)?
         .into_iter()
-        .filter_map(|entry| {
-            let entry_path = entry
-                .path()
-                .strip_prefix(dotfiles_path)
-                .expect("Entry should be in the dotfiles path");
-
-            if glob.is_match(entry_path) {
-                Some(PathBuf::from(entry_path))
+        // Flatten the glob-expanded results
+        .flat_map(Expansion::into_paths)
+        // Then make each path absolute by prepending them with the dotfiles path
+        .map(|path| AbsolutePath::from(dotfiles_path.join(path)))
+        .collect();
+
+    // Finally, remove any duplicate entries due to files matching multiple globs
+    let set: HashSet<_> = paths.drain(..).collect();
+    paths.extend(set);
+
+    Ok(paths)
+}
+
+/// If `exclude` is a negation (prefixed with `!`), returns the glob it negates.
+fn negated(exclude: &Path) -> Option<&Path> {
+    util::strip_os_prefix(exclude.as_os_str(), "!").map(Path::new)
+}
+
+/// Expands `excludes` within each of `dotfiles_paths`, along with the excludes
+/// from each folder's dotignore. If `gitignore` is set, whatever each folder's
+/// `.gitignore` files ignore is excluded first. Symlinked folders are only
+/// searched if `follow_links` is set (see `walk`).
+///
+/// Excludes are evaluated in order (those from the dotignore last), so a
+/// negated exclude like `!secrets/public.asc` re-includes whatever earlier
+/// excludes matched and it also matches. Excluding a directory skips
+/// everything inside it, so re-including part of one means excluding its
+/// contents (`secrets/*`) rather than the directory itself.
+///
+/// Also returns the globs which matched nothing - in any folder, for
+/// `excludes`, or in their own folder, for dotignore entries.
+fn expand_excludes(
+    excludes: &[PathBuf],
+    dotfiles_paths: &[AbsolutePath],
+    gitignore: bool,
+    follow_links: bool,
+) -> Result<(Vec<AbsolutePath>, Vec<PathBuf>), Error> {
+    let mut res = vec![];
+    let mut matched = HashSet::new();
+    let mut dead = vec![];
+    for dotfiles_path in dotfiles_paths {
+        let mut excluded: Vec<AbsolutePath> = if gitignore {
+            gitignored(dotfiles_path, follow_links)?
+        } else {
+            vec![]
+        };
+
+        // Every exclude is expanded at once, then applied in order
+        let dotignore = read_dotignore(dotfiles_path)?;
+        let all: Vec<_> = excludes.iter().chain(&dotignore).collect();
+        let globs: Vec<_> = all
+            .iter()
+            .map(|exclude| negated(exclude).unwrap_or(exclude))
+            .collect();
+        let expansions = expand_glob_set(&globs, dotfiles_path, follow_links)?;
+
+        for (i, (exclude, expansion)) in all.into_iter().zip(expansions).enumerate() {
+            let was_matched = expansion.matched_anything(dotfiles_path);
+            let paths = expansion
+                .into_paths()
+                .into_iter()
+                .map(|path| AbsolutePath::from(dotfiles_path.join(path)));
+            if negated(exclude).is_some() {
+                let paths: HashSet<_> = paths.collect();
+                excluded.retain(|path| !paths.contains(path));
             } else {
-                None
+                excluded.extend(paths);
             }
-        })
-        .collect();
 
-    // If an entry just got expanded to itself, don't print anything about it
-    match expanded_paths.as_slice() {
-        [expanded_path] if expanded_path == path => (),
-        _ => {
-            glob_output();
-            verbose_println!("Glob-expanded {} to:", path.display());
-            for expanded_path in &expanded_paths {
-                verbose_println!("\t- {}", expanded_path.display())
+            // `excludes` only need to match in some folder, but dotignore entries
+            // need to match in their own
+            match (i < excludes.len(), was_matched) {
+                (true, true) => {
+                    matched.insert(&excludes[i]);
+                },
+                (false, false) => dead.push(exclude.clone()),
+                (true, false) | (false, true) => (),
             }
-        },
+        }
+
+        // Overlapping excludes (or gitignored files) may exclude the same path more
+        // than once
+        let mut seen = HashSet::new();
+        excluded.retain(|path| seen.insert(path.clone()));
+        res.extend(excluded);
     }
 
-    Ok(expanded_paths)
+    let unmatched = excludes.iter().filter(|exclude| !matched.contains(exclude));
+    dead.extend(unmatched.cloned());
+
+    Ok((res, dead))
+}
+
+/// Expands `includes` within each of `dotfiles_paths`, as for
+/// `expand_excludes`.
+///
+/// No includes at all means that everything is included, which is represented
+/// by `None`.
+fn expand_includes(
+    includes: &[PathBuf],
+    dotfiles_paths: &[AbsolutePath],
+    follow_links: bool,
+) -> Result<Option<Vec<AbsolutePath>>, Error> {
+    if includes.is_empty() {
+        return Ok(None);
+    }
+
+    let mut res = vec![];
+    for dotfiles_path in dotfiles_paths {
+        res.extend(expand_globs(
+            includes.iter().cloned(),
+            dotfiles_path,
+            follow_links,
+        )?);
+    }
+
+    Ok(Some(res))
 }
 
 /// Merges a partial config (obtained from the CLI and default settings) with a
@@ -236,71 +1158,213 @@ fn merge_dotrc(
     /// - CLI
     /// - dotrc
     /// - Default source
-    fn merge_hierarchy<T>(partial: (T, PartialSource), dotrc: Option<T>) -> T {
+    ///
+    /// along with which of them it came from.
+    fn merge_hierarchy<T>(partial: (T, PartialSource), dotrc: Option<T>) -> (T, Source) {
         match (partial, dotrc) {
-            ((x, PartialSource::Cli), _) => x,
-            (_, Some(x)) => x,
-            ((x, PartialSource::Default), None) => x,
-        }
-    }
-
-    let dotfiles_path = AbsolutePath::from(merge_hierarchy(
-        partial_config.dotfiles_path,
-        dotrc_config.dotfiles_path.map(util::tilde_to_home),
-    ));
-
-    let excludes = {
-        let mut excludes: Vec<AbsolutePath> =
-            // Merge the excludes from partial_config (CLI + default) with the excludes from the dotrc
-            util::append_vecs(
-                partial_config.excludes,
-                // We need to handle the possibility of the dotrc not specifying any excludes,
-                // as well as converting from the raw `String` input to a `PathBuf`
-                dotrc_config
-                    .excludes
-                    .unwrap_or_default()
-                    .iter()
-                    .map(PathBuf::from)
-                    .collect(),
-            )
-            .into_iter()
-            // Try to glob expand each exclude
-            .map(|path| expand_glob(&path, &dotfiles_path))
-            // If any glob expansion failed due to an I/O error, give up
-            .collect::<Result<Vec<Vec<_>>, _>>()?
-            // Then flatten the glob-expanded results
-            .into_iter()
-            .flatten()
-            // Finally, make each exclude path absolute by prepending them with
-            // the dotfiles path
-            .map(|exclude| AbsolutePath::from(dotfiles_path.join(exclude)))
-            .collect();
+            ((x, PartialSource::Cli), _) => (x, Source::Cli),
+            (_, Some(x)) => (x, Source::Dotrc),
+            ((x, PartialSource::Default), None) => (x, Source::Default),
+        }
+    }
+
+    /// Expands the environment variables in each of the raw `String` paths
+    /// from the dotrc, converting them to `PathBuf`s
+    fn dotrc_paths(paths: Vec<String>) -> Result<Vec<PathBuf>, Error> {
+        paths
+            .iter()
+            .map(|path| Ok(PathBuf::from(util::expand_env_vars(path)?)))
+            .collect()
+    }
 
-        // Finally, remove any duplicate entries due to files matching multiple globs
-        let set: HashSet<_> = excludes.drain(..).collect();
-        excludes.extend(set.into_iter());
+    let dotrc_dotfiles_paths = dotrc_config
+        .dotfiles_path
+        // An empty list in the dotrc is treated like no list at all
+        .filter(|paths| !paths.is_empty())
+        .map(dotrc_paths)
+        .transpose()?
+        .map(|paths| paths.iter().map(util::tilde_to_home).collect());
+    let (dotfiles_paths, dotfiles_paths_source) =
+        merge_hierarchy(partial_config.dotfiles_paths, dotrc_dotfiles_paths);
+    let dotfiles_paths: Vec<_> = dotfiles_paths.into_iter().map(AbsolutePath::from).collect();
+    let remote_clones = partial_config
+        .remote_clones
+        .into_iter()
+        .map(AbsolutePath::from)
+        .collect();
 
-        excludes
-    };
+    /// Converts the raw `String` globs from the dotrc to `PathBuf`s, handling
+    /// the possibility of the dotrc not specifying any
+    fn dotrc_globs(globs: Option<Vec<String>>) -> Result<Vec<PathBuf>, Error> {
+        dotrc_paths(globs.unwrap_or_default())
+    }
 
     let tags = util::append_vecs(partial_config.tags, dotrc_config.tags.unwrap_or_default());
 
-    let hostname = merge_hierarchy(partial_config.hostname, dotrc_config.hostname);
+    let (platform, platform_source) = match (partial_config.platform, dotrc_config.platform) {
+        ((platform, PartialSource::Cli), _) => (platform, Source::Cli),
+        (_, Some(platform)) => (Platform::from_str(&platform)?, Source::Dotrc),
+        ((platform, PartialSource::Default), None) => (platform, Source::Default),
+    };
+
+    // Merge the excludes from partial_config (CLI + default) with the excludes from
+    // the dotrc, followed by those from the dotrc's active platform and tag groups
+    let mut dotrc_excludes = dotrc_config.excludes.unwrap_or_default();
+    for (group_platform, group_excludes) in dotrc_config.platform_excludes.unwrap_or_default() {
+        if Platform::from_str(&group_platform)? == platform {
+            dotrc_excludes.extend(group_excludes);
+        }
+    }
+    for (tag, group_excludes) in dotrc_config.tag_excludes.unwrap_or_default() {
+        if tags.contains(&tag) {
+            dotrc_excludes.extend(group_excludes);
+        }
+    }
+    let follow_source_links =
+        partial_config.follow_source_links || dotrc_config.follow_source_links.unwrap_or(false);
+    // These are only expanded once the rest of the configuration is known (see
+    // `Config::expand_globs`)
+    let raw_globs = RawGlobs {
+        excludes: util::append_vecs(partial_config.excludes, dotrc_paths(dotrc_excludes)?),
+        includes: util::append_vecs(partial_config.includes, dotrc_globs(dotrc_config.includes)?),
+        gitignore: dotrc_config.gitignore.unwrap_or(false),
+    };
+
+    let dotrc_hostnames = dotrc_config
+        .hostname
+        // As with dotfiles paths, an empty list is treated like no list at all
+        .filter(|hostnames| !hostnames.is_empty());
+    let (hostnames, hostnames_source) = merge_hierarchy(partial_config.hostnames, dotrc_hostnames);
+
+    let (arch, arch_source) = merge_hierarchy(partial_config.arch, dotrc_config.arch);
+    check_names(&tags, &hostnames, &arch)?;
+
+    let dotrc_target = dotrc_config
+        .target
+        .map(|target| util::expand_env_vars(&target))
+        .transpose()?
+        .map(util::tilde_to_home)
+        // Unlike on the CLI, there's no folder it's obviously relative to
+        .map(|target| {
+            if target.is_absolute() {
+                Ok(target)
+            } else {
+                Err(RelativeTarget(target.display().to_string()))
+            }
+        })
+        .transpose()?;
+    let (target, target_source) = merge_hierarchy(partial_config.target, dotrc_target);
+    let target = AbsolutePath::from(target);
+
+    let links = dotrc_config
+        .links
+        .unwrap_or_default()
+        .iter()
+        .map(|(source, dest)| Link::new(source, dest, &target))
+        .collect::<Result<_, _>>()?;
+
+    let prefixes = {
+        let dotrc_prefixes = dotrc_config.prefixes.unwrap_or_default();
+        let default = PrefixNames::default();
+        PrefixNames {
+            host: dotrc_prefixes.host.unwrap_or(default.host),
+            tag: dotrc_prefixes.tag.unwrap_or(default.tag),
+            platform: dotrc_prefixes.platform.unwrap_or(default.platform),
+            arch: dotrc_prefixes.arch.unwrap_or(default.arch),
+        }
+    };
+    check_prefixes(&prefixes)?;
+
+    let prefix_roots = dotrc_config
+        .prefix_roots
+        .unwrap_or_default()
+        .iter()
+        .map(|(name, root)| PrefixRoot::new(name, root.root(), root.hidden(), &target, &prefixes))
+        .collect::<Result<_, _>>()?;
+
+    let dest_excludes = {
+        let mut builder = GlobSetBuilder::new();
+        for glob in dotrc_config.dest_excludes.unwrap_or_default() {
+            builder.add(dest_glob(&glob, &target)?);
+        }
 
-    let platform = match (partial_config.platform, dotrc_config.platform) {
-        ((platform, PartialSource::Cli), _) => platform,
-        (_, Some(platform)) => Platform::from_str(&platform)?,
-        ((platform, PartialSource::Default), None) => platform,
+        builder.build()?
     };
 
+    /// Builds a set out of the raw globs from the dotrc, if it has any
+    fn dotrc_glob_set(globs: Option<Vec<String>>) -> Result<GlobSet, Error> {
+        let mut builder = GlobSetBuilder::new();
+        for glob in globs.unwrap_or_default() {
+            builder.add(Glob::new(&glob)?);
+        }
+
+        Ok(builder.build()?)
+    }
+
+    let no_dot = dotrc_glob_set(dotrc_config.no_dot)?;
+    let link_first = dotrc_glob_set(dotrc_config.link_first)?;
+    let link_last = dotrc_glob_set(dotrc_config.link_last)?;
+
+    let requirements = dotrc_config
+        .requires
+        .unwrap_or_default()
+        .iter()
+        .map(|(source, command)| Requirement::new(source, command))
+        .collect::<Result<_, _>>()?;
+
+    let modes = dotrc_config
+        .modes
+        .unwrap_or_default()
+        .iter()
+        .map(|(source, mode)| Mode::new(source, mode))
+        .collect::<Result<_, _>>()?;
+
+    let pre_link_hooks = dotrc_config.pre_link.unwrap_or_default();
+    let post_link_hooks = dotrc_config.post_link.unwrap_or_default();
+    let auto_pull = dotrc_config.auto_pull.unwrap_or(false);
+    let conflict_policy = dotrc_config
+        .conflict_policy
+        .map(|policy| ConflictPolicy::from_str(&policy))
+        .transpose()?;
+
+    let sources = Sources {
+        dotfiles_paths: dotfiles_paths_source,
+        hostnames: hostnames_source,
+        platform: platform_source,
+        arch: arch_source,
+        target: target_source,
+    };
+    let cache = dotrc_config.cache.unwrap_or(false) && !partial_config.no_cache;
     let command = partial_config.command;
 
     Ok(Config {
-        excludes,
+        raw_globs,
+        excludes: vec![],
+        dead_excludes: vec![],
+        dest_excludes,
+        includes: None,
         tags,
-        dotfiles_path,
-        hostname,
+        dotfiles_paths,
+        remote_clones,
+        hostnames,
         platform,
+        arch,
+        target,
+        links,
+        prefix_roots,
+        prefixes,
+        no_dot,
+        link_first,
+        link_last,
+        requirements,
+        modes,
+        follow_source_links,
+        pre_link_hooks,
+        post_link_hooks,
+        auto_pull,
+        conflict_policy,
+        cache,
+        sources,
         command,
     })
 }
@@ -309,15 +1373,22 @@ fn merge_dotrc(
 /// to find the dotrc file.
 ///
 /// Searches the following locations, in order:
-/// - The `host-` folder matching the hostname in `partial_config`
+/// - The `host-` folders matching the hostnames in `partial_config`
 /// - Any `tag-` folders matching the tags in `partial_config` (the tags are
 ///   searched in an unspecified order)
+/// - `$XDG_CONFIG_HOME/dotman/` (or `~/.config/dotman/`), except on macOS and
+///   Windows
 /// - The default location (`~/.dotrc`)
 fn find_dotrc(partial_config: &PartialConfig) -> Option<AbsolutePath> {
-    let config = partial_config.to_config().ok()?;
-
-    // Try to check if a dotrc was among the files discovered from partial_config
-    let items = crate::resolver::get_items(&config).ok()?;
+    // Try to check if a dotrc was among the files discovered from partial_config.
+    // The dotfiles folders may not exist yet (e.g. if the dotrc points elsewhere),
+    // so failing to search them isn't an error.
+    let items = partial_config
+        .to_config()
+        .and_then(|mut config| config.expand_globs().map(|()| config))
+        .ok()
+        .and_then(|config| crate::resolver::get_items(&config).ok())
+        .unwrap_or_default();
     for item in items {
         match item.dest.file_name() {
             Some(name) if DOTRC_NAMES.contains(&name) => {
@@ -328,17 +1399,42 @@ fn find_dotrc(partial_config: &PartialConfig) -> Option<AbsolutePath> {
         }
     }
 
-    // Otherwise, try to find a dotrc in the home directory
-    for dotrc_name in DOTRC_NAMES.iter() {
-        let dotrc_path = util::home_dir().join(dotrc_name);
-        if dotrc_path.exists() {
-            return Some(AbsolutePath::from(dotrc_path));
+    // Otherwise, try to find a dotrc in the XDG config directory, then the home
+    // directory
+    let dirs = xdg_config_dir()
+        .into_iter()
+        .chain(iter::once(util::home_dir().to_path_buf()));
+    for dir in dirs {
+        for dotrc_name in DOTRC_NAMES.iter() {
+            let dotrc_path = dir.join(dotrc_name);
+            if dotrc_path.exists() {
+                return Some(AbsolutePath::from(dotrc_path));
+            }
         }
     }
 
     None
 }
 
+/// The directory dotman's configuration belongs in according to the XDG base
+/// directory spec, which is `$XDG_CONFIG_HOME/dotman` (falling back to
+/// `~/.config/dotman`).
+///
+/// The spec isn't followed on macOS or Windows, so this is `None` there.
+fn xdg_config_dir() -> Option<PathBuf> {
+    if cfg!(any(target_os = "macos", windows)) {
+        return None;
+    }
+
+    // Relative paths are invalid according to the spec, and should be ignored
+    let config_home = env::var_os("XDG_CONFIG_HOME")
+        .map(PathBuf::from)
+        .filter(|path| path.is_absolute())
+        .unwrap_or_else(|| util::home_dir().join(".config"));
+
+    Some(config_home.join("dotman"))
+}
+
 #[derive(Fail, Debug, From)]
 pub enum Error {
     #[fail(display = "error reading system hostname")]
@@ -350,7 +1446,679 @@ pub enum Error {
     #[fail(display = "{}", _0)]
     DotrcError(#[fail(cause)] dotrc::Error),
 
+    #[fail(display = "error reading .dotignore ({})", _0)]
+    DotignoreError(#[fail(cause)] io::Error),
+
+    #[fail(display = "error reading excludes from {} ({})", path, err)]
+    ExcludeFileError {
+        /// The file as displayed, since `PathBuf` isn't `Display`
+        path: String,
+        #[fail(cause)]
+        err: io::Error,
+    },
+
+    #[fail(display = "error fetching dotfiles from {} ({})", url, err)]
+    RemoteDotfilesError {
+        url: String,
+        #[fail(cause)]
+        err: git::Error,
+    },
+
+    #[fail(
+        display = "invalid {} \"{}\" (it can't contain path separators or \"..\")",
+        kind, name
+    )]
+    #[from(ignore)]
+    InvalidName { kind: &'static str, name: String },
+
+    #[fail(
+        display = "prefix-roots can only be given for prefixed folders (such as tag-bin), not \
+                   \"{}\"",
+        _0
+    )]
+    #[from(ignore)]
+    NotPrefixed(String),
+
+    #[fail(
+        display = "prefix \"{}\" is ambiguous (each kind of prefixed folder needs a nonempty \
+                   prefix of its own)",
+        _0
+    )]
+    #[from(ignore)]
+    AmbiguousPrefix(String),
+
+    #[fail(
+        display = "invalid mode \"{}\" (modes are given in octal, as in 600 or 0755)",
+        _0
+    )]
+    #[from(ignore)]
+    InvalidMode(String),
+
+    #[fail(
+        display = "the target \"{}\" in the dotrc isn't absolute (a leading ~ stands for the home \
+                   folder)",
+        _0
+    )]
+    #[from(ignore)]
+    RelativeTarget(String),
+
+    #[fail(display = "error finding the current folder ({})", _0)]
+    #[from(ignore)]
+    CurrentDirError(#[fail(cause)] io::Error),
+
     #[fail(display = "{}", _0)]
     InvalidPlatform(#[fail(cause)] common::PlatformParseError),
+
+    #[fail(display = "{}", _0)]
+    InvalidConflictPolicy(#[fail(cause)] linker::ConflictPolicyParseError),
+
+    #[fail(display = "error expanding path ({})", _0)]
+    ExpandError(#[fail(cause)] util::ExpandError),
+
+    #[fail(display = "error reading .gitignore ({})", _0)]
+    GitignoreError(#[fail(cause)] ignore::Error),
+
+    #[fail(display = "invalid glob ({})", _0)]
+    InvalidGlob(#[fail(cause)] globset::Error),
 }
 use Error::*;
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        cli, dotrc, expand_cli_path, expand_excludes, expand_glob_set, merge_dotrc, Config,
+        DefaultConfig, Error, PartialConfig, PartialSource, Source, TAGS_VAR,
+    };
+    use crate::{
+        common::{
+            util::{self, Verbosity},
+            AbsolutePath, Platform,
+        },
+        resolver,
+    };
+    use pretty_assertions::assert_eq;
+    use std::{
+        env, fs, io,
+        path::{Path, PathBuf},
+        slice,
+    };
+    use tempfile::TempDir;
+
+    fn dotfiles() -> (TempDir, AbsolutePath) {
+        let dir = TempDir::new().unwrap();
+        fs::create_dir(dir.path().join("secrets")).unwrap();
+        for file in &["secrets/private.key", "secrets/public.asc", "vimrc"] {
+            fs::write(dir.path().join(file), "").unwrap();
+        }
+        let path = AbsolutePath::from(dir.path());
+
+        (dir, path)
+    }
+
+    /// A CLI config with nothing set
+    fn cli_config() -> cli::Config {
+        cli::Config {
+            verbosity: Verbosity::Normal,
+            assume_yes: false,
+            print_config: false,
+            excludes: vec![],
+            exclude_files: vec![],
+            includes: vec![],
+            tags: vec![],
+            dotfiles_paths: vec![],
+            hostnames: vec![],
+            platform: None,
+            arch: None,
+            target: None,
+            follow_source_links: false,
+            no_dotrc: false,
+            no_cache: false,
+            json_errors: false,
+            command: cli::Command::Doctor,
+        }
+    }
+
+    #[test]
+    fn no_dotrc() {
+        let (_dir, dotfiles_path) = dotfiles();
+        fs::write(dotfiles_path.join("dotrc"), "arch: riscv64\ntags: [work]\n").unwrap();
+        let config = |no_dotrc| {
+            let cli = cli::Config {
+                dotfiles_paths: vec![dotfiles_path.to_path_buf()],
+                no_dotrc,
+                ..cli_config()
+            };
+            Config::from_cli(cli).unwrap()
+        };
+
+        let config_with_dotrc = config(false);
+        assert_eq!(config_with_dotrc.arch, "riscv64");
+        assert_eq!(config_with_dotrc.sources.arch, Source::Dotrc);
+
+        let config_without_dotrc = config(true);
+        assert_eq!(config_without_dotrc.arch, env::consts::ARCH);
+        assert_eq!(config_without_dotrc.sources.arch, Source::Default);
+        assert!(!config_without_dotrc.tags.contains(&String::from("work")));
+    }
+
+    #[test]
+    fn env_tags() {
+        env::set_var(TAGS_VAR, "work, laptop  linux");
+        let default = DefaultConfig::get();
+        env::remove_var(TAGS_VAR);
+
+        let cli = cli::Config {
+            tags: vec![String::from("gui")],
+            ..cli_config()
+        };
+        let partial_config = PartialConfig::merge(cli, default.unwrap()).unwrap();
+        let dotrc_config = dotrc::Config {
+            tags: Some(vec![String::from("home")]),
+            ..dotrc::Config::default()
+        };
+        let config = merge_dotrc(partial_config, dotrc_config).unwrap();
+
+        assert_eq!(config.tags, vec!["gui", "work", "laptop", "linux", "home"]);
+    }
+
+    #[test]
+    fn invalid_names() {
+        let cli = cli::Config {
+            tags: vec![String::from("work"), String::from("../../etc")],
+            ..cli_config()
+        };
+        match PartialConfig::merge(cli, DefaultConfig::get().unwrap()) {
+            Err(Error::InvalidName { kind, name }) => {
+                assert_eq!((kind, name.as_str()), ("tag", "../../etc"))
+            },
+            res => panic!("unexpected result {:?}", res),
+        }
+
+        let partial_config = PartialConfig::merge(cli_config(), DefaultConfig::get().unwrap());
+        let dotrc_config = dotrc::Config {
+            hostname: Some(vec![String::from(r"laptop\..\..")]),
+            ..dotrc::Config::default()
+        };
+        match merge_dotrc(partial_config.unwrap(), dotrc_config) {
+            Err(Error::InvalidName { kind, .. }) => assert_eq!(kind, "hostname"),
+            res => panic!("unexpected result {:?}", res),
+        }
+    }
+
+    #[test]
+    fn ambiguous_prefixes() {
+        let config = |prefixes| {
+            let partial_config =
+                PartialConfig::merge(cli_config(), DefaultConfig::get().unwrap()).unwrap();
+            let dotrc_config = dotrc::Config {
+                prefixes: Some(prefixes),
+                ..dotrc::Config::default()
+            };
+
+            merge_dotrc(partial_config, dotrc_config)
+        };
+
+        let renamed = config(dotrc::Prefixes {
+            tag: Some(String::from("tag_")),
+            ..dotrc::Prefixes::default()
+        });
+        assert_eq!(renamed.unwrap().prefixes.tag, "tag_");
+
+        for tag in &["", "host-"] {
+            match config(dotrc::Prefixes {
+                tag: Some(String::from(*tag)),
+                ..dotrc::Prefixes::default()
+            }) {
+                Err(Error::AmbiguousPrefix(prefix)) => assert_eq!(prefix, *tag),
+                res => panic!("unexpected result {:?}", res),
+            }
+        }
+    }
+
+    #[test]
+    fn invalid_modes() {
+        assert_eq!(super::Mode::new("ssh/config", "600").unwrap().mode, 0o600);
+        assert_eq!(super::Mode::new("bin/*", "0755").unwrap().mode, 0o755);
+        for mode in &["", "rw", "800", "17777"] {
+            match super::Mode::new("ssh/config", mode) {
+                Err(Error::InvalidMode(invalid)) => assert_eq!(invalid, *mode),
+                res => panic!("unexpected result {:?}", res),
+            }
+        }
+    }
+
+    #[test]
+    fn no_cache() {
+        let config = |no_cache| {
+            let cli = cli::Config {
+                no_cache,
+                ..cli_config()
+            };
+            let partial_config = PartialConfig::merge(cli, DefaultConfig::get().unwrap()).unwrap();
+            let dotrc_config = dotrc::Config {
+                cache: Some(true),
+                ..dotrc::Config::default()
+            };
+
+            merge_dotrc(partial_config, dotrc_config).unwrap()
+        };
+
+        assert!(config(false).cache);
+        assert!(!config(true).cache);
+    }
+
+    #[test]
+    fn exclude_files() {
+        let dir = TempDir::new().unwrap();
+        let (full, empty) = (dir.path().join("excludes"), dir.path().join("empty"));
+        fs::write(&full, "# Secrets\n*.key\n\n  !public.key\n").unwrap();
+        fs::write(&empty, "").unwrap();
+
+        let cli = cli::Config {
+            excludes: vec![PathBuf::from("vimrc")],
+            exclude_files: vec![full, empty],
+            ..cli_config()
+        };
+        let partial_config = PartialConfig::merge(cli, DefaultConfig::get().unwrap()).unwrap();
+
+        let expected = vec![
+            PathBuf::from("vimrc"),
+            PathBuf::from("*.key"),
+            PathBuf::from("!public.key"),
+        ];
+        assert_eq!(partial_config.excludes, expected);
+    }
+
+    #[test]
+    fn missing_exclude_file() {
+        let dir = TempDir::new().unwrap();
+        let cli = cli::Config {
+            exclude_files: vec![dir.path().join("missing")],
+            ..cli_config()
+        };
+
+        match PartialConfig::merge(cli, DefaultConfig::get().unwrap()) {
+            Err(Error::ExcludeFileError { err, .. }) => {
+                assert_eq!(err.kind(), io::ErrorKind::NotFound)
+            },
+            res => panic!("unexpected result {:?}", res),
+        }
+    }
+
+    #[test]
+    fn cli_path_tilde() {
+        assert_eq!(
+            expand_cli_path(PathBuf::from("~/x")).unwrap(),
+            util::home_dir().join("x")
+        );
+        assert_eq!(
+            expand_cli_path(PathBuf::from("~x/y")).unwrap(),
+            PathBuf::from("~x/y")
+        );
+        assert_eq!(
+            expand_cli_path(PathBuf::from("x/~/y")).unwrap(),
+            PathBuf::from("x/~/y")
+        );
+    }
+
+    #[test]
+    fn relative_targets() {
+        let cli = cli::Config {
+            target: Some(PathBuf::from("sandbox/home")),
+            ..cli_config()
+        };
+        let partial_config = PartialConfig::merge(cli, DefaultConfig::get().unwrap()).unwrap();
+        let target = env::current_dir().unwrap().join("sandbox/home");
+        assert_eq!(partial_config.target.0, target);
+        assert_eq!(
+            partial_config.to_config().unwrap().target,
+            AbsolutePath::from(target)
+        );
+
+        let partial_config = PartialConfig::merge(cli_config(), DefaultConfig::get().unwrap());
+        let dotrc_config = dotrc::Config {
+            target: Some(String::from("sandbox/home")),
+            ..dotrc::Config::default()
+        };
+        match merge_dotrc(partial_config.unwrap(), dotrc_config) {
+            Err(Error::RelativeTarget(target)) => assert_eq!(target, "sandbox/home"),
+            res => panic!("unexpected result {:?}", res),
+        }
+    }
+
+    /// A partial config which only searches `dotfiles_path`, on Linux with the
+    /// `work` tag
+    fn partial_config(dotfiles_path: &AbsolutePath) -> PartialConfig {
+        PartialConfig {
+            excludes: vec![],
+            includes: vec![],
+            tags: vec![String::from("work")],
+            dotfiles_paths: (vec![dotfiles_path.to_path_buf()], PartialSource::Cli),
+            remote_clones: vec![],
+            hostnames: (vec![String::from("hostname")], PartialSource::Default),
+            platform: (Platform::Linux, PartialSource::Cli),
+            arch: (String::from("x86_64"), PartialSource::Default),
+            target: (PathBuf::from("/home/me"), PartialSource::Default),
+            follow_source_links: false,
+            no_cache: false,
+            command: cli::Command::Doctor,
+        }
+    }
+
+    #[test]
+    fn sources() {
+        let (_dir, dotfiles_path) = dotfiles();
+        let dotrc_config = dotrc::Config {
+            hostname: Some(vec![String::from("laptop")]),
+            platform: Some(String::from("macos")),
+            ..dotrc::Config::default()
+        };
+        let config = merge_dotrc(partial_config(&dotfiles_path), dotrc_config).unwrap();
+
+        // The CLI beats the dotrc, which beats the default
+        assert_eq!(config.platform, Platform::Linux);
+        assert_eq!(config.sources.platform, Source::Cli);
+        assert_eq!(config.hostnames, vec!["laptop"]);
+        assert_eq!(config.sources.hostnames, Source::Dotrc);
+        assert_eq!(config.sources.dotfiles_paths, Source::Cli);
+        assert_eq!(config.sources.arch, Source::Default);
+        assert_eq!(config.sources.target, Source::Default);
+    }
+
+    #[test]
+    fn platform_warning() {
+        let (_dir, dotfiles_path) = dotfiles();
+        let config = |platform: Option<Platform>| {
+            let partial_config = PartialConfig {
+                platform: (util::platform(), PartialSource::Default),
+                ..partial_config(&dotfiles_path)
+            };
+            let dotrc_config = dotrc::Config {
+                platform: platform.map(|platform| platform.to_string()),
+                ..dotrc::Config::default()
+            };
+
+            merge_dotrc(partial_config, dotrc_config).unwrap()
+        };
+
+        assert_eq!(config(None).platform_warning(), None);
+        assert_eq!(config(Some(util::platform())).platform_warning(), None);
+
+        let other = if util::platform() == Platform::Macos {
+            Platform::Linux
+        } else {
+            Platform::Macos
+        };
+        let warning = config(Some(other)).platform_warning().unwrap();
+        assert_eq!(
+            warning,
+            format!(
+                "using platform {} (from the dotrc), but this is {}",
+                other,
+                util::platform()
+            )
+        );
+    }
+
+    #[test]
+    fn gitignored_items() {
+        let (_dir, dotfiles_path) = dotfiles();
+        fs::write(dotfiles_path.join(".gitignore"), "*.key\n").unwrap();
+        fs::write(
+            dotfiles_path.join("secrets/.gitignore"),
+            "!private.key\n*.asc\n",
+        )
+        .unwrap();
+        fs::write(dotfiles_path.join("vimrc.swp"), "").unwrap();
+        fs::write(dotfiles_path.join(".dotignore"), "*.swp\n").unwrap();
+        let dotrc_config = dotrc::Config {
+            gitignore: Some(true),
+            ..dotrc::Config::default()
+        };
+
+        let mut config = merge_dotrc(partial_config(&dotfiles_path), dotrc_config).unwrap();
+        config.expand_globs().unwrap();
+        let mut sources: Vec<_> = resolver::get_items(&config)
+            .unwrap()
+            .into_iter()
+            .map(|item| item.source)
+            .collect();
+        sources.sort_by(|x, y| x.as_path().cmp(y.as_path()));
+
+        let expected = vec![
+            AbsolutePath::from(dotfiles_path.join("secrets/private.key")),
+            AbsolutePath::from(dotfiles_path.join("vimrc")),
+        ];
+        assert_eq!(sources, expected);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn symlinked_folders() {
+        let (_dir, dotfiles_path) = dotfiles();
+        std::os::unix::fs::symlink(dotfiles_path.join("secrets"), dotfiles_path.join("keys"))
+            .unwrap();
+
+        // Excludes and item discovery agree on whether `keys` is searched
+        let sources = |follow_source_links| {
+            let dotrc_config = dotrc::Config {
+                excludes: Some(vec![String::from("keys/*.key")]),
+                follow_source_links: Some(follow_source_links),
+                ..dotrc::Config::default()
+            };
+            let mut config = merge_dotrc(partial_config(&dotfiles_path), dotrc_config).unwrap();
+            config.expand_globs().unwrap();
+
+            let mut sources: Vec<_> = resolver::get_items(&config)
+                .unwrap()
+                .into_iter()
+                .map(|item| item.source.strip_prefix(&dotfiles_path).unwrap().to_owned())
+                .collect();
+            sources.sort();
+
+            (sources, config.dead_excludes)
+        };
+
+        assert_eq!(
+            sources(false),
+            (
+                vec![
+                    PathBuf::from("secrets/private.key"),
+                    PathBuf::from("secrets/public.asc"),
+                    PathBuf::from("vimrc"),
+                ],
+                vec![PathBuf::from("keys/*.key")]
+            )
+        );
+        assert_eq!(
+            sources(true),
+            (
+                vec![
+                    PathBuf::from("keys/public.asc"),
+                    PathBuf::from("secrets/private.key"),
+                    PathBuf::from("secrets/public.asc"),
+                    PathBuf::from("vimrc"),
+                ],
+                vec![]
+            )
+        );
+    }
+
+    #[test]
+    fn inactive_exclude_groups() {
+        let (_dir, dotfiles_path) = dotfiles();
+        let partial_config = partial_config(&dotfiles_path);
+        let dotrc_config = dotrc::Config {
+            platform_excludes: Some(vec![
+                (String::from("linux"), vec![String::from("vimrc")]),
+                (
+                    String::from("macos"),
+                    vec![String::from("secrets/private.key")],
+                ),
+            ]),
+            tag_excludes: Some(vec![
+                (
+                    String::from("work"),
+                    vec![String::from("secrets/public.asc")],
+                ),
+                (String::from("home"), vec![String::from("secrets/*")]),
+            ]),
+            ..dotrc::Config::default()
+        };
+
+        let mut config = merge_dotrc(partial_config, dotrc_config).unwrap();
+        config.expand_globs().unwrap();
+        let mut excluded = config.excludes;
+        excluded.sort_by(|x, y| x.as_path().cmp(y.as_path()));
+
+        let expected = vec![
+            AbsolutePath::from(dotfiles_path.join("secrets/public.asc")),
+            AbsolutePath::from(dotfiles_path.join("vimrc")),
+        ];
+        assert_eq!(excluded, expected);
+        assert_eq!(config.dead_excludes, Vec::<PathBuf>::new());
+    }
+
+    #[test]
+    fn negated_exclude() {
+        let (_dir, dotfiles_path) = dotfiles();
+        let excludes = vec![
+            PathBuf::from("secrets/*"),
+            PathBuf::from("!secrets/public.asc"),
+        ];
+
+        let (excluded, dead) =
+            expand_excludes(&excludes, slice::from_ref(&dotfiles_path), false, false).unwrap();
+
+        let expected = vec![AbsolutePath::from(
+            dotfiles_path.join("secrets/private.key"),
+        )];
+        assert_eq!(excluded, expected);
+        assert_eq!(dead, Vec::<PathBuf>::new());
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn non_unicode_negated_exclude() {
+        use std::{
+            ffi::{OsStr, OsString},
+            os::unix::ffi::OsStrExt,
+        };
+
+        let (_dir, dotfiles_path) = dotfiles();
+        // "secrets/été" in Latin-1
+        let name = OsStr::from_bytes(b"secrets/\xe9t\xe9");
+        fs::write(dotfiles_path.join(name), "").unwrap();
+        let mut negated = OsString::from("!");
+        negated.push(name);
+        let excludes = vec![PathBuf::from("secrets/*"), PathBuf::from(negated)];
+
+        let (mut excluded, dead) =
+            expand_excludes(&excludes, slice::from_ref(&dotfiles_path), false, false).unwrap();
+        excluded.sort_by(|x, y| x.as_path().cmp(y.as_path()));
+
+        let expected = vec![
+            AbsolutePath::from(dotfiles_path.join("secrets/private.key")),
+            AbsolutePath::from(dotfiles_path.join("secrets/public.asc")),
+        ];
+        assert_eq!(excluded, expected);
+        assert_eq!(dead, Vec::<PathBuf>::new());
+    }
+
+    #[test]
+    fn overlapping_excludes() {
+        let (_dir, dotfiles_path) = dotfiles();
+        let excludes = vec![PathBuf::from("secrets/*"), PathBuf::from("**/*.asc")];
+
+        let (mut excluded, dead) =
+            expand_excludes(&excludes, slice::from_ref(&dotfiles_path), false, false).unwrap();
+        excluded.sort_by(|x, y| x.as_path().cmp(y.as_path()));
+
+        let expected = vec![
+            AbsolutePath::from(dotfiles_path.join("secrets/private.key")),
+            AbsolutePath::from(dotfiles_path.join("secrets/public.asc")),
+        ];
+        assert_eq!(excluded, expected);
+        assert_eq!(dead, Vec::<PathBuf>::new());
+    }
+
+    #[test]
+    fn negated_exclude_overridden() {
+        let (_dir, dotfiles_path) = dotfiles();
+        let excludes = vec![
+            PathBuf::from("!secrets/public.asc"),
+            PathBuf::from("secrets/*"),
+        ];
+
+        let (mut excluded, _) =
+            expand_excludes(&excludes, slice::from_ref(&dotfiles_path), false, false).unwrap();
+        excluded.sort_by(|x, y| x.as_path().cmp(y.as_path()));
+
+        let expected = vec![
+            AbsolutePath::from(dotfiles_path.join("secrets/private.key")),
+            AbsolutePath::from(dotfiles_path.join("secrets/public.asc")),
+        ];
+        assert_eq!(excluded, expected);
+    }
+
+    #[test]
+    fn dead_negated_exclude() {
+        let (_dir, dotfiles_path) = dotfiles();
+        let excludes = vec![
+            PathBuf::from("secrets/*"),
+            PathBuf::from("!secrets/missing"),
+        ];
+
+        let (excluded, dead) = expand_excludes(&excludes, &[dotfiles_path], false, false).unwrap();
+
+        assert_eq!(excluded.len(), 2);
+        assert_eq!(dead, vec![PathBuf::from("!secrets/missing")]);
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn exclude_symlink_loop() {
+        let (dir, dotfiles_path) = dotfiles();
+        std::os::unix::fs::symlink(dir.path(), dir.path().join("secrets/loop")).unwrap();
+        let excludes = vec![PathBuf::from("**/vimrc")];
+
+        let (excluded, _) =
+            expand_excludes(&excludes, slice::from_ref(&dotfiles_path), false, false).unwrap();
+
+        let expected = vec![AbsolutePath::from(dotfiles_path.join("vimrc"))];
+        assert_eq!(excluded, expected);
+    }
+
+    #[test]
+    fn glob_set_expansions() {
+        let (_dir, dotfiles_path) = dotfiles();
+        let globs = [
+            Path::new("secrets/*"),
+            Path::new("**/*.asc"),
+            Path::new("[unclosed"),
+            Path::new("missing"),
+        ];
+
+        let expansions: Vec<_> = expand_glob_set(&globs, &dotfiles_path, false)
+            .unwrap()
+            .into_iter()
+            .map(|expansion| {
+                let mut paths = expansion.into_paths();
+                paths.sort();
+                paths
+            })
+            .collect();
+
+        // Each glob gets its own matches, even when they overlap
+        let expected = vec![
+            vec![
+                PathBuf::from("secrets/private.key"),
+                PathBuf::from("secrets/public.asc"),
+            ],
+            vec![PathBuf::from("secrets/public.asc")],
+            vec![PathBuf::from("[unclosed")],
+            vec![],
+        ];
+        assert_eq!(expansions, expected);
+    }
+}
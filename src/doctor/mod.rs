@@ -0,0 +1,144 @@
+use crate::{
+    common::{util, AbsolutePath, Platform},
+    config::Config,
+    resolver::{self, Prefixes},
+};
+use derive_more::From;
+use failure::Fail;
+use gethostname::gethostname;
+use itertools::Itertools;
+use std::{
+    env,
+    fmt::{self, Display},
+    path::PathBuf,
+};
+
+/// A configured value alongside the one dotman would detect on its own
+#[derive(Debug)]
+struct Detected<T> {
+    configured: T,
+    detected: Option<T>,
+}
+
+impl<T: Display + PartialEq> Display for Detected<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.detected {
+            Some(detected) if *detected == self.configured => write!(f, "{}", self.configured),
+            Some(detected) => write!(f, "{} (detected {})", self.configured, detected),
+            None => write!(f, "{} (couldn't detect)", self.configured),
+        }
+    }
+}
+
+/// A diagnostic report explaining what dotman will (and won't) link, and why
+#[derive(Debug)]
+pub struct Report {
+    /// Each dotfiles folder, along with whether it exists
+    dotfiles_paths: Vec<(AbsolutePath, bool)>,
+    target: AbsolutePath,
+    hostname: Detected<String>,
+    platform: Detected<Platform>,
+    arch: Detected<String>,
+    tags: Vec<String>,
+    /// Each prefixed directory found, along with whether it's active
+    prefixed_dirs: Vec<(AbsolutePath, bool)>,
+    /// Exclude globs which don't match anything
+    dead_excludes: Vec<PathBuf>,
+}
+
+impl Report {
+    pub fn get(config: &Config) -> Result<Self, Error> {
+        let dotfiles_paths = config
+            .dotfiles_paths
+            .iter()
+            .map(|path| (path.clone(), path.is_dir()))
+            .collect();
+
+        let hostname = Detected {
+            configured: config.hostnames.join(", "),
+            detected: gethostname().to_str().map(String::from),
+        };
+        let platform = Detected {
+            configured: config.platform,
+            detected: Some(util::platform()),
+        };
+        let arch = Detected {
+            configured: config.arch.clone(),
+            detected: Some(String::from(env::consts::ARCH)),
+        };
+
+        let prefixes = Prefixes::new(config);
+        let mut prefixed_dirs = vec![];
+        for dotfiles_path in config.dotfiles_paths.iter().filter(|path| path.is_dir()) {
+            prefixed_dirs.extend(resolver::prefixed_dirs(
+                dotfiles_path,
+                &prefixes,
+                &config.excludes,
+            )?);
+        }
+
+        Ok(Report {
+            dotfiles_paths,
+            target: config.target.clone(),
+            hostname,
+            platform,
+            arch,
+            tags: config.tags.clone(),
+            prefixed_dirs,
+            dead_excludes: config.dead_excludes.clone(),
+        })
+    }
+}
+
+impl Display for Report {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "Dotfiles folders:")?;
+        for (path, exists) in &self.dotfiles_paths {
+            let missing = if *exists { "" } else { " (missing)" };
+            writeln!(f, "    {}{}", path, missing)?;
+        }
+
+        writeln!(f)?;
+        writeln!(f, "Target: {}", self.target)?;
+        writeln!(f, "Hostname: {}", self.hostname)?;
+        writeln!(f, "Platform: {}", self.platform)?;
+        writeln!(f, "Arch: {}", self.arch)?;
+        if self.tags.is_empty() {
+            writeln!(f, "Tags: (none)")?;
+        } else {
+            writeln!(f, "Tags: {}", self.tags.iter().join(", "))?;
+        }
+
+        writeln!(f)?;
+        let active = self
+            .prefixed_dirs
+            .iter()
+            .filter(|(_, active)| *active)
+            .count();
+        write!(
+            f,
+            "Prefixed folders ({} of {} active):",
+            active,
+            self.prefixed_dirs.len()
+        )?;
+        for (path, active) in &self.prefixed_dirs {
+            let state = if *active { "active" } else { "inactive" };
+            write!(f, "\n    {:8}  {}", state, path)?;
+        }
+
+        if !self.dead_excludes.is_empty() {
+            write!(f, "\n\nExcludes matching nothing:")?;
+            for exclude in &self.dead_excludes {
+                write!(f, "\n    {}", exclude.display())?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[derive(Debug, From, Fail)]
+pub enum Error {
+    #[fail(display = "error searching dotfiles folders ({})", _0)]
+    ResolverError(#[fail(cause)] resolver::Error),
+}
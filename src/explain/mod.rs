@@ -0,0 +1,166 @@
+use crate::{
+    common::AbsolutePath,
+    config::Config,
+    hook,
+    resolver::{self, Step, Trace},
+};
+use derive_more::From;
+use failure::Fail;
+use serde::Serialize;
+use std::fmt::{self, Display};
+
+/// Why each dotfile which could be linked to a path (or the dotfile at that
+/// path) is or isn't linked
+#[derive(Debug)]
+pub struct Report {
+    path: AbsolutePath,
+    traces: Vec<Trace>,
+}
+
+impl Report {
+    /// Explains `path`, which is either a dotfile within one of the dotfiles
+    /// folders or a destination. For a destination, every dotfile which would
+    /// be linked there if it were active is explained.
+    ///
+    /// Requirements' commands are run in the system shell (see
+    /// `hook::succeeds`).
+    pub fn get(config: &Config, path: &AbsolutePath) -> Result<Self, Error> {
+        let mut succeeds = hook::succeeds;
+        let traces = match resolver::trace(config, path, &mut succeeds)? {
+            Some(trace) => vec![trace],
+            None if config
+                .dotfiles_paths
+                .iter()
+                .any(|dotfiles_path| path.starts_with(dotfiles_path)) =>
+            {
+                return Err(NotADotfile(path.clone()))
+            },
+            None => {
+                let mut traces = vec![];
+                for source in resolver::sources_linked_to(config, path)? {
+                    traces.extend(resolver::trace(config, &source, &mut succeeds)?);
+                }
+
+                traces
+            },
+        };
+
+        Ok(Report {
+            path: path.clone(),
+            traces,
+        })
+    }
+}
+
+impl Display for Report {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.traces.is_empty() {
+            return write!(f, "No dotfile would be linked to {}", self.path);
+        }
+
+        for (i, trace) in self.traces.iter().enumerate() {
+            if i > 0 {
+                write!(f, "\n\n")?;
+            }
+            let linked = if trace.is_linked() { "is" } else { "isn't" };
+            write!(f, "{} {} linked:", trace.source, linked)?;
+            for step in &trace.steps {
+                write!(f, "\n    {}", step)?;
+            }
+        }
+
+        if self.traces.iter().filter(|trace| trace.is_linked()).count() > 1 {
+            write!(
+                f,
+                "\n\nThese conflict, since more than one of them is linked to {}",
+                self.path
+            )?;
+        }
+
+        Ok(())
+    }
+}
+
+/// A serializable view of a `Report`, for machine-readable output
+#[derive(Debug, Serialize)]
+pub struct SerializableReport {
+    pub path: String,
+    pub dotfiles: Vec<SerializableTrace>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct SerializableTrace {
+    pub source: String,
+    pub dest: Option<String>,
+    pub linked: bool,
+    pub steps: Vec<SerializableStep>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct SerializableStep {
+    /// What kind of check this is, as in `prefixed` or `requirement`
+    pub check: &'static str,
+    /// The path checked, if the check is of a path
+    pub path: Option<String>,
+    /// Whether this check keeps the dotfile from being linked
+    pub drops: bool,
+    pub description: String,
+}
+
+impl SerializableReport {
+    /// Creates a view of `report`. Paths within the home directory are
+    /// abbreviated with a tilde.
+    pub fn new(report: &Report) -> Self {
+        let dotfiles = report
+            .traces
+            .iter()
+            .map(|trace| SerializableTrace {
+                source: trace.source.to_string(),
+                dest: trace.dest().map(AbsolutePath::to_string),
+                linked: trace.is_linked(),
+                steps: trace.steps.iter().map(SerializableStep::new).collect(),
+            })
+            .collect();
+
+        SerializableReport {
+            path: report.path.to_string(),
+            dotfiles,
+        }
+    }
+}
+
+impl SerializableStep {
+    fn new(step: &Step) -> Self {
+        let (check, path) = match step {
+            Step::Hidden(path) => ("hidden", Some(path)),
+            Step::Excluded(path) => ("excluded", Some(path)),
+            Step::Symlink(path) => ("symlink", Some(path)),
+            Step::Prefixed { path, .. } => ("prefixed", Some(path)),
+            Step::Marked(path) => ("marked", Some(path)),
+            Step::NotIncluded => ("not-included", None),
+            Step::Dest { dest, .. } => ("dest", Some(dest)),
+            Step::DestExcluded(dest) => ("dest-excluded", Some(dest)),
+            Step::Requirement { .. } => ("requirement", None),
+        };
+
+        SerializableStep {
+            check,
+            path: path.map(AbsolutePath::to_string),
+            drops: step.drops(),
+            description: step.to_string(),
+        }
+    }
+}
+
+#[derive(Debug, From, Fail)]
+pub enum Error {
+    #[fail(
+        display = "{} isn't a dotfile (folders are only linked if they contain a .dotman-dir file)",
+        _0
+    )]
+    NotADotfile(AbsolutePath),
+
+    #[fail(display = "error searching dotfiles folders ({})", _0)]
+    ResolverError(#[fail(cause)] resolver::Error),
+}
+use Error::*;
@@ -0,0 +1,269 @@
+use crate::{
+    common::{
+        util::{self, Verbosity},
+        AbsolutePath,
+    },
+    verbose_println,
+};
+use derive_more::From;
+use failure::Fail;
+use std::{
+    ffi::OsString,
+    fs,
+    hash::Hasher,
+    io,
+    path::{Path, PathBuf},
+    process::{self, Command, ExitStatus, Stdio},
+};
+
+/// Creates a `Command` which runs git with `args` in `repo`
+fn git(repo: &AbsolutePath, args: &[&str]) -> Command {
+    let mut res = Command::new("git");
+    res.arg("-C").arg(repo.as_path()).args(args);
+
+    res
+}
+
+/// Runs git with `args` in `repo`, failing if it does.
+///
+/// git's own output is silenced if the quiet flag has been set.
+fn run(repo: &AbsolutePath, args: &[&str]) -> Result<(), Error> {
+    let mut command = git(repo, args);
+    if util::get_verbosity() == Verbosity::Quiet {
+        command.arg("--quiet");
+    }
+
+    let status = command.status()?;
+    if !status.success() {
+        return Err(GitFailed {
+            command: args.join(" "),
+            status,
+        });
+    }
+
+    Ok(())
+}
+
+/// Checks that `repo` is (within) a git repository
+fn check_repo(repo: &AbsolutePath) -> Result<(), Error> {
+    let is_repo = git(repo, &["rev-parse", "--is-inside-work-tree"])
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .status()?
+        .success();
+    if !is_repo {
+        return Err(NotARepo(repo.clone()));
+    }
+
+    Ok(())
+}
+
+/// Pulls the latest changes into `repo`
+pub fn pull(repo: &AbsolutePath) -> Result<(), Error> {
+    check_repo(repo)?;
+
+    verbose_println!("Pulling {}", repo);
+    run(repo, &["pull"])
+}
+
+/// Checks whether `s` looks like the URL of a remote repository, either with a
+/// scheme (as in `https://github.com/me/dotfiles`) or in scp-like form (as in
+/// `git@github.com:me/dotfiles`)
+pub fn is_url(s: &str) -> bool {
+    if let Some((scheme, _)) = s.split_once("://") {
+        return !scheme.is_empty()
+            && scheme
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || "+-.".contains(c));
+    }
+
+    match s.split_once(':') {
+        Some((user_host, _)) => user_host.contains('@') && !user_host.contains('/'),
+        None => false,
+    }
+}
+
+/// The folder (within `cache_dir`) which `url` is cloned into. It's named after
+/// the repository, followed by a hash of the whole URL to tell apart
+/// repositories with the same name.
+fn clone_path(url: &str, cache_dir: &Path) -> AbsolutePath {
+    // The hash needs to stay the same between releases, or existing clones would
+    // be lost track of
+    let mut hasher = util::StableHasher::default();
+    hasher.write(url.as_bytes());
+    let hash = hasher.finish();
+    let name = url
+        .trim_end_matches('/')
+        .trim_end_matches(".git")
+        .rsplit(['/', ':'])
+        .next()
+        .unwrap_or_default();
+
+    AbsolutePath::from(cache_dir.join(format!("{}-{:016x}", name, hash)))
+}
+
+/// Clones the repository at `url` into `cache_dir` (see `clone_path`) unless
+/// it's already been cloned, giving the clone's path. Existing clones aren't
+/// pulled (see `pull`).
+///
+/// The repository is cloned alongside the clone's path, then moved into place,
+/// so that concurrent runs never see (or clone into) a partial clone.
+pub fn clone_if_missing(url: &str, cache_dir: &Path) -> Result<AbsolutePath, Error> {
+    let path = clone_path(url, cache_dir);
+    if path.exists() {
+        return Ok(path);
+    }
+
+    verbose_println!("Cloning {} into {}", url, path);
+    let partial = {
+        let mut partial = OsString::from(path.as_os_str());
+        partial.push(format!(".partial-{}", process::id()));
+        PathBuf::from(partial)
+    };
+    let mut command = Command::new("git");
+    command.arg("clone");
+    if util::get_verbosity() == Verbosity::Quiet {
+        command.arg("--quiet");
+    }
+    let status = command.arg(url).arg(&partial).status()?;
+    if !status.success() {
+        let _ = fs::remove_dir_all(&partial);
+        return Err(GitFailed {
+            command: String::from("clone"),
+            status,
+        });
+    }
+
+    if let Err(err) = fs::rename(&partial, &path) {
+        let _ = fs::remove_dir_all(&partial);
+        // Another run may have finished cloning first
+        if !path.exists() {
+            return Err(err.into());
+        }
+    }
+
+    Ok(path)
+}
+
+/// Commits any local changes in `repo` with `message`, then pushes
+pub fn commit_and_push(repo: &AbsolutePath, message: &str) -> Result<(), Error> {
+    check_repo(repo)?;
+
+    let changes = git(repo, &["status", "--porcelain"]).output()?;
+    if !changes.status.success() {
+        return Err(GitFailed {
+            command: String::from("status --porcelain"),
+            status: changes.status,
+        });
+    }
+    if !changes.stdout.is_empty() {
+        verbose_println!("Committing changes in {}", repo);
+        run(repo, &["add", "--all"])?;
+        run(repo, &["commit", "--message", message])?;
+    }
+
+    verbose_println!("Pushing {}", repo);
+    run(repo, &["push"])
+}
+
+#[derive(Debug, From, Fail)]
+pub enum Error {
+    #[fail(display = "{} isn't a git repository", _0)]
+    NotARepo(AbsolutePath),
+
+    #[fail(display = "\"git {}\" failed ({})", command, status)]
+    GitFailed { command: String, status: ExitStatus },
+
+    #[fail(display = "error running git ({})", _0)]
+    IoError(#[fail(cause)] io::Error),
+}
+use Error::*;
+
+#[cfg(test)]
+mod tests {
+    use super::{clone_if_missing, clone_path, is_url, pull, Error};
+    use crate::common::AbsolutePath;
+    use std::{fs, process::Command};
+    use tempfile::TempDir;
+
+    #[test]
+    fn urls() {
+        assert!(is_url("https://github.com/me/dotfiles"));
+        assert!(is_url("ssh://git@example.com/me/dotfiles.git"));
+        assert!(is_url("git@github.com:me/dotfiles"));
+        assert!(!is_url("~/.dotfiles"));
+        assert!(!is_url("/home/me/dotfiles"));
+        assert!(!is_url(r"C:\Users\me\dotfiles"));
+        assert!(!is_url("dotfiles/me@work:old"));
+    }
+
+    #[test]
+    fn clone_paths() {
+        let cache = TempDir::new().unwrap();
+        let path = |url| clone_path(url, cache.path());
+
+        let name = |url| path(url).file_name().unwrap().to_str().unwrap().to_owned();
+        assert!(name("https://github.com/me/dotfiles.git").starts_with("dotfiles-"));
+        assert!(name("git@github.com:me/dots/").starts_with("dots-"));
+
+        assert_eq!(
+            path("https://github.com/me/dotfiles"),
+            path("https://github.com/me/dotfiles")
+        );
+        assert_ne!(
+            path("https://github.com/me/dotfiles"),
+            path("https://github.com/you/dotfiles")
+        );
+    }
+
+    #[test]
+    fn clone_then_pull() {
+        let remote = TempDir::new().unwrap();
+        let cache = TempDir::new().unwrap();
+        let git = |args: &[&str]| {
+            let status = Command::new("git")
+                .arg("-C")
+                .arg(remote.path())
+                .args([
+                    "-c",
+                    "user.name=dotman",
+                    "-c",
+                    "user.email=dotman@example.com",
+                ])
+                .args(args)
+                .status()
+                .unwrap();
+            assert!(status.success());
+        };
+        git(&["init", "--quiet"]);
+        fs::write(remote.path().join("vimrc"), "").unwrap();
+        git(&["add", "vimrc"]);
+        git(&["commit", "--quiet", "--message", "Add vimrc"]);
+
+        let url = format!("file://{}", remote.path().display());
+        let clone = clone_if_missing(&url, cache.path()).unwrap();
+        assert!(clone.join("vimrc").is_file());
+        // Nothing is left over from cloning
+        assert_eq!(fs::read_dir(cache.path()).unwrap().count(), 1);
+
+        fs::write(remote.path().join("zshrc"), "").unwrap();
+        git(&["add", "zshrc"]);
+        git(&["commit", "--quiet", "--message", "Add zshrc"]);
+        // The second time, the existing clone is reused as it is
+        assert_eq!(clone_if_missing(&url, cache.path()).unwrap(), clone);
+        assert!(!clone.join("zshrc").exists());
+        pull(&clone).unwrap();
+        assert!(clone.join("zshrc").is_file());
+    }
+
+    #[test]
+    fn pull_not_a_repo() {
+        let dir = TempDir::new().unwrap();
+        let dir = AbsolutePath::from(dir.path());
+
+        match pull(&dir) {
+            Err(Error::NotARepo(path)) => assert_eq!(path, dir),
+            res => panic!("expected NotARepo, got {:?}", res),
+        }
+    }
+}
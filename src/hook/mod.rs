@@ -0,0 +1,67 @@
+use crate::{info_println, verbose_println};
+use derive_more::From;
+use failure::Fail;
+use std::{
+    io,
+    path::Path,
+    process::{Command, ExitStatus, Stdio},
+};
+
+/// Creates a `Command` which runs `command` in the system shell
+fn shell_command(command: &str) -> Command {
+    #[cfg(unix)]
+    let (shell, flag) = ("sh", "-c");
+    #[cfg(windows)]
+    let (shell, flag) = ("cmd", "/C");
+
+    let mut res = Command::new(shell);
+    res.arg(flag).arg(command);
+
+    res
+}
+
+/// Runs each of `hooks` (shell commands) in order from `dir`, stopping at the
+/// first one which fails.
+///
+/// In a dry run, the hooks are printed rather than run.
+pub fn run_hooks(hooks: &[String], dir: &Path, dry_run: bool) -> Result<(), Error> {
+    for hook in hooks {
+        if dry_run {
+            info_println!("Would run {}", hook);
+            continue;
+        }
+
+        verbose_println!("Running {}", hook);
+        let status = shell_command(hook).current_dir(dir).status()?;
+        if !status.success() {
+            return Err(HookFailed {
+                hook: hook.clone(),
+                status,
+            });
+        }
+    }
+
+    Ok(())
+}
+
+/// Runs `command` in the system shell without any output, returning whether
+/// it succeeded. A command which can't even be started counts as failing.
+pub fn succeeds(command: &str) -> bool {
+    verbose_println!("Checking {}", command);
+    shell_command(command)
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .status()
+        .is_ok_and(|status| status.success())
+}
+
+#[derive(Debug, From, Fail)]
+pub enum Error {
+    #[fail(display = "hook \"{}\" failed ({})", hook, status)]
+    HookFailed { hook: String, status: ExitStatus },
+
+    #[fail(display = "error running hook ({})", _0)]
+    IoError(#[fail(cause)] io::Error),
+}
+use Error::*;
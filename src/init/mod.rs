@@ -0,0 +1,103 @@
+use crate::{common::AbsolutePath, config, info_println};
+use derive_more::From;
+use failure::Fail;
+use std::{env, fs, io, path::Path};
+
+/// The name of the starter dotrc written into new dotfiles folders. Like any
+/// other dotfile, it's linked into the home directory (as `~/.dotrc.yml`),
+/// which is where dotman looks for it.
+const DOTRC_NAME: &str = "dotrc.yml";
+
+/// Creates a dotfiles folder at `path`, containing a starter dotrc.
+///
+/// An existing folder is only used if it's empty or `force` is set. Even then,
+/// an existing dotrc is left alone.
+pub fn init(path: &Path, force: bool) -> Result<(), Error> {
+    let path = AbsolutePath::from(env::current_dir()?.join(path));
+
+    let is_empty = match path.read_dir() {
+        Ok(mut entries) => entries.next().is_none(),
+        Err(ref err) if err.kind() == io::ErrorKind::NotFound => true,
+        Err(err) => return Err(IoError(err)),
+    };
+    if !is_empty && !force {
+        return Err(NotEmpty(path));
+    }
+
+    fs::create_dir_all(&path)?;
+    let dotrc = AbsolutePath::from(path.join(DOTRC_NAME));
+    if dotrc.exists() {
+        info_println!("Leaving the existing {} alone", dotrc);
+    } else {
+        fs::write(&dotrc, config::STARTER_DOTRC)?;
+    }
+
+    info_println!("Set up a dotfiles folder in {}. Next:", path);
+    info_println!(
+        "    1. Move your dotfiles into it, without their leading dots (e.g. ~/.vimrc becomes \
+         {}/vimrc)",
+        path
+    );
+    info_println!("    2. Uncomment any options you need in {}", dotrc);
+    info_println!("    3. Run `dot link`");
+
+    Ok(())
+}
+
+#[derive(Debug, From, Fail)]
+pub enum Error {
+    #[fail(
+        display = "{} already exists and isn't empty. Pass --force to use it anyway.",
+        _0
+    )]
+    NotEmpty(AbsolutePath),
+
+    #[fail(display = "error creating dotfiles folder ({})", _0)]
+    IoError(#[fail(cause)] io::Error),
+}
+use Error::*;
+
+#[cfg(test)]
+mod tests {
+    use super::{init, Error, DOTRC_NAME};
+    use crate::config;
+    use std::fs;
+    use tempfile::TempDir;
+
+    #[test]
+    fn init_new_dir() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join(".dotfiles");
+
+        init(&path, false).unwrap();
+
+        let dotrc = fs::read_to_string(path.join(DOTRC_NAME)).unwrap();
+        assert_eq!(dotrc, config::STARTER_DOTRC);
+    }
+
+    #[test]
+    fn init_non_empty_dir() {
+        let dir = TempDir::new().unwrap();
+        fs::write(dir.path().join("vimrc"), "").unwrap();
+
+        match init(dir.path(), false) {
+            Err(Error::NotEmpty(_)) => (),
+            res => panic!("expected NotEmpty, got {:?}", res),
+        }
+        assert!(!dir.path().join(DOTRC_NAME).exists());
+
+        init(dir.path(), true).unwrap();
+        assert!(dir.path().join(DOTRC_NAME).exists());
+    }
+
+    #[test]
+    fn init_keeps_existing_dotrc() {
+        let dir = TempDir::new().unwrap();
+        fs::write(dir.path().join(DOTRC_NAME), "tags: [work]").unwrap();
+
+        init(dir.path(), true).unwrap();
+
+        let dotrc = fs::read_to_string(dir.path().join(DOTRC_NAME)).unwrap();
+        assert_eq!(dotrc, "tags: [work]");
+    }
+}
@@ -8,9 +8,23 @@
     unreachable_pub
 )]
 #![deny(future_incompatible, unsafe_code)]
+// `failure_derive` generates its trait impls inside an anonymous `const`, which
+// newer compilers flag.
+#![allow(non_local_definitions)]
 
 #[macro_use]
 pub mod common;
 pub mod config;
+pub mod doctor;
+pub mod explain;
+pub mod git;
+pub mod hook;
+pub mod init;
 pub mod linker;
+pub mod lock;
 pub mod resolver;
+pub mod secret;
+pub mod status;
+pub mod tags;
+pub mod template;
+pub mod verify;
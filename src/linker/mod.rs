@@ -1,72 +1,1527 @@
 use crate::{
-    common::{util, AbsolutePath, FormattedItem, FormattedItems, YN},
-    verbose_println,
+    common::{
+        style,
+        util::{self, Verbosity},
+        AbsolutePath, FormattedItem, FormattedItems, Item, YNAll, YN,
+    },
+    info_println, secret, verbose_println,
 };
 use derive_more::From;
 use failure::Fail;
-use std::{fs, io, path::Path};
+use filetime::FileTime;
+use indicatif::{ProgressBar, ProgressDrawTarget, ProgressStyle};
+use rayon::prelude::*;
+use similar::TextDiff;
+use std::{
+    collections::HashSet,
+    ffi::OsString,
+    fmt::{self, Display},
+    fs,
+    io::{self, IsTerminal},
+    path::{Path, PathBuf},
+    str::{self, FromStr},
+};
+use walkdir::WalkDir;
+
+const BACKUP_EXTENSION: &str = "dotman-bak";
+
+/// In atomic mode, overwritten files are moved aside with this extension until
+/// linking finishes, so they can be restored if it fails.
+const STASH_EXTENSION: &str = "dotman-tmp";
+
+/// The most lines of a diff shown before an overwrite prompt
+const MAX_DIFF_LINES: usize = 200;
 
 #[cfg(unix)]
-fn symlink(source: impl AsRef<Path>, dest: impl AsRef<Path>) -> io::Result<()> {
-    std::os::unix::fs::symlink(source, dest)
+fn symlink(source: impl AsRef<Path>, dest: impl AsRef<Path>) -> Result<(), Error> {
+    std::os::unix::fs::symlink(source, dest)?;
+
+    Ok(())
 }
 
-fn link_item(formatted_item: &FormattedItem, dry_run: bool) -> Result<(), Error> {
-    let (source, dest) = (&formatted_item.item().source, &formatted_item.item().dest);
+#[cfg(windows)]
+fn symlink(source: impl AsRef<Path>, dest: impl AsRef<Path>) -> Result<(), Error> {
+    use std::os::windows::fs::{symlink_dir, symlink_file};
+
+    /// The OS error code Windows returns when the current user isn't allowed to
+    /// create symlinks
+    const ERROR_PRIVILEGE_NOT_HELD: i32 = 1314;
+
+    // Unlike on Unix, Windows distinguishes between symlinks to files and
+    // symlinks to directories
+    let source = source.as_ref();
+    let res = if source.is_dir() {
+        symlink_dir(source, dest)
+    } else {
+        symlink_file(source, dest)
+    };
+
+    res.map_err(|err| match err.raw_os_error() {
+        Some(ERROR_PRIVILEGE_NOT_HELD) => SymlinkPrivilege,
+        _ => IoError(err),
+    })
+}
+
+/// How a source gets placed at its destination
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Strategy {
+    /// Creates a symlink at the destination pointing to the source
+    #[default]
+    Symlink,
+
+    /// Copies the source to the destination, preserving permissions
+    Copy,
+}
+
+/// What to do when something else is already at a dotfile's destination
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ConflictPolicy {
+    /// Asks whether to overwrite it
+    #[default]
+    Prompt,
+
+    /// Leaves it alone, without linking the dotfile
+    Skip,
+
+    /// Overwrites it without asking
+    Overwrite,
+
+    /// Backs it up (see `backup_path`), then overwrites it without asking
+    Backup,
+}
+
+#[derive(Debug, Fail)]
+#[fail(
+    display = "unsupported conflict policy \"{}\" (expected prompt, skip, overwrite, or backup)",
+    input
+)]
+pub struct ConflictPolicyParseError {
+    input: String,
+}
+
+impl FromStr for ConflictPolicy {
+    type Err = ConflictPolicyParseError;
+
+    fn from_str(s: &str) -> Result<ConflictPolicy, Self::Err> {
+        match s.trim().to_lowercase().as_str() {
+            "prompt" => Ok(ConflictPolicy::Prompt),
+            "skip" => Ok(ConflictPolicy::Skip),
+            "overwrite" => Ok(ConflictPolicy::Overwrite),
+            "backup" => Ok(ConflictPolicy::Backup),
+            s => Err(ConflictPolicyParseError {
+                input: s.to_owned(),
+            }),
+        }
+    }
+}
+
+/// Checks whether existing files should be backed up before they're replaced,
+/// either with `--backup` or the backup conflict policy
+fn backs_up(options: Options) -> bool {
+    options.backup || options.on_conflict == Some(ConflictPolicy::Backup)
+}
+
+/// Options controlling how `link_items` behaves
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Options {
+    /// Skips the actual linking step. Everything else (e.g. errors and
+    /// prompts) remains unchanged.
+    pub dry_run: bool,
+
+    pub strategy: Strategy,
+
+    /// Moves existing files aside (see `backup_path`) instead of deleting them
+    /// when overwriting.
+    pub backup: bool,
+
+    /// How to handle conflicting files, if given on the command line. `None`
+    /// prompts, as with `ConflictPolicy::Prompt`. Directories are never
+    /// overwritten, whatever the policy.
+    pub on_conflict: Option<ConflictPolicy>,
+
+    /// Creates symlinks with paths relative to the destination rather than
+    /// absolute paths, where possible.
+    pub relative: bool,
+
+    /// Links items whose destinations are free concurrently. Items which might
+    /// need a prompt are still handled serially afterwards.
+    pub parallel: bool,
+
+    /// Shows a diff from the existing file to the source before prompting to
+    /// overwrite it.
+    pub diff: bool,
+
+    /// Undoes every change made so far if linking fails partway through.
+    pub atomic: bool,
+
+    /// Shows a progress bar while linking (see `progress_bar`).
+    pub progress: bool,
+
+    /// Answers every prompt to overwrite a conflicting file with this input,
+    /// without showing the prompt (e.g. in tests).
+    pub answer: Option<&'static str>,
+}
+
+/// A change made to the filesystem while linking, recorded so that it can be
+/// undone
+#[derive(Debug)]
+enum Action {
+    /// Created a directory which didn't exist before
+    CreatedDir(PathBuf),
+
+    /// Put a symlink or copy at the path
+    Placed(PathBuf),
+
+    /// Moved an existing file at `dest` to `backup` for the user to keep
+    BackedUp { dest: PathBuf, backup: PathBuf },
+
+    /// Moved an existing file at `dest` to `stash`, to be deleted once linking
+    /// succeeds
+    Stashed { dest: PathBuf, stash: PathBuf },
+}
+
+impl Action {
+    fn undo(&self) -> io::Result<()> {
+        // Something already gone doesn't need undoing
+        let ignore_missing = |res: io::Result<()>| match res {
+            Err(ref err) if err.kind() == io::ErrorKind::NotFound => Ok(()),
+            res => res,
+        };
+
+        match self {
+            Action::CreatedDir(dir) => ignore_missing(fs::remove_dir(dir)),
+            Action::Placed(path) => ignore_missing(remove_path(path)),
+            Action::BackedUp {
+                dest,
+                backup: moved,
+            }
+            | Action::Stashed { dest, stash: moved } => fs::rename(moved, dest),
+        }
+    }
+}
+
+impl Display for Action {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let tilde = |path: &Path| util::home_to_tilde(path).display().to_string();
+        match self {
+            Action::CreatedDir(dir) => write!(f, "creating {}", tilde(dir)),
+            Action::Placed(path) => write!(f, "linking {}", tilde(path)),
+            Action::BackedUp {
+                dest,
+                backup: moved,
+            }
+            | Action::Stashed { dest, stash: moved } => {
+                write!(f, "moving {} to {}", tilde(dest), tilde(moved))
+            },
+        }
+    }
+}
+
+/// Undoes each of `actions`, most recent first.
+///
+/// Carries on past any failures so that as much as possible is restored,
+/// reporting each failure as it goes.
+fn rollback(actions: Vec<Action>) {
+    verbose_println!("Undoing {} changes", actions.len());
+    for action in actions.iter().rev() {
+        if let Err(err) = action.undo() {
+            eprintln!("Warning: couldn't undo {} ({})", action, err);
+        }
+    }
+}
+
+/// Finds a path not yet in use to back up `dest` to.
+///
+/// Tries `<dest>.dotman-bak` first, then `<dest>.dotman-bak.1`,
+/// `<dest>.dotman-bak.2`, and so on, so previous backups never get clobbered.
+fn backup_path(dest: &Path) -> PathBuf {
+    unused_path(dest, BACKUP_EXTENSION)
+}
+
+/// Finds a path not yet in use by appending `extension` to `dest`, followed by
+/// a number if needed (see `backup_path`).
+fn unused_path(dest: &Path, extension: &str) -> PathBuf {
+    let with_suffix = |suffix: &str| {
+        let mut path = OsString::from(dest.as_os_str());
+        path.push(suffix);
 
-    // Performs the actual linking after all validation
-    // is finished.
-    let link = |item: &FormattedItem| -> Result<(), Error> {
-        verbose_println!("Linking {}", item);
+        PathBuf::from(path)
+    };
+
+    let base = with_suffix(&format!(".{}", extension));
+    let mut path = base.clone();
+    let mut n = 0;
+    // `symlink_metadata` rather than `exists` so broken symlinks count as taken
+    while path.symlink_metadata().is_ok() {
+        n += 1;
+        path = with_suffix(&format!(".{}.{}", extension, n));
+    }
+
+    path
+}
+
+/// Checks whether `x` and `y` have the same contents. Directories are compared
+/// recursively.
+fn same_contents(x: impl AsRef<Path>, y: impl AsRef<Path>) -> io::Result<bool> {
+    let (x, y) = (x.as_ref(), y.as_ref());
+    match (x.is_dir(), y.is_dir()) {
+        (false, false) => {
+            if x.metadata()?.len() != y.metadata()?.len() {
+                return Ok(false);
+            }
+
+            Ok(fs::read(x)? == fs::read(y)?)
+        },
+        (true, true) => {
+            // Lists the paths within `dir`, relative to `dir`
+            let relative_entries = |dir: &Path| -> io::Result<Vec<PathBuf>> {
+                WalkDir::new(dir)
+                    .sort_by(|a, b| a.file_name().cmp(b.file_name()))
+                    .into_iter()
+                    .map(|entry| {
+                        Ok(entry?
+                            .path()
+                            .strip_prefix(dir)
+                            .expect("dir must be a prefix of entry")
+                            .to_path_buf())
+                    })
+                    .collect()
+            };
+
+            let entries = relative_entries(x)?;
+            if entries != relative_entries(y)? {
+                return Ok(false);
+            }
+
+            for entry in entries.iter().filter(|entry| !x.join(entry).is_dir()) {
+                if !same_contents(x.join(entry), y.join(entry))? {
+                    return Ok(false);
+                }
+            }
+
+            Ok(true)
+        },
+        _ => Ok(false),
+    }
+}
+
+/// Copies `source` to `dest`, recursing into `source` if it's a directory.
+///
+/// Permission bits and modification times are carried over too (see
+/// `copy_metadata`).
+fn copy(source: &Path, dest: &Path) -> io::Result<()> {
+    if !source.is_dir() {
+        fs::copy(source, dest)?;
+        return copy_metadata(source, dest);
+    }
+
+    // Directories get their metadata last, since filling them in bumps their
+    // mtime (and a read-only directory couldn't be filled in at all)
+    let mut dirs = vec![];
+    for entry in WalkDir::new(source) {
+        let entry = entry?;
+        let target = dest.join(
+            entry
+                .path()
+                .strip_prefix(source)
+                .expect("source must be a prefix of entry"),
+        );
 
-        if !dry_run {
-            fs::create_dir_all(dest.parent().unwrap_or(dest))?;
-            symlink(source, dest)?;
+        if entry.file_type().is_dir() {
+            fs::create_dir_all(&target)?;
+            dirs.push((entry.into_path(), target));
+        } else {
+            fs::copy(entry.path(), &target)?;
+            copy_metadata(entry.path(), &target)?;
         }
+    }
+
+    for (dir, target) in dirs.iter().rev() {
+        copy_metadata(dir, target)?;
+    }
+
+    Ok(())
+}
+
+/// Like `copy`, but refuses to replace anything already at `dest`. If copying
+/// fails partway through, whatever was copied so far is removed.
+fn copy_new(source: &Path, dest: &Path) -> io::Result<()> {
+    if dest.symlink_metadata().is_ok() {
+        return Err(io::Error::new(
+            io::ErrorKind::AlreadyExists,
+            format!("{} already exists", dest.display()),
+        ));
+    }
+
+    copy(source, dest).inspect_err(|_| {
+        if let Err(cleanup_err) = remove_path(dest) {
+            if cleanup_err.kind() != io::ErrorKind::NotFound {
+                eprintln!(
+                    "Warning: couldn't remove the partial copy at {} ({})",
+                    util::home_to_tilde(dest).display(),
+                    cleanup_err
+                );
+            }
+        }
+    })
+}
+
+/// Removes whatever is at `path`, recursing into directories
+fn remove_path(path: &Path) -> io::Result<()> {
+    match util::file_type(path)? {
+        util::FileType::Directory => fs::remove_dir_all(path),
+        util::FileType::File | util::FileType::Symlink => fs::remove_file(path),
+    }
+}
+
+/// Replicates `source`'s permission bits and modification time onto `dest`.
+///
+/// Some programs (like ssh) refuse to use config files with loose
+/// permissions, so copies need to match their sources exactly.
+fn copy_metadata(source: &Path, dest: &Path) -> io::Result<()> {
+    let metadata = fs::metadata(source)?;
+    copy_permissions(&metadata, dest)?;
+    filetime::set_file_mtime(dest, FileTime::from_last_modification_time(&metadata))
+}
 
-        Ok(())
+#[cfg(unix)]
+fn copy_permissions(metadata: &fs::Metadata, dest: &Path) -> io::Result<()> {
+    use std::os::unix::fs::PermissionsExt;
+
+    let mode = metadata.permissions().mode();
+    fs::set_permissions(dest, fs::Permissions::from_mode(mode))
+}
+
+#[cfg(windows)]
+fn copy_permissions(_metadata: &fs::Metadata, _dest: &Path) -> io::Result<()> {
+    // Windows has no permission bits to speak of
+    Ok(())
+}
+
+/// Gives each item's source the permission bits paired with it (see
+/// `config::Mode`), once it's linked. With `Strategy::Copy`, the copies get
+/// them too.
+///
+/// Only items which `link_items` placed (or found already in place; see
+/// `LinkSummary::is_placed`) should be given, so that nothing the user declined
+/// to overwrite is touched.
+///
+/// Does nothing on Windows, which has no permission bits to speak of.
+#[cfg(unix)]
+pub fn set_modes(items: &[(Item, u32)], strategy: Strategy, dry_run: bool) -> Result<(), Error> {
+    use std::os::unix::fs::PermissionsExt;
+
+    let set_mode = |path: &Path, mode: u32| -> io::Result<()> {
+        if fs::metadata(path)?.permissions().mode() & 0o7777 == mode {
+            return Ok(());
+        }
+
+        let would = if dry_run { "Would set" } else { "Setting" };
+        verbose_println!(
+            "{} the mode of {} to {:o}",
+            would,
+            util::home_to_tilde(path).display(),
+            mode
+        );
+        if dry_run {
+            return Ok(());
+        }
+
+        fs::set_permissions(path, fs::Permissions::from_mode(mode))
     };
 
-    if !dest.exists() {
-        link(formatted_item)?
+    for (item, mode) in items {
+        let copied = matches!(
+            util::file_type(&item.dest),
+            Ok(util::FileType::File) | Ok(util::FileType::Directory)
+        );
+        if strategy == Strategy::Copy && copied {
+            set_mode(&item.dest, *mode)?;
+        }
+        set_mode(&item.source, *mode)?;
+    }
+
+    Ok(())
+}
+
+#[cfg(windows)]
+pub fn set_modes(_items: &[(Item, u32)], _strategy: Strategy, _dry_run: bool) -> Result<(), Error> {
+    Ok(())
+}
+
+/// The OS error code for trying to rename a file onto a different filesystem
+#[cfg(unix)]
+const CROSS_DEVICE: i32 = 18; // EXDEV
+#[cfg(windows)]
+const CROSS_DEVICE: i32 = 17; // ERROR_NOT_SAME_DEVICE
+
+/// Moves `from` to `to`, which may be on a different filesystem (see
+/// `move_with`)
+fn move_path(from: &Path, to: &Path, copy_fallback: bool) -> Result<(), Error> {
+    move_with(from, to, copy_fallback, |from, to| fs::rename(from, to))
+}
+
+/// Moves `from` to `to` using `rename`.
+///
+/// Renaming can't cross filesystems. If `copy_fallback` is set, that falls back
+/// to copying `from` and removing the original. Otherwise, it's reported as
+/// `CrossDevice`.
+fn move_with(
+    from: &Path,
+    to: &Path,
+    copy_fallback: bool,
+    rename: impl FnOnce(&Path, &Path) -> io::Result<()>,
+) -> Result<(), Error> {
+    match rename(from, to) {
+        Err(ref err) if err.raw_os_error() == Some(CROSS_DEVICE) => {
+            if !copy_fallback {
+                return Err(CrossDevice {
+                    from: AbsolutePath::from(from),
+                    to: AbsolutePath::from(to),
+                });
+            }
+
+            verbose_println!(
+                "Copying {} across filesystems",
+                util::home_to_tilde(from).display()
+            );
+            match util::file_type(from)? {
+                util::FileType::Symlink => symlink(fs::read_link(from)?, to)?,
+                util::FileType::File => copy(from, to)?,
+                util::FileType::Directory => {
+                    copy(from, to)?;
+                    return Ok(fs::remove_dir_all(from)?);
+                },
+            }
+
+            Ok(fs::remove_file(from)?)
+        },
+        res => Ok(res?),
+    }
+}
+
+/// Interprets `bytes` as text, unless they look like the contents of a binary
+/// file
+fn as_text(bytes: &[u8]) -> Option<&str> {
+    if bytes.contains(&0) {
+        None
     } else {
-        match fs::read_link(dest) {
-            // If the file at `dest` is already a link to `source`, ignore it.
-            Ok(target) if target.as_path() == source.as_path() => {
-                verbose_println!("Skipping identical {}", dest)
+        str::from_utf8(bytes).ok()
+    }
+}
+
+/// Renders a unified diff from the file at `dest` to the file at `source`.
+///
+/// Binary files aren't diffed, and diffs longer than `max_lines` are
+/// truncated.
+fn diff(source: &Path, dest: &Path, max_lines: usize) -> io::Result<String> {
+    let (old_name, new_name) = (
+        util::home_to_tilde(dest).display().to_string(),
+        util::home_to_tilde(source).display().to_string(),
+    );
+
+    let (old, new) = (fs::read(dest)?, fs::read(source)?);
+    let (old, new) = match (as_text(&old), as_text(&new)) {
+        (Some(old), Some(new)) => (old, new),
+        _ => {
+            return Ok(format!(
+                "Binary files {} and {} differ\n",
+                old_name, new_name
+            ))
+        },
+    };
+
+    let diff = TextDiff::from_lines(old, new)
+        .unified_diff()
+        .header(&old_name, &new_name)
+        .to_string();
+
+    let mut lines = diff.lines();
+    let mut res: String = lines
+        .by_ref()
+        .take(max_lines)
+        .map(|line| format!("{}\n", line))
+        .collect();
+    let remaining = lines.count();
+    if remaining > 0 {
+        res.push_str(&format!("... ({} more lines)\n", remaining));
+    }
+
+    Ok(res)
+}
+
+/// Builds the prompt asking whether to overwrite `dest` with `source`,
+/// describing what's currently at `dest`
+fn overwrite_prompt(source: &AbsolutePath, dest: &AbsolutePath) -> io::Result<String> {
+    let existing = match util::file_type(dest)? {
+        util::FileType::Symlink => format!(
+            "a symlink to {}",
+            util::home_to_tilde(fs::read_link(dest)?).display()
+        ),
+        util::FileType::File => String::from("a file"),
+        util::FileType::Directory => String::from("a directory"),
+    };
+
+    Ok(format!(
+        "{} is {}. Overwrite with {}?",
+        dest, existing, source
+    ))
+}
+
+/// Checks whether nothing at all (not even a broken symlink) exists at `path`
+fn is_free(path: &Path) -> bool {
+    path.symlink_metadata().is_err()
+}
+
+/// Finds the nearest ancestor of `dest` which exists, if it's in the way of
+/// creating the folders `dest` goes in (i.e. it isn't a folder, or a symlink to
+/// one), as when `~/.config` is a file but `dest` is `~/.config/foo`
+fn blocking_ancestor(dest: &Path) -> Option<&Path> {
+    dest.ancestors()
+        .skip(1)
+        .find(|dir| !is_free(dir))
+        .filter(|dir| !dir.is_dir())
+}
+
+/// Computes the path which the symlink at `dest` should contain to point to
+/// `source`.
+///
+/// When linking relatively, falls back to the absolute `source` if `source` and
+/// `dest` have no common ancestor.
+fn link_target(source: &Path, dest: &Path, options: Options) -> PathBuf {
+    match dest.parent() {
+        Some(dest_dir) if options.relative => {
+            util::relative_path(dest_dir, source).unwrap_or_else(|| source.to_path_buf())
+        },
+        _ => source.to_path_buf(),
+    }
+}
+
+/// Checks whether `dest` already holds what linking `source` would put there.
+///
+/// Checking an encrypted `source` means decrypting it, so in a dry run it's
+/// never considered identical.
+fn is_identical(source: &AbsolutePath, dest: &Path, options: Options) -> Result<bool, Error> {
+    if secret::is_encrypted(source) {
+        return Ok(!options.dry_run && secret::is_decrypted_at(source, dest)?);
+    }
+
+    Ok(match options.strategy {
+        Strategy::Symlink => match fs::read_link(dest) {
+            Ok(target) => target == link_target(source, dest, options),
+            Err(_) => false,
+        },
+        Strategy::Copy => match util::file_type(dest)? {
+            util::FileType::File | util::FileType::Directory => same_contents(source, dest)?,
+            util::FileType::Symlink => false,
+        },
+    })
+}
+
+/// Puts `formatted_item`'s source at its destination, which must be free.
+/// Encrypted sources are decrypted there, whatever the strategy.
+///
+/// Changes are recorded in `log` as they're made.
+fn place(
+    formatted_item: &FormattedItem,
+    dotfiles_paths: &[AbsolutePath],
+    options: Options,
+    log: &mut Vec<Action>,
+) -> Result<(), Error> {
+    let (source, dest) = (&formatted_item.item().source, &formatted_item.item().dest);
+    verbose_println!(
+        "{} {}",
+        style::paint("Linking", style::action()),
+        formatted_item
+    );
+
+    if !options.dry_run {
+        if let Some(ancestor) = blocking_ancestor(dest) {
+            if !backs_up(options) {
+                return Err(AncestorNotDir {
+                    dest: dest.clone(),
+                    ancestor: AbsolutePath::from(ancestor),
+                });
+            }
+
+            let backup = backup_path(ancestor);
+            verbose_println!(
+                "Backing up {} to {}",
+                util::home_to_tilde(ancestor).display(),
+                util::home_to_tilde(&backup).display()
+            );
+            move_path(ancestor, &backup, options.strategy == Strategy::Copy)?;
+            log.push(Action::BackedUp {
+                dest: ancestor.to_path_buf(),
+                backup,
+            });
+        }
+
+        create_parent_dirs(dest, log)?;
+        match options.strategy {
+            _ if secret::is_encrypted(source) => secret::decrypt_to(source, dest, dotfiles_paths)?,
+            Strategy::Symlink => symlink(link_target(source, dest, options), dest)?,
+            Strategy::Copy => copy_new(source, dest)?,
+        }
+        // Only recorded once it's there, so that undoing never removes something
+        // which was in the way
+        log.push(Action::Placed(dest.to_path_buf()));
+    }
+
+    Ok(())
+}
+
+/// Creates the missing directories `path` is in, recording each in `log`
+fn create_parent_dirs(path: &Path, log: &mut Vec<Action>) -> io::Result<()> {
+    let dir = path.parent().unwrap_or(path);
+    let mut missing_dirs: Vec<_> = dir
+        .ancestors()
+        .take_while(|dir| !dir.exists())
+        .map(Path::to_path_buf)
+        .collect();
+    fs::create_dir_all(dir)?;
+    // Outermost first, so that undoing in reverse removes the innermost first
+    missing_dirs.reverse();
+    log.extend(missing_dirs.into_iter().map(Action::CreatedDir));
+
+    Ok(())
+}
+
+/// What `link_item` did with an item
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Outcome {
+    /// The destination was free, so the item was simply placed there
+    Linked,
+    /// The destination already held the item, so it was left alone
+    Identical,
+    /// The user chose not to overwrite the destination
+    Declined,
+    /// The destination was replaced
+    Overwritten,
+    /// The destination was backed up, then replaced
+    BackedUp,
+}
+
+/// Tallies what `link_items` did
+#[derive(Debug, Clone)]
+pub struct LinkSummary {
+    pub linked: usize,
+    pub identical: usize,
+    pub declined: usize,
+    /// Includes the items which were backed up
+    pub overwritten: usize,
+    pub backed_up: usize,
+    dry_run: bool,
+    /// The destinations of the items which were placed, or already were (i.e.
+    /// all but the declined ones)
+    placed: HashSet<AbsolutePath>,
+}
+
+impl LinkSummary {
+    fn new(dry_run: bool) -> Self {
+        LinkSummary {
+            linked: 0,
+            identical: 0,
+            declined: 0,
+            overwritten: 0,
+            backed_up: 0,
+            dry_run,
+            placed: HashSet::new(),
+        }
+    }
+
+    /// Checks whether the item destined for `dest` was placed, or already was
+    pub fn is_placed(&self, dest: &AbsolutePath) -> bool {
+        self.placed.contains(dest)
+    }
+
+    /// Checks whether every item was linked (or already was), with no conflicts
+    /// left unresolved
+    pub fn is_complete(&self) -> bool {
+        self.declined == 0
+    }
+
+    fn record(&mut self, outcome: Outcome, dest: &AbsolutePath) {
+        if outcome != Outcome::Declined {
+            self.placed.insert(dest.clone());
+        }
+        match outcome {
+            Outcome::Linked => self.linked += 1,
+            Outcome::Identical => self.identical += 1,
+            Outcome::Declined => self.declined += 1,
+            Outcome::Overwritten => self.overwritten += 1,
+            Outcome::BackedUp => {
+                self.overwritten += 1;
+                self.backed_up += 1;
             },
-            // If the file at `dest` is anything else, ask if it should be overwritten
-            _ => {
-                let prompt = format!("Overwrite {}?", dest);
-                match YN::read_from_cli(&prompt)? {
-                    YN::No => println!("Skipping {}", dest),
-                    YN::Yes => {
-                        match util::file_type(dest)? {
-                            util::FileType::File | util::FileType::Symlink => {
-                                fs::remove_file(dest)?
-                            },
-                            // To be careful, we don't want to overwrite directories. Especially
-                            // since dotman currently only links files and not whole directories.
-                            // To make sure the user _absolutely_ wants to overwrite a directory
-                            // with a file symlink, we ask them to delete the directory manually
-                            // before running dotman.
-                            util::FileType::Directory => {
-                                return Err(DirectoryOverwrite(dest.clone()))
-                            },
-                        };
-                        link(formatted_item)?;
-                    },
+        }
+    }
+}
+
+impl Display for LinkSummary {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let would = if self.dry_run { "would be " } else { "" };
+
+        write!(
+            f,
+            "{} {}linked, {} skipped (identical), {} declined, {} {}overwritten, {} {}backed up",
+            self.linked,
+            would,
+            self.identical,
+            self.declined,
+            self.overwritten,
+            would,
+            self.backed_up,
+            would
+        )
+    }
+}
+
+/// What linking an item would do, as decided by `plan`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PlannedAction {
+    /// The destination is free, so the item would simply be placed there
+    WouldLink,
+    /// The destination already holds the item, so it would be left alone
+    AlreadyLinked,
+    /// Something else is at the destination, so it would only be overwritten
+    /// as the conflict policy says to
+    Conflict,
+    /// The destination is a broken link into the dotfiles folders, left over
+    /// from a dotfile since moved or renamed, so it would be replaced without
+    /// asking
+    BrokenReplace,
+}
+
+/// Decides what linking `formatted_item` would do, without changing anything
+fn plan_item(
+    formatted_item: &FormattedItem,
+    dotfiles_paths: &[AbsolutePath],
+    options: Options,
+) -> Result<PlannedAction, Error> {
+    let (source, dest) = (&formatted_item.item().source, &formatted_item.item().dest);
+
+    Ok(if is_free(dest) {
+        PlannedAction::WouldLink
+    } else if is_identical(source, dest, options)? {
+        PlannedAction::AlreadyLinked
+    } else if is_stale_link(dest, dotfiles_paths) {
+        PlannedAction::BrokenReplace
+    } else {
+        PlannedAction::Conflict
+    })
+}
+
+/// Decides what linking each of `items` would do, in order, by inspecting the
+/// filesystem without changing it. `dotfiles_paths` are used to recognize stale
+/// links.
+///
+/// This is how `link_items` makes its decisions, but neither prompts nor acts.
+pub fn plan(
+    items: &FormattedItems,
+    dotfiles_paths: &[AbsolutePath],
+    options: Options,
+) -> Result<Vec<PlannedAction>, Error> {
+    items
+        .into_iter()
+        .map(|item| plan_item(item, dotfiles_paths, options))
+        .collect()
+}
+
+/// Links a single item.
+///
+/// `remembered` holds the answer the user gave to "all" conflicts, if any. It
+/// is consulted instead of prompting, and updated when the user picks such an
+/// answer.
+///
+/// Changes are recorded in `log` as they're made.
+fn link_item(
+    formatted_item: &FormattedItem,
+    dotfiles_paths: &[AbsolutePath],
+    options: Options,
+    progress: &ProgressBar,
+    remembered: &mut Option<YN>,
+    log: &mut Vec<Action>,
+) -> Result<Outcome, Error> {
+    let dest = &formatted_item.item().dest;
+
+    let planned = plan_item(formatted_item, dotfiles_paths, options)?;
+    match planned {
+        PlannedAction::WouldLink => {
+            place(formatted_item, dotfiles_paths, options, log)?;
+
+            Ok(Outcome::Linked)
+        },
+        // If the file at `dest` is already what we would put there, ignore it.
+        PlannedAction::AlreadyLinked => {
+            verbose_println!(
+                "{}",
+                style::paint(format!("Skipping identical {}", dest), style::skip())
+            );
+
+            Ok(Outcome::Identical)
+        },
+        // If the file at `dest` is anything else, ask if it should be overwritten
+        PlannedAction::Conflict | PlannedAction::BrokenReplace => overwrite_item(
+            formatted_item,
+            dotfiles_paths,
+            planned,
+            options,
+            progress,
+            remembered,
+            log,
+        ),
+    }
+}
+
+/// Prints a (truncated) diff from `dest` to `source`, if they're both files.
+///
+/// Only real files have contents worth comparing, and secrets mustn't be
+/// printed, so nothing is printed otherwise.
+fn print_diff(source: &Path, dest: &Path) -> io::Result<()> {
+    let is_file =
+        |path: &Path| -> io::Result<bool> { Ok(util::file_type(path)? == util::FileType::File) };
+    if is_file(dest)? && is_file(source)? && !secret::is_encrypted(source) {
+        print!("{}", diff(source, dest, MAX_DIFF_LINES)?);
+    }
+
+    Ok(())
+}
+
+/// Overwrites the destination of `formatted_item` if the conflict policy (or
+/// the user) agrees to.
+///
+/// Nobody is asked unless the policy is to prompt, and not even then when
+/// `planned` found a stale link or `remembered` already holds an answer (see
+/// `link_item`).
+fn overwrite_item(
+    formatted_item: &FormattedItem,
+    dotfiles_paths: &[AbsolutePath],
+    planned: PlannedAction,
+    options: Options,
+    progress: &ProgressBar,
+    remembered: &mut Option<YN>,
+    log: &mut Vec<Action>,
+) -> Result<Outcome, Error> {
+    let (source, dest) = (&formatted_item.item().source, &formatted_item.item().dest);
+
+    let policy = options.on_conflict.unwrap_or_default();
+    let answer = match (policy, *remembered) {
+        // A stale link isn't really a conflict, so it's replaced whatever the policy
+        _ if planned == PlannedAction::BrokenReplace => {
+            verbose_println!("Replacing stale link {}", dest);
+
+            YN::Yes
+        },
+        (ConflictPolicy::Overwrite, _) | (ConflictPolicy::Backup, _) => YN::Yes,
+        (ConflictPolicy::Skip, _) => YN::No,
+        (ConflictPolicy::Prompt, Some(answer)) => answer,
+        // The progress bar would otherwise be drawn over the prompt
+        (ConflictPolicy::Prompt, None) => progress.suspend(|| -> Result<YN, Error> {
+            if options.diff {
+                print_diff(source, dest)?;
+            }
+
+            let prompt = style::paint(overwrite_prompt(source, dest)?, style::conflict());
+            // Overwriting is destructive, so it only happens when asked for explicitly
+            let answer = match options.answer {
+                Some(answer) => YNAll::read_default_from(
+                    &mut answer.as_bytes(),
+                    &mut io::sink(),
+                    &prompt,
+                    YN::No,
+                )?,
+                None => YNAll::read_from_cli_default(&prompt, YN::No)?,
+            };
+            if answer.applies_to_all() {
+                *remembered = Some(answer.answer());
+            }
+
+            Ok(answer.answer())
+        })?,
+    };
+
+    match answer {
+        YN::No => {
+            progress.suspend(|| {
+                info_println!(
+                    "{}",
+                    style::paint(format!("Skipping {}", dest), style::skip())
+                )
+            });
+
+            Ok(Outcome::Declined)
+        },
+        YN::Yes => {
+            let outcome = match util::file_type(dest)? {
+                util::FileType::File | util::FileType::Symlink if backs_up(options) => {
+                    let backup = backup_path(dest);
+                    verbose_println!(
+                        "Backing up {} to {}",
+                        dest,
+                        util::home_to_tilde(&backup).display()
+                    );
+                    if !options.dry_run {
+                        move_path(dest, &backup, options.strategy == Strategy::Copy)?;
+                        log.push(Action::BackedUp {
+                            dest: dest.to_path_buf(),
+                            backup,
+                        });
+                    }
+
+                    Outcome::BackedUp
+                },
+                util::FileType::File | util::FileType::Symlink if options.atomic => {
+                    if !options.dry_run {
+                        let stash = unused_path(dest, STASH_EXTENSION);
+                        move_path(dest, &stash, options.strategy == Strategy::Copy)?;
+                        log.push(Action::Stashed {
+                            dest: dest.to_path_buf(),
+                            stash,
+                        });
+                    }
+
+                    Outcome::Overwritten
+                },
+                util::FileType::File | util::FileType::Symlink => {
+                    if !options.dry_run {
+                        fs::remove_file(dest)?
+                    }
+
+                    Outcome::Overwritten
+                },
+                // To be careful, we don't want to overwrite directories. This applies
+                // even when `source` is itself a directory (see `resolver::DIR_MARKER`),
+                // since the existing directory may hold files that aren't in `source`.
+                // To make sure the user _absolutely_ wants to overwrite a directory,
+                // we ask them to delete the directory manually before running dotman.
+                util::FileType::Directory => return Err(DirectoryOverwrite(dest.clone())),
+            };
+            place(formatted_item, dotfiles_paths, options, log)?;
+
+            Ok(outcome)
+        },
+    }
+}
+
+/// Creates the progress bar shown while linking `len` items, if
+/// `options.progress` is set.
+///
+/// The bar is hidden unless stdout is a terminal, and it's never shown in quiet
+/// or verbose mode (which already prints a line per item).
+fn progress_bar(len: usize, options: Options) -> ProgressBar {
+    let shown = options.progress
+        && io::stdout().is_terminal()
+        && util::get_verbosity() == Verbosity::Normal;
+    if !shown {
+        return ProgressBar::hidden();
+    }
+
+    let bar = ProgressBar::with_draw_target(Some(len as u64), ProgressDrawTarget::stdout());
+    bar.set_style(
+        ProgressStyle::with_template("{bar:40} {pos}/{len}").expect("template must be valid"),
+    );
+
+    bar
+}
+
+/// Reports what linking each of `items` would change (see `plan`), without
+/// prompting or changing anything.
+///
+/// There's nobody to ask whether conflicts should be overwritten, so they're
+/// counted as overwritten.
+fn preview(
+    items: &FormattedItems,
+    dotfiles_paths: &[AbsolutePath],
+    options: Options,
+) -> Result<LinkSummary, Error> {
+    let mut summary = LinkSummary::new(true);
+    let actions = plan(items, dotfiles_paths, options)?;
+    for (formatted_item, action) in items.into_iter().zip(actions) {
+        let (source, dest) = (&formatted_item.item().source, &formatted_item.item().dest);
+        // Padded to the longest description, so the items line up
+        let describe = |description: &str| {
+            info_println!(
+                "{} {}",
+                style::paint(format!("{:25}", description), style::action()),
+                formatted_item
+            )
+        };
+        let policy = options.on_conflict.unwrap_or_default();
+        let overwritten = if backs_up(options) {
+            Outcome::BackedUp
+        } else {
+            Outcome::Overwritten
+        };
+
+        let outcome = match action {
+            // As with directories below, linking for real would fail here
+            PlannedAction::WouldLink => match blocking_ancestor(dest) {
+                Some(ancestor) if !backs_up(options) => {
+                    return Err(AncestorNotDir {
+                        dest: dest.clone(),
+                        ancestor: AbsolutePath::from(ancestor),
+                    })
+                },
+                _ => {
+                    describe("Would link (new)");
+                    Outcome::Linked
+                },
+            },
+            PlannedAction::AlreadyLinked => {
+                verbose_println!(
+                    "{}",
+                    style::paint(
+                        format!("Already linked {} (no change)", dest),
+                        style::skip()
+                    )
+                );
+                Outcome::Identical
+            },
+            PlannedAction::Conflict if policy == ConflictPolicy::Skip => {
+                describe("Would skip (conflict)");
+                Outcome::Declined
+            },
+            // Linking for real would fail here, so a dry run does too
+            PlannedAction::Conflict if util::file_type(dest)? == util::FileType::Directory => {
+                return Err(DirectoryOverwrite(dest.clone()))
+            },
+            PlannedAction::Conflict => {
+                describe("Would overwrite");
+                if options.diff {
+                    print_diff(source, dest)?;
                 }
+                overwritten
             },
+            PlannedAction::BrokenReplace => {
+                describe("Would replace broken link");
+                overwritten
+            },
+        };
+        summary.record(outcome, dest);
+    }
+
+    if summary.linked == 0 && summary.overwritten == 0 {
+        info_println!("No changes");
+    }
+
+    Ok(summary)
+}
+
+/// Links each of `items`. `dotfiles_paths` are used to recognize stale links
+/// which can be replaced without asking.
+///
+/// A dry run only reports what would change (see `preview`).
+pub fn link_items(
+    items: FormattedItems,
+    dotfiles_paths: &[AbsolutePath],
+    options: Options,
+) -> Result<LinkSummary, Error> {
+    if options.dry_run {
+        return preview(&items, dotfiles_paths, options);
+    }
+
+    let mut log = vec![];
+    let mut summary = LinkSummary::new(options.dry_run);
+    let progress = progress_bar(items.len(), options);
+    let res = link_all(
+        &items,
+        dotfiles_paths,
+        options,
+        &progress,
+        &mut log,
+        &mut summary,
+    );
+    progress.finish_and_clear();
+    if let Err(err) = res {
+        if options.atomic {
+            rollback(log);
         }
+
+        return Err(err);
+    }
+
+    // Everything worked, so the overwritten files are no longer needed
+    for action in log {
+        if let Action::Stashed { stash, .. } = action {
+            fs::remove_file(stash)?;
+        }
+    }
+
+    Ok(summary)
+}
+
+/// Links each of `items`, recording changes in `log` as they're made and what
+/// happened to each item in `summary`
+fn link_all(
+    items: &FormattedItems,
+    dotfiles_paths: &[AbsolutePath],
+    options: Options,
+    progress: &ProgressBar,
+    log: &mut Vec<Action>,
+    summary: &mut LinkSummary,
+) -> Result<(), Error> {
+    let mut remembered = None;
+    if !options.parallel {
+        for item in items {
+            let outcome = link_item(
+                item,
+                dotfiles_paths,
+                options,
+                progress,
+                &mut remembered,
+                log,
+            )?;
+            summary.record(outcome, &item.item().dest);
+            progress.inc(1);
+        }
+
+        return Ok(());
+    }
+
+    // Items with free destinations never prompt, so they can be placed
+    // concurrently. The rest may need the user's input (including encrypted items,
+    // whose decryptor may ask for a passphrase, and those under a file which may
+    // need backing up once for all of them), so they're linked serially (and in
+    // order) afterwards.
+    let (free, rest): (Vec<_>, Vec<_>) = items.into_iter().partition(|item| {
+        let (source, dest) = (&item.item().source, &item.item().dest);
+        is_free(dest) && blocking_ancestor(dest).is_none() && !secret::is_encrypted(source)
+    });
+
+    // Items often share missing parents, which would otherwise be recorded as
+    // created by each of them (and then fail to be removed by all but the last
+    // to undo it), so they're created serially up front
+    if !options.dry_run {
+        for item in &free {
+            create_parent_dirs(&item.item().dest, log)?;
+        }
+    }
+
+    let results: Vec<_> = free
+        .par_iter()
+        .map(|item| {
+            let mut item_log = vec![];
+            let res = place(item, dotfiles_paths, options, &mut item_log);
+            progress.inc(1);
+
+            (item_log, res)
+        })
+        .collect();
+    // Every item's changes need recording before giving up, so that they can all
+    // be undone
+    let mut first_err = None;
+    for (item, (item_log, res)) in free.iter().zip(results) {
+        log.extend(item_log);
+        match res {
+            Ok(()) => summary.record(Outcome::Linked, &item.item().dest),
+            Err(err) => {
+                first_err.get_or_insert(err);
+            },
+        }
+    }
+    if let Some(err) = first_err {
+        return Err(err);
+    }
+
+    for item in rest {
+        let outcome = link_item(
+            item,
+            dotfiles_paths,
+            options,
+            progress,
+            &mut remembered,
+            log,
+        )?;
+        summary.record(outcome, &item.item().dest);
+        progress.inc(1);
     }
 
     Ok(())
 }
 
-pub fn link_items(items: FormattedItems, dry_run: bool) -> Result<(), Error> {
+/// Prints the differences between each of `items` and what's currently at
+/// their destinations, e.g. after editing a copied dotfile in place.
+///
+/// Destinations linked to their dotfiles have no differences. Missing
+/// destinations and directories are noted, but aren't counted as differences.
+///
+/// Returns whether any differences were found.
+pub fn diff_items(items: &FormattedItems) -> Result<bool, Error> {
+    let mut differs = false;
+    for formatted_item in items {
+        let (source, dest) = (&formatted_item.item().source, &formatted_item.item().dest);
+
+        if !dest.exists() {
+            info_println!(
+                "{}",
+                style::paint(format!("{} doesn't exist", dest), style::skip())
+            );
+            continue;
+        }
+        if source.is_dir() || dest.is_dir() {
+            info_println!(
+                "{}",
+                style::paint(format!("Skipping directory {}", dest), style::skip())
+            );
+            continue;
+        }
+        // Following the link to `source` would find the same contents, so there's
+        // nothing to compare
+        if dest.canonicalize()? == source.canonicalize()? {
+            continue;
+        }
+
+        // Decrypted secrets are compared, but never printed
+        if secret::is_encrypted(source) {
+            if !secret::is_decrypted_at(source, dest)? {
+                differs = true;
+                println!("Secret {} differs (contents not shown)", dest);
+            }
+            continue;
+        }
+
+        if fs::read(source)? != fs::read(dest)? {
+            differs = true;
+            print!("{}", diff(source, dest, usize::MAX)?);
+        }
+    }
+
+    Ok(differs)
+}
+
+/// Moves the file (or directory) `dest` to `source` in the dotfiles folder,
+/// then links it back in its original place.
+///
+/// Nothing is overwritten, so `source` mustn't exist yet. If `dest` is already
+/// a symlink, it's most likely already a dotfile, so it isn't moved either.
+pub fn add(dest: &AbsolutePath, source: &AbsolutePath, dry_run: bool) -> Result<(), Error> {
+    if is_free(dest) {
+        return Err(NothingToAdd(dest.clone()));
+    }
+    if util::file_type(dest)? == util::FileType::Symlink {
+        return Err(AlreadySymlink(dest.clone()));
+    }
+    if !is_free(source) {
+        return Err(SourceExists(source.clone()));
+    }
+
+    if dry_run {
+        info_println!("Would move {} to {} and link it back", dest, source);
+        return Ok(());
+    }
+
+    verbose_println!("Moving {} to {}", dest, source);
+    fs::create_dir_all(source.parent().unwrap_or(source))?;
+    move_path(dest, source, true)?;
+    if let Err(err) = symlink(source, dest) {
+        // Put things back as they were, so the file isn't left only in the dotfiles
+        // folder
+        if let Err(undo_err) = move_path(source, dest, true) {
+            eprintln!(
+                "Warning: couldn't move {} back to {} ({})",
+                source, dest, undo_err
+            );
+        }
+
+        return Err(err);
+    }
+    info_println!("Added {} as {}", dest, source);
+
+    Ok(())
+}
+
+/// Tallies what `unlink_items` did
+#[derive(Debug, Clone, Copy)]
+pub struct UnlinkSummary {
+    pub removed: usize,
+    pub skipped: usize,
+    dry_run: bool,
+}
+
+impl Display for UnlinkSummary {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let removed = if self.dry_run {
+            "would be removed"
+        } else {
+            "removed"
+        };
+
+        write!(f, "{} {}, {} skipped", self.removed, removed, self.skipped)
+    }
+}
+
+/// Removes the symlink at `dest` if (and only if) it points to `source`.
+///
+/// Returns whether the link was (or, in a dry run, would have been) removed.
+fn unlink_item(formatted_item: &FormattedItem, dry_run: bool) -> Result<bool, Error> {
+    let (source, dest) = (&formatted_item.item().source, &formatted_item.item().dest);
+
+    match util::read_link_absolute(dest) {
+        Ok(target) if target.as_path() == source.as_path() => {
+            verbose_println!("Unlinking {}", formatted_item);
+
+            if !dry_run {
+                fs::remove_file(dest)?;
+            }
+
+            Ok(true)
+        },
+        Ok(target) => {
+            verbose_println!(
+                "Skipping {}, which links to {}",
+                dest,
+                util::home_to_tilde(target).display()
+            );
+            Ok(false)
+        },
+        // Either `dest` doesn't exist or it isn't a symlink. Either way, it
+        // isn't ours to remove.
+        Err(_) => Ok(false),
+    }
+}
+
+pub fn unlink_items(items: FormattedItems, dry_run: bool) -> Result<UnlinkSummary, Error> {
+    let mut summary = UnlinkSummary {
+        removed: 0,
+        skipped: 0,
+        dry_run,
+    };
+
     for item in &items {
-        link_item(item, dry_run)?;
+        if unlink_item(item, dry_run)? {
+            summary.removed += 1;
+        } else {
+            summary.skipped += 1;
+        }
+    }
+
+    Ok(summary)
+}
+
+/// Checks whether `path` is a symlink which points into one of `dotfiles_paths`
+/// at something that no longer exists
+fn is_stale_link(path: &Path, dotfiles_paths: &[AbsolutePath]) -> bool {
+    !path.exists()
+        && match util::read_link_absolute(path) {
+            Ok(target) => dotfiles_paths.iter().any(|dir| target.starts_with(dir)),
+            Err(_) => false,
+        }
+}
+
+/// Finds the symlinks under `root` which point into one of `dotfiles_paths` at
+/// something that no longer exists.
+///
+/// The dotfiles folders themselves aren't searched, and symlinks pointing
+/// anywhere else are never reported. Entries which can't be read are skipped.
+pub fn broken_links(root: &Path, dotfiles_paths: &[AbsolutePath]) -> Vec<AbsolutePath> {
+    symlinks_under(root, dotfiles_paths)
+        .filter(|link| is_stale_link(link, dotfiles_paths))
+        .collect()
+}
+
+/// Finds the symlinks under `root` which point into one of `dotfiles_paths`,
+/// but not at (or into) the source of any of `items`, as when a dotfile was
+/// deleted or moved into an inactive folder. This includes broken links (see
+/// `broken_links`).
+///
+/// Symlinks at the destination of any of `items` are left to linking them, and
+/// the dotfiles folders themselves aren't searched.
+pub fn stale_links(
+    root: &Path,
+    dotfiles_paths: &[AbsolutePath],
+    items: &[Item],
+) -> Vec<AbsolutePath> {
+    let is_current = |target: &Path| items.iter().any(|item| target.starts_with(&item.source));
+
+    symlinks_under(root, dotfiles_paths)
+        .filter(|link| items.iter().all(|item| item.dest != *link))
+        .filter(|link| match util::read_link_absolute(link) {
+            Ok(target) => {
+                dotfiles_paths.iter().any(|dir| target.starts_with(dir)) && !is_current(&target)
+            },
+            Err(_) => false,
+        })
+        .collect()
+}
+
+/// Lists the symlinks under `root`, outside of `dotfiles_paths`. Entries which
+/// can't be read are skipped.
+fn symlinks_under<'a>(
+    root: &Path,
+    dotfiles_paths: &'a [AbsolutePath],
+) -> impl Iterator<Item = AbsolutePath> + 'a {
+    let in_dotfiles = move |path: &Path| dotfiles_paths.iter().any(|dir| path.starts_with(dir));
+
+    WalkDir::new(root)
+        .into_iter()
+        .filter_entry(move |entry| !in_dotfiles(entry.path()))
+        .filter_map(Result::ok)
+        .filter(|entry| entry.path_is_symlink())
+        .map(|entry| AbsolutePath::from(entry.into_path()))
+}
+
+/// Lists the broken links under `root` (see `broken_links`), then removes them
+/// if the user agrees. A dry run only lists them.
+pub fn clean(root: &Path, dotfiles_paths: &[AbsolutePath], dry_run: bool) -> Result<(), Error> {
+    let links = broken_links(root, dotfiles_paths);
+    if links.is_empty() {
+        info_println!("No broken links found");
+        return Ok(());
+    }
+
+    for link in &links {
+        println!("{}", link);
+    }
+
+    if dry_run {
+        info_println!("{} broken links would be removed", links.len());
+        return Ok(());
+    }
+    let prompt = format!("Remove {} broken links?", links.len());
+    if let YN::No = YN::read_from_cli(&prompt)? {
+        return Ok(());
+    }
+
+    for link in &links {
+        verbose_println!("Removing {}", link);
+        fs::remove_file(link)?;
+    }
+
+    Ok(())
+}
+
+/// Lists the stale links under `root` which aren't links to any of `items`
+/// (see `stale_links`), then removes them if the user agrees. A dry run only
+/// lists them.
+pub fn prune(
+    root: &Path,
+    dotfiles_paths: &[AbsolutePath],
+    items: &[Item],
+    dry_run: bool,
+) -> Result<(), Error> {
+    let links = stale_links(root, dotfiles_paths, items);
+    if links.is_empty() {
+        info_println!("No stale links found");
+        return Ok(());
+    }
+
+    for link in &links {
+        println!("{}", link);
+    }
+
+    if dry_run {
+        info_println!("{} stale links would be removed", links.len());
+        return Ok(());
+    }
+    let prompt = format!("Remove {} stale links?", links.len());
+    if let YN::No = YN::read_from_cli(&prompt)? {
+        return Ok(());
+    }
+
+    for link in &links {
+        verbose_println!("Removing {}", link);
+        fs::remove_file(link)?;
     }
 
     Ok(())
@@ -82,5 +1537,904 @@ pub enum Error {
         _0
     )]
     DirectoryOverwrite(AbsolutePath),
+
+    /// Windows only allows creating symlinks with administrator privileges or
+    /// when developer mode is enabled.
+    #[fail(
+        display = "insufficient privileges to create symlinks. Try enabling developer mode or \
+                   running as an administrator."
+    )]
+    SymlinkPrivilege,
+
+    #[fail(
+        display = "can't move {} to {}, since they're on different filesystems. Try linking with \
+                   --copy.",
+        from, to
+    )]
+    CrossDevice {
+        from: AbsolutePath,
+        to: AbsolutePath,
+    },
+
+    #[fail(
+        display = "can't link {}, since {} isn't a folder. Move it out of the way, or link with \
+                   --backup to have it backed up.",
+        dest, ancestor
+    )]
+    #[from(ignore)]
+    AncestorNotDir {
+        dest: AbsolutePath,
+        ancestor: AbsolutePath,
+    },
+
+    #[fail(display = "{} doesn't exist", _0)]
+    #[from(ignore)]
+    NothingToAdd(AbsolutePath),
+
+    #[fail(display = "{} is already a symlink", _0)]
+    #[from(ignore)]
+    AlreadySymlink(AbsolutePath),
+
+    #[fail(display = "{} already exists in the dotfiles folder", _0)]
+    #[from(ignore)]
+    SourceExists(AbsolutePath),
+
+    #[fail(display = "{}", _0)]
+    SecretError(#[fail(cause)] secret::Error),
 }
 use Error::*;
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        add, backup_path, broken_links, diff, diff_items, link_items, move_with, overwrite_prompt,
+        plan, ConflictPolicy, Error, Options, PlannedAction, Strategy, CROSS_DEVICE,
+        MAX_DIFF_LINES,
+    };
+    use crate::common::{AbsolutePath, FormattedItems, Item};
+    use pretty_assertions::assert_eq;
+    use std::{fs, io, path::Path};
+    use tempfile::TempDir;
+
+    fn options() -> Options {
+        Options {
+            dry_run: false,
+            strategy: Strategy::Symlink,
+            backup: false,
+            on_conflict: None,
+            relative: false,
+            parallel: false,
+            diff: false,
+            atomic: false,
+            progress: false,
+            answer: None,
+        }
+    }
+
+    #[test]
+    fn link_items_parallel() {
+        let dotfiles = TempDir::new().unwrap();
+        let home = TempDir::new().unwrap();
+        let names: Vec<_> = (0..100).map(|n| format!("file{}", n)).collect();
+        for name in &names {
+            fs::write(dotfiles.path().join(name), name).unwrap();
+        }
+
+        let items = names
+            .iter()
+            .map(|name| {
+                Item::new(
+                    dotfiles.path().join(name),
+                    home.path().join("nested").join(name),
+                )
+            })
+            .collect();
+        let options = Options {
+            parallel: true,
+            progress: true,
+            ..options()
+        };
+        let summary = link_items(FormattedItems::from_items(items), &[], options).unwrap();
+        assert_eq!(summary.linked, names.len());
+
+        for name in &names {
+            let dest = home.path().join("nested").join(name);
+            assert_eq!(fs::read_link(&dest).unwrap(), dotfiles.path().join(name));
+        }
+    }
+
+    #[test]
+    fn link_all_parallel_shared_dirs() {
+        let dotfiles = TempDir::new().unwrap();
+        let home = TempDir::new().unwrap();
+        let names: Vec<_> = (0..20).map(|n| format!("file{}", n)).collect();
+        for name in &names {
+            fs::write(dotfiles.path().join(name), name).unwrap();
+        }
+
+        let items = FormattedItems::from_items(
+            names
+                .iter()
+                .map(|name| {
+                    Item::new(
+                        dotfiles.path().join(name),
+                        home.path().join("nested/deeper").join(name),
+                    )
+                })
+                .collect(),
+        );
+        let options = Options {
+            parallel: true,
+            ..options()
+        };
+        let mut log = vec![];
+        let mut summary = super::LinkSummary::new(false);
+        super::link_all(
+            &items,
+            &[],
+            options,
+            &indicatif::ProgressBar::hidden(),
+            &mut log,
+            &mut summary,
+        )
+        .unwrap();
+
+        // Each shared directory is only recorded (and so only undone) once
+        let created: Vec<_> = log
+            .iter()
+            .filter_map(|action| match action {
+                super::Action::CreatedDir(dir) => Some(dir.clone()),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(
+            created,
+            vec![
+                home.path().join("nested"),
+                home.path().join("nested/deeper")
+            ]
+        );
+
+        super::rollback(log);
+        assert!(!home.path().join("nested").exists());
+    }
+
+    #[test]
+    fn link_items_summary() {
+        let dotfiles = TempDir::new().unwrap();
+        let home = TempDir::new().unwrap();
+        for name in &["vimrc", "zshrc", "bashrc"] {
+            fs::write(dotfiles.path().join(name), name).unwrap();
+        }
+        super::symlink(dotfiles.path().join("zshrc"), home.path().join(".zshrc")).unwrap();
+        fs::write(home.path().join(".bashrc"), "old").unwrap();
+
+        let items = ["vimrc", "zshrc", "bashrc"]
+            .iter()
+            .map(|name| {
+                Item::new(
+                    dotfiles.path().join(name),
+                    home.path().join(format!(".{}", name)),
+                )
+            })
+            .collect();
+        let options = Options {
+            on_conflict: Some(ConflictPolicy::Overwrite),
+            backup: true,
+            ..options()
+        };
+        let summary = link_items(FormattedItems::from_items(items), &[], options).unwrap();
+
+        assert_eq!(
+            summary.to_string(),
+            "1 linked, 1 skipped (identical), 0 declined, 1 overwritten, 1 backed up"
+        );
+    }
+
+    #[test]
+    fn diff_items_drift() {
+        let dotfiles = TempDir::new().unwrap();
+        let home = TempDir::new().unwrap();
+        for name in &["vimrc", "zshrc", "bashrc"] {
+            fs::write(dotfiles.path().join(name), name).unwrap();
+        }
+        super::symlink(dotfiles.path().join("vimrc"), home.path().join(".vimrc")).unwrap();
+        fs::write(home.path().join(".zshrc"), "zshrc").unwrap();
+
+        let item = |name: &str| {
+            Item::new(
+                dotfiles.path().join(name),
+                home.path().join(format!(".{}", name)),
+            )
+        };
+        // Linked, an identical copy, and missing
+        let items = vec![item("vimrc"), item("zshrc"), item("bashrc")];
+        assert!(!diff_items(&FormattedItems::from_items(items)).unwrap());
+
+        fs::write(home.path().join(".zshrc"), "edited").unwrap();
+        let items = vec![item("zshrc")];
+        assert!(diff_items(&FormattedItems::from_items(items)).unwrap());
+    }
+
+    #[test]
+    fn conflict_policies() {
+        /// Links a dotfile over an existing `.vimrc` with `policy`, returning
+        /// what ended up at (and beside) the destination
+        fn link_over(policy: ConflictPolicy) -> (bool, Option<String>) {
+            let dotfiles = TempDir::new().unwrap();
+            let home = TempDir::new().unwrap();
+            fs::write(dotfiles.path().join("vimrc"), "new").unwrap();
+            fs::write(home.path().join(".vimrc"), "old").unwrap();
+
+            let items = vec![Item::new(
+                dotfiles.path().join("vimrc"),
+                home.path().join(".vimrc"),
+            )];
+            let options = Options {
+                on_conflict: Some(policy),
+                // Stands in for the user answering the prompt
+                answer: Some("y\n"),
+                ..options()
+            };
+            link_items(FormattedItems::from_items(items), &[], options).unwrap();
+
+            let dest = home.path().join(".vimrc");
+            let linked = fs::read_link(&dest).ok() == Some(dotfiles.path().join("vimrc"));
+            let backup = fs::read_to_string(home.path().join(".vimrc.dotman-bak")).ok();
+
+            (linked, backup)
+        }
+
+        assert_eq!(link_over(ConflictPolicy::Prompt), (true, None));
+        assert_eq!(link_over(ConflictPolicy::Skip), (false, None));
+        assert_eq!(link_over(ConflictPolicy::Overwrite), (true, None));
+        assert_eq!(
+            link_over(ConflictPolicy::Backup),
+            (true, Some(String::from("old")))
+        );
+    }
+
+    #[test]
+    fn ancestor_not_dir() {
+        let dotfiles = TempDir::new().unwrap();
+        let home = TempDir::new().unwrap();
+        fs::create_dir(dotfiles.path().join("config")).unwrap();
+        fs::write(dotfiles.path().join("config/foo"), "").unwrap();
+        fs::write(home.path().join(".config"), "not a folder").unwrap();
+
+        let items = || {
+            FormattedItems::from_items(vec![Item::new(
+                dotfiles.path().join("config/foo"),
+                home.path().join(".config/foo"),
+            )])
+        };
+        for &dry_run in &[true, false] {
+            let options = Options {
+                dry_run,
+                ..options()
+            };
+            match link_items(items(), &[], options) {
+                Err(Error::AncestorNotDir { dest, ancestor }) => {
+                    assert_eq!(dest, AbsolutePath::from(home.path().join(".config/foo")));
+                    assert_eq!(ancestor, AbsolutePath::from(home.path().join(".config")));
+                },
+                res => panic!("expected AncestorNotDir, got {:?}", res),
+            }
+        }
+        assert!(home.path().join(".config").is_file());
+
+        // With backups, the file is moved aside instead
+        let options = Options {
+            backup: true,
+            ..options()
+        };
+        link_items(items(), &[], options).unwrap();
+        assert_eq!(
+            fs::read_link(home.path().join(".config/foo")).unwrap(),
+            dotfiles.path().join("config/foo")
+        );
+        assert_eq!(
+            fs::read_to_string(home.path().join(".config.dotman-bak")).unwrap(),
+            "not a folder"
+        );
+    }
+
+    #[test]
+    fn conflict_policy_directory_overwrite() {
+        let dotfiles = TempDir::new().unwrap();
+        let home = TempDir::new().unwrap();
+        fs::write(dotfiles.path().join("config"), "").unwrap();
+        fs::create_dir(home.path().join(".config")).unwrap();
+
+        for &policy in &[ConflictPolicy::Overwrite, ConflictPolicy::Backup] {
+            let items = vec![Item::new(
+                dotfiles.path().join("config"),
+                home.path().join(".config"),
+            )];
+            let options = Options {
+                on_conflict: Some(policy),
+                ..options()
+            };
+
+            match link_items(FormattedItems::from_items(items), &[], options) {
+                Err(Error::DirectoryOverwrite(_)) => {},
+                res => panic!("expected DirectoryOverwrite, got {:?}", res),
+            }
+        }
+        assert!(home.path().join(".config").is_dir());
+    }
+
+    #[test]
+    fn yes_directory_overwrite() {
+        let dotfiles = TempDir::new().unwrap();
+        let home = TempDir::new().unwrap();
+        fs::write(dotfiles.path().join("config"), "").unwrap();
+        fs::create_dir(home.path().join(".config")).unwrap();
+
+        let items = vec![Item::new(
+            dotfiles.path().join("config"),
+            home.path().join(".config"),
+        )];
+        // Answering yes (as `--yes` does; see `common::tests::assume_yes`) still
+        // doesn't overwrite a directory
+        let options = Options {
+            answer: Some("y\n"),
+            ..options()
+        };
+
+        match link_items(FormattedItems::from_items(items), &[], options) {
+            Err(Error::DirectoryOverwrite(path)) => {
+                assert_eq!(path, AbsolutePath::from(home.path().join(".config")))
+            },
+            res => panic!("expected DirectoryOverwrite, got {:?}", res),
+        }
+    }
+
+    #[test]
+    fn link_items_stale_link() {
+        let dotfiles = TempDir::new().unwrap();
+        let home = TempDir::new().unwrap();
+        fs::write(dotfiles.path().join("vimrc"), "").unwrap();
+        // Left over from before `vimrc` was renamed from `vim.conf`
+        super::symlink(dotfiles.path().join("vim.conf"), home.path().join(".vimrc")).unwrap();
+
+        let items = vec![Item::new(
+            dotfiles.path().join("vimrc"),
+            home.path().join(".vimrc"),
+        )];
+        let dotfiles_paths = vec![AbsolutePath::from(dotfiles.path())];
+        // Not forced, so anything other than a stale link would prompt
+        let summary = link_items(
+            FormattedItems::from_items(items),
+            &dotfiles_paths,
+            options(),
+        )
+        .unwrap();
+
+        assert_eq!(summary.overwritten, 1);
+        assert_eq!(
+            fs::read_link(home.path().join(".vimrc")).unwrap(),
+            dotfiles.path().join("vimrc")
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn copy_preserves_metadata() {
+        use filetime::FileTime;
+        use std::os::unix::fs::PermissionsExt;
+
+        let dotfiles = TempDir::new().unwrap();
+        let home = TempDir::new().unwrap();
+        let source = dotfiles.path().join("ssh").join("config");
+        fs::create_dir(source.parent().unwrap()).unwrap();
+        fs::write(&source, "Host *").unwrap();
+        fs::set_permissions(&source, fs::Permissions::from_mode(0o600)).unwrap();
+        let mtime = FileTime::from_unix_time(1_500_000_000, 0);
+        filetime::set_file_mtime(&source, mtime).unwrap();
+        fs::set_permissions(source.parent().unwrap(), fs::Permissions::from_mode(0o700)).unwrap();
+
+        let items = vec![
+            Item::new(source.clone(), home.path().join(".ssh-config")),
+            Item::new(source.parent().unwrap(), home.path().join(".ssh")),
+        ];
+        let options = Options {
+            strategy: Strategy::Copy,
+            ..options()
+        };
+        link_items(FormattedItems::from_items(items), &[], options).unwrap();
+
+        for dest in &[
+            home.path().join(".ssh-config"),
+            home.path().join(".ssh/config"),
+        ] {
+            let metadata = fs::metadata(dest).unwrap();
+            assert_eq!(metadata.permissions().mode() & 0o777, 0o600);
+            assert_eq!(FileTime::from_last_modification_time(&metadata), mtime);
+        }
+        let metadata = fs::metadata(home.path().join(".ssh")).unwrap();
+        assert_eq!(metadata.permissions().mode() & 0o777, 0o700);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn set_modes() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dotfiles = TempDir::new().unwrap();
+        let home = TempDir::new().unwrap();
+        let mode = |path: &Path| fs::metadata(path).unwrap().permissions().mode() & 0o7777;
+        for name in &["netrc", "npmrc", "vimrc"] {
+            let source = dotfiles.path().join(name);
+            fs::write(&source, name).unwrap();
+            fs::set_permissions(&source, fs::Permissions::from_mode(0o644)).unwrap();
+        }
+        // Declined, so it's no copy of the dotfile
+        fs::write(home.path().join(".npmrc"), "token").unwrap();
+
+        let items: Vec<_> = ["netrc", "npmrc", "vimrc"]
+            .iter()
+            .map(|name| {
+                Item::new(
+                    dotfiles.path().join(name),
+                    home.path().join(format!(".{}", name)),
+                )
+            })
+            .collect();
+        let options = Options {
+            strategy: Strategy::Copy,
+            on_conflict: Some(ConflictPolicy::Skip),
+            ..options()
+        };
+        let mut modes = vec![(items[0].clone(), 0o600), (items[1].clone(), 0o600)];
+        let summary = link_items(FormattedItems::from_items(items), &[], options).unwrap();
+        modes.retain(|(item, _)| summary.is_placed(&item.dest));
+
+        super::set_modes(&modes, options.strategy, true).unwrap();
+        assert_eq!(mode(&dotfiles.path().join("netrc")), 0o644);
+
+        super::set_modes(&modes, options.strategy, false).unwrap();
+        assert_eq!(mode(&dotfiles.path().join("netrc")), 0o600);
+        assert_eq!(mode(&home.path().join(".netrc")), 0o600);
+        // Declined, so neither it nor its dotfile is touched
+        assert_eq!(mode(&dotfiles.path().join("npmrc")), 0o644);
+        assert_ne!(mode(&home.path().join(".npmrc")), 0o600);
+        assert_eq!(mode(&dotfiles.path().join("vimrc")), 0o644);
+    }
+
+    #[test]
+    fn link_items_encrypted_dry_run() {
+        let dotfiles = TempDir::new().unwrap();
+        let home = TempDir::new().unwrap();
+        // Not valid ciphertext, so actually decrypting would fail
+        fs::write(dotfiles.path().join("netrc.age"), "").unwrap();
+        fs::write(dotfiles.path().join("npmrc.gpg"), "").unwrap();
+        fs::write(home.path().join(".npmrc"), "token").unwrap();
+
+        let items = vec![
+            Item::new(
+                dotfiles.path().join("netrc.age"),
+                home.path().join(".netrc"),
+            ),
+            Item::new(
+                dotfiles.path().join("npmrc.gpg"),
+                home.path().join(".npmrc"),
+            ),
+        ];
+        let options = Options {
+            dry_run: true,
+            on_conflict: Some(ConflictPolicy::Overwrite),
+            ..options()
+        };
+        let summary = link_items(FormattedItems::from_items(items), &[], options).unwrap();
+
+        assert_eq!((summary.linked, summary.overwritten), (1, 1));
+        assert!(!home.path().join(".netrc").exists());
+        assert_eq!(
+            fs::read_to_string(home.path().join(".npmrc")).unwrap(),
+            "token"
+        );
+    }
+
+    #[test]
+    fn plan_each_action() {
+        let dotfiles = TempDir::new().unwrap();
+        let home = TempDir::new().unwrap();
+        for name in &["free", "linked", "conflict", "renamed"] {
+            fs::write(dotfiles.path().join(name), "").unwrap();
+        }
+        super::symlink(dotfiles.path().join("linked"), home.path().join("linked")).unwrap();
+        fs::write(home.path().join("conflict"), "").unwrap();
+        super::symlink(dotfiles.path().join("old"), home.path().join("renamed")).unwrap();
+
+        let items = ["free", "linked", "conflict", "renamed"]
+            .iter()
+            .map(|name| Item::new(dotfiles.path().join(name), home.path().join(name)))
+            .collect();
+        let actions = plan(
+            &FormattedItems::from_items(items),
+            &[AbsolutePath::from(dotfiles.path())],
+            options(),
+        )
+        .unwrap();
+
+        assert_eq!(
+            actions,
+            vec![
+                PlannedAction::WouldLink,
+                PlannedAction::AlreadyLinked,
+                PlannedAction::Conflict,
+                PlannedAction::BrokenReplace,
+            ]
+        );
+        // Planning changes nothing
+        assert!(!home.path().join("free").exists());
+        assert_eq!(
+            fs::read_link(home.path().join("renamed")).unwrap(),
+            dotfiles.path().join("old")
+        );
+    }
+
+    #[test]
+    fn link_items_parallel_dry_run() {
+        let dotfiles = TempDir::new().unwrap();
+        let home = TempDir::new().unwrap();
+        fs::write(dotfiles.path().join("vimrc"), "").unwrap();
+
+        let items = vec![Item::new(
+            dotfiles.path().join("vimrc"),
+            home.path().join(".vimrc"),
+        )];
+        let options = Options {
+            dry_run: true,
+            parallel: true,
+            ..options()
+        };
+        let summary = link_items(FormattedItems::from_items(items), &[], options).unwrap();
+
+        assert!(!home.path().join(".vimrc").exists());
+        assert_eq!(
+            summary.to_string(),
+            "1 would be linked, 0 skipped (identical), 0 declined, 0 would be overwritten, 0 \
+             would be backed up"
+        );
+    }
+
+    #[test]
+    fn link_items_dry_run_conflicts() {
+        let dotfiles = TempDir::new().unwrap();
+        let home = TempDir::new().unwrap();
+        for name in &["vimrc", "bashrc"] {
+            fs::write(dotfiles.path().join(name), name).unwrap();
+        }
+        super::symlink(dotfiles.path().join("vimrc"), home.path().join(".vimrc")).unwrap();
+        fs::write(home.path().join(".bashrc"), "mine").unwrap();
+
+        let items = vec![
+            Item::new(dotfiles.path().join("vimrc"), home.path().join(".vimrc")),
+            Item::new(dotfiles.path().join("bashrc"), home.path().join(".bashrc")),
+        ];
+        // Not forced, but a dry run never prompts
+        let options = Options {
+            dry_run: true,
+            backup: true,
+            ..options()
+        };
+        let summary = link_items(FormattedItems::from_items(items), &[], options).unwrap();
+
+        assert_eq!(
+            summary.to_string(),
+            "0 would be linked, 1 skipped (identical), 0 declined, 1 would be overwritten, 1 \
+             would be backed up"
+        );
+        assert_eq!(
+            fs::read_to_string(home.path().join(".bashrc")).unwrap(),
+            "mine"
+        );
+        assert!(!home.path().join(".bashrc.dotman-bak").exists());
+    }
+
+    /// Renaming which always fails as if crossing filesystems
+    fn cross_device_rename(_: &Path, _: &Path) -> io::Result<()> {
+        Err(io::Error::from_raw_os_error(CROSS_DEVICE))
+    }
+
+    #[test]
+    fn move_cross_device() {
+        let dir = TempDir::new().unwrap();
+        let (from, to) = (
+            dir.path().join(".vimrc"),
+            dir.path().join(".vimrc.dotman-bak"),
+        );
+        fs::write(&from, "old").unwrap();
+
+        match move_with(&from, &to, false, cross_device_rename) {
+            Err(Error::CrossDevice { .. }) => (),
+            res => panic!("expected CrossDevice, got {:?}", res),
+        }
+        assert!(from.exists());
+        assert!(!to.exists());
+    }
+
+    #[test]
+    fn move_cross_device_copy() {
+        let dir = TempDir::new().unwrap();
+        let (from, to) = (
+            dir.path().join(".vimrc"),
+            dir.path().join(".vimrc.dotman-bak"),
+        );
+        fs::write(&from, "old").unwrap();
+
+        move_with(&from, &to, true, cross_device_rename).unwrap();
+
+        assert!(!from.exists());
+        assert_eq!(fs::read_to_string(&to).unwrap(), "old");
+    }
+
+    #[test]
+    fn add_file() {
+        let dotfiles = TempDir::new().unwrap();
+        let home = TempDir::new().unwrap();
+        let dest = AbsolutePath::from(home.path().join(".vimrc"));
+        let source = AbsolutePath::from(dotfiles.path().join("vimrc"));
+        fs::write(&dest, "set number").unwrap();
+
+        add(&dest, &source, false).unwrap();
+
+        assert_eq!(fs::read_to_string(&source).unwrap(), "set number");
+        assert_eq!(fs::read_link(&dest).unwrap(), source.to_path_buf());
+    }
+
+    #[test]
+    fn add_existing_source() {
+        let dotfiles = TempDir::new().unwrap();
+        let home = TempDir::new().unwrap();
+        let dest = AbsolutePath::from(home.path().join(".vimrc"));
+        let source = AbsolutePath::from(dotfiles.path().join("vimrc"));
+        fs::write(&dest, "new").unwrap();
+        fs::write(&source, "old").unwrap();
+
+        match add(&dest, &source, false) {
+            Err(Error::SourceExists(path)) => assert_eq!(path, source),
+            res => panic!("expected SourceExists, got {:?}", res),
+        }
+        assert_eq!(fs::read_to_string(&source).unwrap(), "old");
+        assert_eq!(fs::read_to_string(&dest).unwrap(), "new");
+    }
+
+    #[test]
+    fn add_dry_run() {
+        let dotfiles = TempDir::new().unwrap();
+        let home = TempDir::new().unwrap();
+        let dest = AbsolutePath::from(home.path().join(".vimrc"));
+        let source = AbsolutePath::from(dotfiles.path().join("vimrc"));
+        fs::write(&dest, "").unwrap();
+
+        add(&dest, &source, true).unwrap();
+
+        assert!(!source.exists());
+        assert!(dest.is_file());
+    }
+
+    #[test]
+    fn backup_path_unused() {
+        let dir = TempDir::new().unwrap();
+        let dest = dir.path().join(".vimrc");
+        fs::write(&dest, "").unwrap();
+
+        assert_eq!(backup_path(&dest), dir.path().join(".vimrc.dotman-bak"));
+    }
+
+    #[test]
+    fn backup_path_keeps_extension() {
+        let dir = TempDir::new().unwrap();
+        let dest = dir.path().join("config.toml");
+
+        assert_eq!(
+            backup_path(&dest),
+            dir.path().join("config.toml.dotman-bak")
+        );
+    }
+
+    #[test]
+    fn backup_path_avoids_existing_backups() {
+        let dir = TempDir::new().unwrap();
+        let dest = dir.path().join(".vimrc");
+        fs::write(dir.path().join(".vimrc.dotman-bak"), "").unwrap();
+        fs::write(dir.path().join(".vimrc.dotman-bak.1"), "").unwrap();
+
+        assert_eq!(backup_path(&dest), dir.path().join(".vimrc.dotman-bak.2"));
+    }
+
+    #[test]
+    fn diff_text() {
+        let dir = TempDir::new().unwrap();
+        let (source, dest) = (dir.path().join("source"), dir.path().join("dest"));
+        fs::write(&source, "a\nb\n").unwrap();
+        fs::write(&dest, "a\nc\n").unwrap();
+
+        let diff = diff(&source, &dest, MAX_DIFF_LINES).unwrap();
+        let lines: Vec<_> = diff.lines().skip(2).collect();
+        assert_eq!(lines, vec!["@@ -1,2 +1,2 @@", " a", "-c", "+b"]);
+    }
+
+    #[test]
+    fn diff_binary() {
+        let dir = TempDir::new().unwrap();
+        let (source, dest) = (dir.path().join("source"), dir.path().join("dest"));
+        fs::write(&source, [0, 1, 2]).unwrap();
+        fs::write(&dest, "text").unwrap();
+
+        assert!(diff(&source, &dest, MAX_DIFF_LINES)
+            .unwrap()
+            .starts_with("Binary files"));
+    }
+
+    #[test]
+    fn diff_truncated() {
+        let dir = TempDir::new().unwrap();
+        let (source, dest) = (dir.path().join("source"), dir.path().join("dest"));
+        let contents: String = (0..MAX_DIFF_LINES).map(|n| format!("{}\n", n)).collect();
+        fs::write(&source, &contents).unwrap();
+        fs::write(&dest, "").unwrap();
+
+        let diff = diff(&source, &dest, MAX_DIFF_LINES).unwrap();
+        let lines: Vec<_> = diff.lines().collect();
+        assert_eq!(lines.len(), MAX_DIFF_LINES + 1);
+        assert_eq!(lines.last().unwrap(), &"... (3 more lines)");
+    }
+
+    #[test]
+    fn broken_links_only_into_dotfiles() {
+        let dotfiles = TempDir::new().unwrap();
+        let home = TempDir::new().unwrap();
+        fs::write(dotfiles.path().join("vimrc"), "").unwrap();
+        let symlink = |source: &str, dest: &str| super::symlink(source, home.path().join(dest));
+
+        let dotfiles_path = dotfiles.path().display().to_string();
+        symlink(&format!("{}/vimrc", dotfiles_path), ".vimrc").unwrap();
+        symlink(&format!("{}/zshrc", dotfiles_path), ".zshrc").unwrap();
+        symlink("/nonexistent/bashrc", ".bashrc").unwrap();
+
+        assert_eq!(
+            broken_links(home.path(), &[AbsolutePath::from(dotfiles.path())]),
+            vec![AbsolutePath::from(home.path().join(".zshrc"))]
+        );
+    }
+
+    #[test]
+    fn stale_links() {
+        let dotfiles = TempDir::new().unwrap();
+        let home = TempDir::new().unwrap();
+        for name in &["vimrc", "zshrc"] {
+            fs::write(dotfiles.path().join(name), "").unwrap();
+        }
+        fs::create_dir_all(dotfiles.path().join("config/nvim")).unwrap();
+        fs::write(dotfiles.path().join("config/nvim/init.vim"), "").unwrap();
+        fs::create_dir(home.path().join(".config")).unwrap();
+        let symlink = |source: &str, dest: &str| {
+            super::symlink(dotfiles.path().join(source), home.path().join(dest)).unwrap()
+        };
+
+        // Current
+        symlink("vimrc", ".vimrc");
+        symlink("config/nvim/init.vim", ".config/init.vim");
+        // No longer a dotfile, or deleted altogether
+        symlink("zshrc", ".zshrc");
+        symlink("bashrc", ".bashrc");
+        // Left to linking, since a dotfile is linked there
+        symlink("zshrc", ".profile");
+        super::symlink("/elsewhere/gitconfig", home.path().join(".gitconfig")).unwrap();
+
+        let items = vec![
+            Item::new(dotfiles.path().join("vimrc"), home.path().join(".vimrc")),
+            Item::new(
+                dotfiles.path().join("config/nvim"),
+                home.path().join(".config/nvim"),
+            ),
+            Item::new(
+                dotfiles.path().join("profile"),
+                home.path().join(".profile"),
+            ),
+        ];
+        let mut links =
+            super::stale_links(home.path(), &[AbsolutePath::from(dotfiles.path())], &items);
+        links.sort_by(|x, y| x.cmp(y));
+
+        assert_eq!(
+            links,
+            vec![
+                AbsolutePath::from(home.path().join(".bashrc")),
+                AbsolutePath::from(home.path().join(".zshrc")),
+            ]
+        );
+    }
+
+    #[test]
+    fn overwrite_prompt_symlink() {
+        let dir = TempDir::new().unwrap();
+        let (source, dest) = (
+            AbsolutePath::from(dir.path().join("source")),
+            AbsolutePath::from(dir.path().join("dest")),
+        );
+        super::symlink("/elsewhere/vimrc", &dest).unwrap();
+
+        assert_eq!(
+            overwrite_prompt(&source, &dest).unwrap(),
+            format!(
+                "{} is a symlink to /elsewhere/vimrc. Overwrite with {}?",
+                dest, source
+            )
+        );
+    }
+
+    #[test]
+    fn link_items_atomic_rollback() {
+        let dotfiles = TempDir::new().unwrap();
+        let home = TempDir::new().unwrap();
+        for name in &["zshrc", "vimrc", "config"] {
+            fs::write(dotfiles.path().join(name), name).unwrap();
+        }
+        fs::write(home.path().join(".zshrc"), "original").unwrap();
+        fs::create_dir(home.path().join(".config")).unwrap();
+
+        let items = vec![
+            // Overwrites an existing file
+            Item::new(dotfiles.path().join("zshrc"), home.path().join(".zshrc")),
+            // Creates a new directory
+            Item::new(
+                dotfiles.path().join("vimrc"),
+                home.path().join(".vim/vimrc"),
+            ),
+            // Fails, since directories are never overwritten
+            Item::new(dotfiles.path().join("config"), home.path().join(".config")),
+        ];
+        let options = Options {
+            on_conflict: Some(ConflictPolicy::Overwrite),
+            atomic: true,
+            ..options()
+        };
+        assert!(link_items(FormattedItems::from_items(items), &[], options).is_err());
+
+        assert_eq!(
+            fs::read_to_string(home.path().join(".zshrc")).unwrap(),
+            "original"
+        );
+        assert!(!home.path().join(".vim").exists());
+        let mut entries: Vec<_> = fs::read_dir(home.path())
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name())
+            .collect();
+        entries.sort();
+        assert_eq!(entries, vec![".config", ".zshrc"]);
+    }
+
+    #[test]
+    fn rollback_keeps_what_was_in_the_way() {
+        let dotfiles = TempDir::new().unwrap();
+        let home = TempDir::new().unwrap();
+        fs::write(dotfiles.path().join("vimrc"), "dotfile").unwrap();
+        // As if created after checking that the destination was free
+        fs::write(home.path().join(".vimrc"), "original").unwrap();
+
+        let items = FormattedItems::from_items(vec![Item::new(
+            dotfiles.path().join("vimrc"),
+            home.path().join(".vimrc"),
+        )]);
+        for &strategy in &[Strategy::Symlink, Strategy::Copy] {
+            let options = Options {
+                strategy,
+                ..options()
+            };
+            let mut log = vec![];
+            let item = (&items).into_iter().next().unwrap();
+            assert!(super::place(item, &[], options, &mut log).is_err());
+            super::rollback(log);
+
+            assert_eq!(
+                fs::read_to_string(home.path().join(".vimrc")).unwrap(),
+                "original"
+            );
+        }
+    }
+}
@@ -0,0 +1,78 @@
+use crate::common::AbsolutePath;
+use derive_more::From;
+use failure::Fail;
+use fs2::FileExt;
+use std::{
+    fs::{self, File, OpenOptions},
+    io,
+    path::Path,
+};
+
+/// The name of the lock file within the directory passed to `Lock::acquire`
+const LOCK_NAME: &str = "dotman.lock";
+
+/// An advisory lock held while dotman modifies links, so that concurrent runs
+/// don't race on the same destinations.
+///
+/// The lock is released when this is dropped (or the process exits). The lock
+/// file itself is left in place, since removing it could let another process
+/// lock a different file.
+#[derive(Debug)]
+pub struct Lock {
+    _file: File,
+}
+
+impl Lock {
+    /// Takes the lock in `dir`, failing if another dotman process already holds
+    /// it
+    pub fn acquire(dir: &Path) -> Result<Self, Error> {
+        fs::create_dir_all(dir)?;
+        let path = dir.join(LOCK_NAME);
+        let file = OpenOptions::new()
+            .create(true)
+            .truncate(false)
+            .write(true)
+            .open(&path)?;
+
+        match file.try_lock_exclusive() {
+            Ok(()) => Ok(Lock { _file: file }),
+            Err(err) if err.raw_os_error() == fs2::lock_contended_error().raw_os_error() => {
+                Err(Held(AbsolutePath::from(path)))
+            },
+            Err(err) => Err(IoError(err)),
+        }
+    }
+}
+
+#[derive(Debug, From, Fail)]
+pub enum Error {
+    #[fail(
+        display = "another dotman process is running (holding {}). Try again once it's finished.",
+        _0
+    )]
+    Held(AbsolutePath),
+
+    #[fail(display = "error taking lock ({})", _0)]
+    IoError(#[fail(cause)] io::Error),
+}
+use Error::*;
+
+#[cfg(test)]
+mod tests {
+    use super::{Error, Lock};
+    use tempfile::TempDir;
+
+    #[test]
+    fn lock_held() {
+        let dir = TempDir::new().unwrap();
+
+        let lock = Lock::acquire(dir.path()).unwrap();
+        match Lock::acquire(dir.path()) {
+            Err(Error::Held(_)) => (),
+            res => panic!("expected Held, got {:?}", res),
+        }
+
+        drop(lock);
+        assert!(Lock::acquire(dir.path()).is_ok());
+    }
+}
@@ -0,0 +1,248 @@
+//! Reusing the dotfiles resolved (and the globs expanded) by an earlier run,
+//! for large dotfiles folders
+
+use super::Error;
+use crate::{
+    common::{util, AbsolutePath, Item},
+    config::{self, Config, Expansions},
+    verbose_println,
+};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use std::{
+    fmt::Debug,
+    fs,
+    hash::{Hash, Hasher},
+    path::{Path, PathBuf},
+};
+use walkdir::WalkDir;
+
+/// The file (within the cache directory) which resolved dotfiles are cached in
+const CACHE_FILE: &str = "resolved.json";
+/// The file (within the cache directory) which expanded globs are cached in
+const EXPANSIONS_FILE: &str = "expanded.json";
+
+#[derive(Debug, Serialize, Deserialize)]
+struct Cache {
+    /// The `key` of the configuration and dotfiles folders the items were
+    /// resolved from
+    key: u64,
+    items: Vec<CachedItem>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct CachedItem {
+    source: PathBuf,
+    dest: PathBuf,
+    origin: Option<PathBuf>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct CachedExpansions {
+    /// As for `Cache`
+    key: u64,
+    excludes: Vec<PathBuf>,
+    dead_excludes: Vec<PathBuf>,
+    includes: Option<Vec<PathBuf>>,
+}
+
+/// Like `resolver::get_items`, but if `config.cache` is set, the items found
+/// by the last run are reused as long as neither the configuration nor anything
+/// in the dotfiles folders has changed since.
+///
+/// Requirements' commands aren't rerun while the cache is reused.
+pub fn get_items(config: &Config) -> Result<Vec<Item>, Error> {
+    if !config.cache {
+        return super::get_items(config);
+    }
+
+    get_items_in(config, &util::cache_dir().join(CACHE_FILE))
+}
+
+/// Does the work of `get_items`, caching the items at `cache_path`
+pub(super) fn get_items_in(config: &Config, cache_path: &Path) -> Result<Vec<Item>, Error> {
+    let key = key(config);
+    let cached = read::<Cache>(cache_path)
+        .filter(|cache| cache.key == key)
+        .and_then(|cache| {
+            cache
+                .items
+                .into_iter()
+                .map(|item| {
+                    Some(Item {
+                        origin: item.origin,
+                        source: absolute(item.source)?,
+                        dest: absolute(item.dest)?,
+                    })
+                })
+                .collect::<Option<Vec<_>>>()
+        });
+    if let Some(items) = cached {
+        verbose_println!("Using the dotfiles cached in {}", cache_path.display());
+        return Ok(items);
+    }
+
+    let items = super::get_items(config)?;
+    let cache = Cache {
+        key,
+        items: items
+            .iter()
+            .map(|item| CachedItem {
+                source: item.source.to_path_buf(),
+                dest: item.dest.to_path_buf(),
+                origin: item.origin.clone(),
+            })
+            .collect(),
+    };
+    write(&cache, cache_path, "dotfiles");
+
+    Ok(items)
+}
+
+/// Like `config::Expansions::of`, but if `config.cache` is set, the expansions
+/// found by the last run are reused as for `get_items`. That spares walking the
+/// dotfiles folders to expand them.
+pub fn get_expansions(config: &Config) -> Result<Expansions, config::Error> {
+    if !config.cache {
+        return Expansions::of(config);
+    }
+
+    get_expansions_in(config, &util::cache_dir().join(EXPANSIONS_FILE))
+}
+
+/// Does the work of `get_expansions`, caching the expansions at `cache_path`
+pub(super) fn get_expansions_in(
+    config: &Config,
+    cache_path: &Path,
+) -> Result<Expansions, config::Error> {
+    let key = key(config);
+    let cached = read::<CachedExpansions>(cache_path)
+        .filter(|cache| cache.key == key)
+        .and_then(|cache| {
+            let absolute_all = |paths: Vec<PathBuf>| -> Option<Vec<_>> {
+                paths.into_iter().map(absolute).collect()
+            };
+
+            Some(Expansions {
+                excludes: absolute_all(cache.excludes)?,
+                dead_excludes: cache.dead_excludes,
+                includes: match cache.includes {
+                    Some(includes) => Some(absolute_all(includes)?),
+                    None => None,
+                },
+            })
+        });
+    if let Some(expansions) = cached {
+        verbose_println!("Using the globs expanded in {}", cache_path.display());
+        return Ok(expansions);
+    }
+
+    let expansions = Expansions::of(config)?;
+    let paths = |paths: &[AbsolutePath]| paths.iter().map(|path| path.to_path_buf()).collect();
+    let cache = CachedExpansions {
+        key,
+        excludes: paths(&expansions.excludes),
+        dead_excludes: expansions.dead_excludes.clone(),
+        includes: expansions.includes.as_deref().map(paths),
+    };
+    write(&cache, cache_path, "expanded globs");
+
+    Ok(expansions)
+}
+
+/// Reads the cache at `cache_path`, if there's a readable one
+fn read<T: DeserializeOwned>(cache_path: &Path) -> Option<T> {
+    let cache = fs::read(cache_path).ok()?;
+
+    serde_json::from_slice(&cache).ok()
+}
+
+/// Writes `cache` to `cache_path`. What's cached is still usable without the
+/// cache, so failing to write it only warns (naming `what` wasn't cached).
+fn write(cache: &impl Serialize, cache_path: &Path, what: &str) {
+    let written = serde_json::to_vec(cache)
+        .map_err(|err| err.to_string())
+        .and_then(|cache| {
+            fs::create_dir_all(cache_path.parent().unwrap_or(cache_path))
+                .and_then(|()| fs::write(cache_path, cache))
+                .map_err(|err| err.to_string())
+        });
+    if let Err(err) = written {
+        eprintln!(
+            "Warning: couldn't cache the {} in {} ({})",
+            what,
+            cache_path.display(),
+            err
+        );
+    }
+}
+
+/// Converts a path read from a cache, which might have been edited (or written
+/// by something else entirely), giving `None` unless it's absolute
+fn absolute(path: PathBuf) -> Option<AbsolutePath> {
+    if path.is_absolute() {
+        Some(AbsolutePath::from(path))
+    } else {
+        None
+    }
+}
+
+/// A fingerprint of everything resolving the dotfiles (and expanding globs)
+/// depends on: the options which affect it, along with the path, type, size,
+/// and modification time of everything in the dotfiles folders.
+///
+/// Hidden folders (like `.git`) are never searched for dotfiles, so they're
+/// skipped. Hidden files are kept, since the `.gitignore` and `.dotignore`
+/// files among them affect what's excluded.
+fn key(config: &Config) -> u64 {
+    let mut hasher = util::StableHasher::default();
+    // Newer versions may resolve the same dotfiles differently
+    env!("CARGO_PKG_VERSION").hash(&mut hasher);
+    // Not everything here implements `Hash` (globs in particular), but their
+    // debug representations are as good
+    let options: [&dyn Debug; 16] = [
+        &config.raw_globs,
+        &config.dest_excludes,
+        &config.tags,
+        &config.dotfiles_paths,
+        &config.hostnames,
+        &config.platform,
+        &config.arch,
+        &config.target,
+        &config.links,
+        &config.prefix_roots,
+        &config.prefixes,
+        &config.no_dot,
+        &config.link_first,
+        &config.link_last,
+        &config.requirements,
+        &config.follow_source_links,
+    ];
+    for option in &options {
+        format!("{:?}", option).hash(&mut hasher);
+    }
+
+    for dotfiles_path in &config.dotfiles_paths {
+        let entries = WalkDir::new(dotfiles_path)
+            .follow_links(config.follow_source_links)
+            .sort_by(|x, y| x.file_name().cmp(y.file_name()))
+            .into_iter()
+            .filter_entry(|entry| {
+                entry.depth() == 0
+                    || !(util::is_hidden(entry.file_name()) && entry.file_type().is_dir())
+            })
+            // Anything unreadable is left to the resolver to report
+            .filter_map(Result::ok);
+        for entry in entries {
+            entry.path().hash(&mut hasher);
+            let metadata = match entry.metadata() {
+                Ok(metadata) => metadata,
+                Err(_) => continue,
+            };
+            (metadata.is_dir(), metadata.file_type().is_symlink()).hash(&mut hasher);
+            metadata.len().hash(&mut hasher);
+            metadata.modified().ok().hash(&mut hasher);
+        }
+    }
+
+    hasher.finish()
+}
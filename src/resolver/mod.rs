@@ -1,18 +1,36 @@
+pub mod cache;
+
 use crate::{
-    common::{util, AbsolutePath, Item},
-    config::Config,
-    verbose_println,
+    common::{util, AbsolutePath, Item, Platform},
+    config::{Config, Link, PrefixRoot},
+    hook, secret, template, verbose_println,
 };
 use derive_more::From;
 use failure::Fail;
+use globset::GlobSet;
 use std::{
-    collections::HashSet,
-    ffi::OsString,
+    collections::{HashMap, HashSet},
+    ffi::{OsStr, OsString},
+    fmt::{self, Display},
     io, iter,
     path::{Path, PathBuf},
 };
+use strum::IntoEnumIterator;
+use strum_macros::EnumIter;
 use walkdir::WalkDir;
 
+/// Directories containing a file with this name are linked as a single unit
+/// rather than having each of their files linked individually.
+pub const DIR_MARKER: &str = ".dotman-dir";
+
+/// Separates the tags of a `tag-` directory which requires several tags, as in
+/// `tag-work+linux`.
+pub const TAG_SEPARATOR: char = '+';
+
+/// Separates the alternatives of a `platform-` directory which applies to any
+/// of several platforms, as in `platform-macos+linux`.
+pub const PLATFORM_SEPARATOR: char = '+';
+
 /// Appends a "." to the start of `path`
 fn make_hidden(path: &Path) -> PathBuf {
     let path_str = OsString::from(path.as_os_str());
@@ -26,27 +44,166 @@ fn make_hidden(path: &Path) -> PathBuf {
     PathBuf::from(hidden_path)
 }
 
+/// Checks whether the top-level component of `path` (relative to the folder
+/// it's linked from) matches `no_dot`
+fn is_no_dot(path: &Path, no_dot: &GlobSet) -> bool {
+    path.iter().next().is_some_and(|top| no_dot.is_match(top))
+}
+
+/// Finds the path relative to the target folder which `path` (relative to the
+/// folder it's linked from) is linked to by default
+fn default_dest(path: &Path, no_dot: &GlobSet) -> PathBuf {
+    if is_no_dot(path, no_dot) {
+        path.to_path_buf()
+    } else {
+        make_hidden(path)
+    }
+}
+
+/// Finds where a dotfile would need to be in `dotfiles_path` to be linked to
+/// `dest` by default (i.e. without a custom destination). This is the reverse
+/// of the mapping `link_dir_contents` uses.
+///
+/// Only hidden paths in `target`, or those matching `no_dot`, can be reached
+/// this way.
+pub fn default_source(
+    dest: &AbsolutePath,
+    target: &AbsolutePath,
+    dotfiles_path: &AbsolutePath,
+    no_dot: &GlobSet,
+) -> Result<AbsolutePath, Error> {
+    let not_hidden = || NotHiddenInTarget {
+        path: dest.clone(),
+        target: target.clone(),
+    };
+
+    let tail = dest.strip_prefix(target).map_err(|_| not_hidden())?;
+    if is_no_dot(tail, no_dot) {
+        return Ok(AbsolutePath::from(dotfiles_path.join(tail)));
+    }
+    let tail = util::strip_os_prefix(tail.as_os_str(), ".")
+        .filter(|tail| !tail.is_empty())
+        .ok_or_else(not_hidden)?;
+
+    Ok(AbsolutePath::from(dotfiles_path.join(tail)))
+}
+
+/// The folder which dotfiles are linked into by default, and whether they're
+/// hidden there
+#[derive(Debug, Clone, Copy)]
+struct DestRoot<'a> {
+    dir: &'a AbsolutePath,
+    hidden: bool,
+}
+
+impl<'a> DestRoot<'a> {
+    /// Finds where the dotfiles in the prefixed folder `origin` (if any) are
+    /// linked: the root of the innermost prefixed folder in `prefix_roots`, or
+    /// hidden paths in `target` if there is none
+    fn new(
+        origin: Option<&Path>,
+        target: &'a AbsolutePath,
+        prefix_roots: &'a [PrefixRoot],
+    ) -> Self {
+        origin
+            .into_iter()
+            .flat_map(|origin| origin.iter().rev())
+            .find_map(|name| prefix_roots.iter().find(|root| name == root.name.as_str()))
+            .map_or(
+                DestRoot {
+                    dir: target,
+                    hidden: true,
+                },
+                |root| DestRoot {
+                    dir: &root.root,
+                    hidden: root.hidden,
+                },
+            )
+    }
+}
+
+/// Finds where the dotfile at `path` is linked to, given its path `dest_tail`
+/// relative to the folder it's linked from (see `link_dir_contents`)
+fn item_dest(
+    path: &AbsolutePath,
+    dest_tail: &Path,
+    root: DestRoot<'_>,
+    links: &[Link],
+    no_dot: &GlobSet,
+) -> AbsolutePath {
+    // Templates and encrypted files are linked without their extension
+    let strip_extension = |dest: PathBuf| {
+        if template::is_template(path) || secret::is_encrypted(path) {
+            AbsolutePath::from(dest.with_extension(""))
+        } else {
+            AbsolutePath::from(dest)
+        }
+    };
+
+    match links.iter().find(|link| link.matches(dest_tail)) {
+        Some(link) if link.into_dir => strip_extension(
+            link.dest
+                .join(path.file_name().expect("entry must have a file name")),
+        ),
+        Some(link) => link.dest.clone(),
+        None if root.hidden => strip_extension(root.dir.join(default_dest(dest_tail, no_dot))),
+        None => strip_extension(root.dir.join(dest_tail)),
+    }
+}
+
 /// Returns every non-hidden non-excluded file in `dir` (recursively, ignoring
 /// directories).
+///
+/// Directories marked with a `DIR_MARKER` file are returned as a single item
+/// instead of being descended into. Symlinked directories are only descended
+/// into if `follow_links` is set.
+///
+/// Each item is linked to the destination given by the first of `links` which
+/// matches it, or to the corresponding path in `root` if none do. That path is
+/// hidden if `root` says so, unless it matches `no_dot`.
 fn link_dir_contents(
     dir: &AbsolutePath,
+    root: DestRoot<'_>,
     excludes: &HashSet<&AbsolutePath>,
+    links: &[Link],
+    no_dot: &GlobSet,
+    origin: Option<&Path>,
+    follow_links: bool,
 ) -> Result<Vec<Item>, Error> {
     let mut res = vec![];
-    for entry in WalkDir::new(dir)
+    let mut entries = WalkDir::new(dir)
+        .follow_links(follow_links)
         .into_iter()
-        .filter_entry(|entry| !util::is_hidden(entry.file_name()))
-    {
-        let entry = entry?;
+        .filter_entry(|entry| !util::is_hidden(entry.file_name()));
+    while let Some(entry) = entries.next() {
+        let entry = match entry {
+            Ok(entry) => entry,
+            Err(ref err) if err.loop_ancestor().is_some() => {
+                verbose_println!(
+                    "Skipping {}, which loops back to a folder already searched",
+                    util::home_to_tilde(err.path().unwrap_or(dir)).display()
+                );
+                continue;
+            },
+            Err(err) => return Err(err.into()),
+        };
 
         let path = AbsolutePath::from(entry.path());
+        if entry.path_is_symlink() && !follow_links && path.is_dir() {
+            skip_symlinked_dir(&path);
+        }
 
         if excludes.contains(&path) {
             verbose_println!("Excluded {}", path);
         }
 
+        let is_marked_dir = entry.file_type().is_dir() && path.join(DIR_MARKER).exists();
+        // Symlinked files are linked like any other file, even when symlinks
+        // aren't followed
+        let is_file = entry.file_type().is_file() || (entry.path_is_symlink() && path.is_file());
+
         if !util::is_hidden(entry.file_name())
-            && entry.file_type().is_file()
+            && (is_file || is_marked_dir)
             && !excludes.contains(&path)
         {
             let dest = {
@@ -57,26 +214,240 @@ fn link_dir_contents(
                         .expect("dir must be a prefix of entry"),
                 };
 
-                AbsolutePath::from(util::home_dir().join(make_hidden(dest_tail)))
+                item_dest(&path, dest_tail, root, links, no_dot)
             };
             let source = path;
 
-            res.push(Item::new(source, dest));
+            res.push(Item {
+                source,
+                dest,
+                origin: origin.map(PathBuf::from),
+            });
+        }
+
+        if is_marked_dir {
+            entries.skip_current_dir();
         }
     }
 
     Ok(res)
 }
 
+/// The default prefix of `host-` directories, which are active on the given
+/// host
+pub const HOSTNAME_PREFIX: &str = "host-";
+/// The default prefix of `tag-` directories, which are active with the given
+/// tags
+pub const TAG_PREFIX: &str = "tag-";
+/// The default prefix of `platform-` directories, which are active on the
+/// given platforms
+pub const PLATFORM_PREFIX: &str = "platform-";
+/// The default prefix of `arch-` directories, which are active on the given
+/// CPU architecture
+pub const ARCH_PREFIX: &str = "arch-";
+
+/// The kinds of prefixed directories, which are only searched for items under
+/// some configurations
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, EnumIter)]
+pub enum PrefixKind {
+    Host,
+    Tag,
+    Platform,
+    Arch,
+}
+
+/// The prefix each kind of prefixed directory's name starts with, which the
+/// dotrc can override for dotfiles folders with other conventions (as in
+/// `tag_work`)
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PrefixNames {
+    pub host: String,
+    pub tag: String,
+    pub platform: String,
+    pub arch: String,
+}
+
+impl Default for PrefixNames {
+    fn default() -> Self {
+        PrefixNames {
+            host: String::from(HOSTNAME_PREFIX),
+            tag: String::from(TAG_PREFIX),
+            platform: String::from(PLATFORM_PREFIX),
+            arch: String::from(ARCH_PREFIX),
+        }
+    }
+}
+
+impl PrefixNames {
+    pub fn get(&self, kind: PrefixKind) -> &str {
+        match kind {
+            PrefixKind::Host => &self.host,
+            PrefixKind::Tag => &self.tag,
+            PrefixKind::Platform => &self.platform,
+            PrefixKind::Arch => &self.arch,
+        }
+    }
+
+    /// Splits `filename` into the kind of prefixed directory it names and the
+    /// rest of its name, as in `Tag` and `work` for `tag-work`. If more than
+    /// one prefix matches, the longest wins. If it cannot be read as a String,
+    /// only its kind is known (see `kind`), so this gives `None`.
+    pub fn split<'a>(&self, filename: &'a Path) -> Option<(PrefixKind, &'a str)> {
+        let s = filename.to_str()?;
+        PrefixKind::iter()
+            .filter_map(|kind| Some((kind, s.strip_prefix(self.get(kind))?)))
+            .min_by_key(|(_, rest)| rest.len())
+    }
+
+    /// The kind of prefixed directory `filename` names, as with `split`, but
+    /// whether or not the rest of it is valid Unicode
+    pub fn kind(&self, filename: &OsStr) -> Option<PrefixKind> {
+        let filename = filename.as_encoded_bytes();
+        PrefixKind::iter()
+            .filter(|&kind| filename.starts_with(self.get(kind).as_bytes()))
+            .max_by_key(|&kind| self.get(kind).len())
+    }
+}
+
+/// Decides which prefixed (`host-`, `tag-`, `platform-`, and `arch-`)
+/// directories are searched for items under a configuration
+#[derive(Debug)]
+pub struct Prefixes {
+    names: PrefixNames,
+    active_dirs: HashSet<PathBuf>,
+    tags: HashSet<String>,
+    platform: Platform,
+}
+
+impl Prefixes {
+    pub fn new(config: &Config) -> Self {
+        let hostname_dirs = config
+            .hostnames
+            .iter()
+            .map(|hostname| PathBuf::from([config.prefixes.host.as_str(), hostname].concat()));
+
+        let arch_dir = PathBuf::from([config.prefixes.arch.as_str(), &config.arch].concat());
+
+        let active_dirs = hostname_dirs.chain(iter::once(arch_dir)).collect();
+
+        let tags = config.tags.iter().cloned().collect();
+
+        Prefixes {
+            names: config.prefixes.clone(),
+            active_dirs,
+            tags,
+            platform: config.platform,
+        }
+    }
+
+    /// Checks if `filename` starts with any of the prefixes (see
+    /// `PrefixNames::kind`)
+    pub fn is_prefixed(&self, filename: &Path) -> bool {
+        self.names.kind(filename.as_os_str()).is_some()
+    }
+
+    /// Checks if a prefixed directory should be searched for items.
+    /// `tag-` directories are active only if every one of their
+    /// `TAG_SEPARATOR`-separated tags is enabled, while `platform-` directories
+    /// are active if any of their `PLATFORM_SEPARATOR`-separated platforms is
+    /// the current one (see `Platform::is_named`).
+    ///
+    /// Hostnames, tags, and such are always valid Unicode, so directories whose
+    /// names aren't can never be active.
+    pub fn is_active(&self, filename: &Path) -> bool {
+        if self.active_dirs.contains(filename) {
+            return true;
+        }
+        if filename.to_str().is_none() {
+            verbose_println!(
+                "Warning: skipping prefixed folder {}, since its name isn't valid Unicode",
+                filename.display()
+            );
+            return false;
+        }
+
+        match self.names.split(filename) {
+            Some((PrefixKind::Tag, tags)) => {
+                tags.split(TAG_SEPARATOR).all(|tag| self.tags.contains(tag))
+            },
+            Some((PrefixKind::Platform, platforms)) => platforms
+                .split(PLATFORM_SEPARATOR)
+                .any(|platform| self.platform.is_named(platform)),
+            _ => false,
+        }
+    }
+}
+
+/// Records that `dir` is being searched, returning whether it hadn't been
+/// already. `visited` holds the canonical path of each directory searched.
+///
+/// Symlinks can lead back into a directory which is already being searched,
+/// so this is what keeps searches from looping forever.
+fn visit(dir: &AbsolutePath, visited: &mut HashSet<PathBuf>) -> bool {
+    let is_new = match dir.canonicalize() {
+        Ok(dir) => visited.insert(dir.to_path_buf()),
+        // Nothing can be searched in a directory which can't be resolved anyway
+        Err(_) => true,
+    };
+    if !is_new {
+        verbose_println!(
+            "Skipping {}, which loops back to a folder already searched",
+            dir
+        );
+    }
+
+    is_new
+}
+
+/// Notes that the symlinked directory `path` isn't searched, since
+/// `follow_source_links` isn't set
+fn skip_symlinked_dir(path: &AbsolutePath) {
+    verbose_println!(
+        "Skipping symlinked folder {} (see --follow-source-links)",
+        path
+    );
+}
+
+/// Options shared by every step of `find_items`' search
+struct Search<'a> {
+    target: &'a AbsolutePath,
+    prefixes: &'a Prefixes,
+    excludes: &'a HashSet<&'a AbsolutePath>,
+    links: &'a [Link],
+    prefix_roots: &'a [PrefixRoot],
+    no_dot: &'a GlobSet,
+    /// Whether symlinked directories are searched
+    follow_links: bool,
+    /// See `visit`
+    visited: HashSet<PathBuf>,
+}
+
 /// Finds the items under `path` which are to be symlinked, according to all the
 /// options specified, and place then in `res`
+///
+/// Active prefixed directories are searched with this too (rather than with
+/// `link_dir_contents`), so prefixed directories nested within them are only
+/// searched if they're active as well.
+///
+/// `origin` is the prefixed folder (relative to the dotfiles folder) `root` is
+/// in, if any.
 fn find_items(
     root: AbsolutePath,
-    is_prefixed: &impl Fn(&Path) -> bool,
-    active_prefixed_dirs: &HashSet<&Path>,
-    excludes: &HashSet<&AbsolutePath>,
+    search: &mut Search<'_>,
+    origin: Option<&Path>,
     res: &mut Vec<Item>,
 ) -> Result<(), Error> {
+    if !visit(&root, &mut search.visited) {
+        return Ok(());
+    }
+
+    let (target, prefixes, excludes, links, no_dot) = (
+        search.target,
+        search.prefixes,
+        search.excludes,
+        search.links,
+        search.no_dot,
+    );
     for entry in root.read_dir()? {
         let entry = entry?;
         let path = AbsolutePath::from(entry.path());
@@ -92,12 +463,31 @@ fn find_items(
             continue;
         }
 
-        if is_prefixed(&entry_name) {
-            if active_prefixed_dirs.contains(entry_name) {
-                find_items(path, is_prefixed, active_prefixed_dirs, excludes, res)?;
+        // Symlinked directories are only searched if asked to be, as for excludes
+        // (see `config::walk`)
+        if !search.follow_links && entry.file_type()?.is_symlink() && path.is_dir() {
+            skip_symlinked_dir(&path);
+            continue;
+        }
+
+        if prefixes.is_prefixed(entry_name) {
+            if prefixes.is_active(entry_name) {
+                let origin = match origin {
+                    Some(origin) => origin.join(entry_name),
+                    None => PathBuf::from(entry_name),
+                };
+                find_items(path, search, Some(&origin), res)?;
             }
         } else {
-            let contents = link_dir_contents(&AbsolutePath::from(entry.path()), excludes)?;
+            let contents = link_dir_contents(
+                &AbsolutePath::from(entry.path()),
+                DestRoot::new(origin, target, search.prefix_roots),
+                excludes,
+                links,
+                no_dot,
+                origin,
+                search.follow_links,
+            )?;
             res.extend(contents);
         }
     }
@@ -105,75 +495,660 @@ fn find_items(
     Ok(())
 }
 
-pub fn get_items(config: &Config) -> Result<Vec<Item>, Error> {
-    let hostname_prefix = "host-";
-    let tag_prefix = "tag-";
-    let platform_prefix = "platform-";
-    let prefixes = [hostname_prefix, tag_prefix, platform_prefix];
-
-    // Checks if a path is prefixed by any element of `prefixes`
-    // If the path cannot be read as a String, assume it isn't.
-    let is_prefixed = |filename: &Path| -> bool {
-        for prefix in &prefixes {
-            match filename.to_str() {
-                Some(s) if s.starts_with(prefix) => return true,
-                _ => continue,
-            }
+/// Finds the prefixed directories under `root` which `get_items` considers,
+/// along with whether each is active.
+///
+/// As with `get_items`, only active directories are searched for further
+/// prefixed directories.
+pub fn prefixed_dirs(
+    root: &AbsolutePath,
+    prefixes: &Prefixes,
+    excludes: &[AbsolutePath],
+) -> Result<Vec<(AbsolutePath, bool)>, Error> {
+    let mut res = find_prefixed_dirs(root, prefixes, excludes, false, &mut HashSet::new())?;
+    res.sort_by(|(x, _), (y, _)| x.as_path().cmp(y.as_path()));
+
+    Ok(res)
+}
+
+/// Like `prefixed_dirs`, but inactive directories are searched too, so that
+/// every prefixed directory under `root` is found. Each is marked with whether
+/// it's active itself, whether or not the directories it's in are.
+pub fn all_prefixed_dirs(
+    root: &AbsolutePath,
+    prefixes: &Prefixes,
+    excludes: &[AbsolutePath],
+) -> Result<Vec<(AbsolutePath, bool)>, Error> {
+    let mut res = find_prefixed_dirs(root, prefixes, excludes, true, &mut HashSet::new())?;
+    res.sort_by(|(x, _), (y, _)| x.as_path().cmp(y.as_path()));
+
+    Ok(res)
+}
+
+/// Does the work of `prefixed_dirs` (or `all_prefixed_dirs`, if `all` is set),
+/// without sorting. `visited` is as for `visit`.
+fn find_prefixed_dirs(
+    root: &AbsolutePath,
+    prefixes: &Prefixes,
+    excludes: &[AbsolutePath],
+    all: bool,
+    visited: &mut HashSet<PathBuf>,
+) -> Result<Vec<(AbsolutePath, bool)>, Error> {
+    let mut res = vec![];
+    if !visit(root, visited) {
+        return Ok(res);
+    }
+
+    for entry in root.read_dir()? {
+        let entry = entry?;
+        let path = AbsolutePath::from(entry.path());
+
+        let entry_name = entry.file_name();
+        let entry_name = Path::new(&entry_name);
+        if util::is_hidden(entry_name.as_os_str())
+            || excludes.contains(&path)
+            || !prefixes.is_prefixed(entry_name)
+        {
+            continue;
         }
 
-        false
-    };
+        if all && !path.is_dir() {
+            continue;
+        }
+
+        let active = prefixes.is_active(entry_name);
+        res.push((path.clone(), active));
+        if active || all {
+            res.extend(find_prefixed_dirs(&path, prefixes, excludes, all, visited)?);
+        }
+    }
+
+    Ok(res)
+}
 
-    let hostname_dir = PathBuf::from([hostname_prefix, &config.hostname].concat());
+/// Finds the active items under `config`, sorted by destination (apart from
+/// those pinned by `config.link_first` and `config.link_last`).
+///
+/// The commands of `config.requirements` are run in the system shell (see
+/// `hook::succeeds`).
+pub fn get_items(config: &Config) -> Result<Vec<Item>, Error> {
+    get_items_with(config, &mut hook::succeeds)
+}
 
-    let platform_dirs: Vec<PathBuf> = config
-        .platform
-        .strs()
+/// The path of `item`'s source relative to the folder it's linked from (see
+/// `Link`), as in `vim/vimrc` for `~/.dotfiles/host-foo/vim/vimrc`
+fn linked_path<'a>(item: &'a Item, dotfiles_paths: &[AbsolutePath]) -> &'a Path {
+    let path = dotfiles_paths
         .iter()
-        .map(|platform| PathBuf::from([platform_prefix, platform].concat()))
-        .collect();
+        .find_map(|dotfiles_path| item.source.strip_prefix(dotfiles_path).ok())
+        .unwrap_or(&item.source);
+
+    match &item.origin {
+        Some(origin) => path.strip_prefix(origin).unwrap_or(path),
+        None => path,
+    }
+}
 
-    let tag_dirs: Vec<PathBuf> = config
-        .tags
+/// The permission bits `item`'s source is given after linking: those of the
+/// first of `config.modes` matching it (as for `links`), if any
+pub fn mode_of(item: &Item, config: &Config) -> Option<u32> {
+    let path = linked_path(item, &config.dotfiles_paths);
+    config
+        .modes
         .iter()
-        .map(|tag| PathBuf::from([tag_prefix, tag].concat()))
-        .collect();
+        .find(|mode| mode.matches(path))
+        .map(|mode| mode.mode)
+}
 
-    let active_prefixed_dirs: HashSet<&Path> = iter::once(&hostname_dir)
-        .chain(tag_dirs.iter())
-        .chain(platform_dirs.iter())
-        .map(|p| p.as_path())
-        .collect();
+/// Checks whether the source of `item` (relative to the dotfiles folder it's
+/// in), or any folder it's in, matches `globs`
+pub fn source_matches(item: &Item, globs: &GlobSet, dotfiles_paths: &[AbsolutePath]) -> bool {
+    dotfiles_paths
+        .iter()
+        .find_map(|dotfiles_path| item.source.strip_prefix(dotfiles_path).ok())
+        .is_some_and(|path| {
+            path.ancestors()
+                .take_while(|path| !path.as_os_str().is_empty())
+                .any(|path| globs.is_match(path))
+        })
+}
+
+/// Does the work of `get_items`, checking whether requirements' commands
+/// succeed with `succeeds`. Each command is only checked once.
+fn get_items_with(
+    config: &Config,
+    succeeds: &mut dyn FnMut(&str) -> bool,
+) -> Result<Vec<Item>, Error> {
+    let prefixes = Prefixes::new(config);
 
     let excludes = config.excludes.iter().collect();
 
     let mut res = vec![];
 
-    find_items(
-        config.dotfiles_path.clone(),
-        &is_prefixed,
-        &active_prefixed_dirs,
-        &excludes,
-        &mut res,
-    )?;
-
-    // Check for duplicate destinations
-    let dests = res.iter().map(|item| &item.dest);
-    if let Some(duplicate_dest) = util::find_duplicate(dests) {
-        return Err(DuplicateFiles {
-            dest: duplicate_dest.clone(),
+    // Later dotfiles folders don't override earlier ones. Instead, any destination
+    // provided by more than one folder is reported as a duplicate below.
+    for dotfiles_path in &config.dotfiles_paths {
+        // Most likely a new machine or a typo, either of which deserves a clearer
+        // explanation than whatever reading the folder would fail with
+        if !dotfiles_path.is_dir() {
+            return Err(DotfilesDirMissing(dotfiles_path.clone()));
+        }
+
+        let mut search = Search {
+            target: &config.target,
+            prefixes: &prefixes,
+            excludes: &excludes,
+            links: &config.links,
+            prefix_roots: &config.prefix_roots,
+            no_dot: &config.no_dot,
+            follow_links: config.follow_source_links,
+            visited: HashSet::new(),
+        };
+        find_items(dotfiles_path.clone(), &mut search, None, &mut res)?;
+    }
+
+    // If there are includes, drop everything outside of them
+    if let Some(includes) = &config.includes {
+        res.retain(|item| {
+            includes
+                .iter()
+                .any(|include| item.source.starts_with(include))
         });
     }
 
+    // Drop anything linked to an excluded destination
+    res.retain(|item| {
+        let excluded = item
+            .dest
+            .ancestors()
+            .any(|dest| config.dest_excludes.is_match(dest));
+        if excluded {
+            verbose_println!(
+                "Excluded {} (its destination {} is excluded)",
+                item.source,
+                item.dest
+            );
+        }
+
+        !excluded
+    });
+
+    // Drop anything whose requirements aren't met. This happens before checking
+    // for duplicates, so that alternatives for a destination can require different
+    // things.
+    let mut results = HashMap::new();
+    res.retain(|item| {
+        config
+            .requirements
+            .iter()
+            .filter(|requirement| requirement.matches(linked_path(item, &config.dotfiles_paths)))
+            .all(|requirement| {
+                let command = requirement.command.as_str();
+                let met = *results.entry(command).or_insert_with(|| succeeds(command));
+                if !met {
+                    verbose_println!("Skipping {}, since `{}` failed", item.source, command);
+                }
+
+                met
+            })
+    });
+
+    // Check for duplicate destinations, reporting every one at once
+    let mut items_by_dest: HashMap<_, Vec<_>> = HashMap::new();
+    for item in &res {
+        items_by_dest.entry(&item.dest).or_default().push(item);
+    }
+    let mut collisions: Vec<_> = items_by_dest
+        .into_iter()
+        .filter(|(_, items)| items.len() > 1)
+        .map(|(dest, items)| Collision {
+            dest: dest.clone(),
+            sources: items
+                .iter()
+                .map(|item| (item.source.clone(), Origin(item.origin.clone())))
+                .collect(),
+        })
+        .collect();
+    if !collisions.is_empty() {
+        collisions.sort_by(|x, y| x.dest.as_path().cmp(y.dest.as_path()));
+        return Err(DuplicateFiles(Collisions(collisions)));
+    }
+
+    // `read_dir`'s order depends on the filesystem, so sort for output (and
+    // linking) which is the same everywhere
+    res.sort_by(|x, y| x.dest.as_path().cmp(y.dest.as_path()));
+
+    // Then move anything pinned to the front or back, keeping the order above
+    // among dotfiles pinned by the same glob
+    res.sort_by_cached_key(|item| {
+        let path = linked_path(item, &config.dotfiles_paths);
+        let first_glob = |globs: &GlobSet| globs.matches(path).into_iter().min();
+        match (
+            first_glob(&config.link_first),
+            first_glob(&config.link_last),
+        ) {
+            (Some(i), _) => (0, i),
+            (None, Some(i)) => (2, i),
+            (None, None) => (1, 0),
+        }
+    });
+
     Ok(res)
 }
 
+/// A check `get_items` makes on its way to a dotfile, as recorded by `trace`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Step {
+    /// The path is hidden, so it isn't searched
+    Hidden(AbsolutePath),
+
+    Excluded(AbsolutePath),
+
+    /// The path is a symlinked directory, which isn't searched without
+    /// `follow_source_links`
+    Symlink(AbsolutePath),
+
+    /// The path is a prefixed directory, which is only searched if it's active
+    Prefixed {
+        path: AbsolutePath,
+        active: bool,
+    },
+
+    /// The path is a directory marked with `DIR_MARKER`, so it's linked as a
+    /// single unit
+    Marked(AbsolutePath),
+
+    /// The dotfile isn't within any of the includes
+    NotIncluded,
+
+    /// The dotfile's destination, and whether it comes from one of the links
+    Dest {
+        dest: AbsolutePath,
+        custom: bool,
+    },
+
+    /// The dotfile's destination is within this excluded destination
+    DestExcluded(AbsolutePath),
+
+    Requirement {
+        command: String,
+        met: bool,
+    },
+}
+
+impl Step {
+    /// Checks whether this step keeps the dotfile from being linked
+    pub fn drops(&self) -> bool {
+        match self {
+            Step::Hidden(_)
+            | Step::Excluded(_)
+            | Step::Symlink(_)
+            | Step::NotIncluded
+            | Step::DestExcluded(_) => true,
+            Step::Prefixed { active, .. } => !active,
+            Step::Requirement { met, .. } => !met,
+            Step::Marked(_) | Step::Dest { .. } => false,
+        }
+    }
+}
+
+impl Display for Step {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Step::Hidden(path) => write!(f, "{} is hidden, so it isn't searched", path),
+            Step::Excluded(path) => write!(f, "{} is excluded", path),
+            Step::Symlink(path) => write!(
+                f,
+                "{} is a symlinked folder, which isn't searched (see --follow-source-links)",
+                path
+            ),
+            Step::Prefixed { path, active } => {
+                let state = if *active { "active" } else { "inactive" };
+                write!(f, "{} is {}", path, state)
+            },
+            Step::Marked(path) => write!(
+                f,
+                "{} contains a {} file, so it's linked as a whole",
+                path, DIR_MARKER
+            ),
+            Step::NotIncluded => write!(f, "it isn't within any of the includes"),
+            Step::Dest { dest, custom } => {
+                let custom = if *custom { " (from a custom link)" } else { "" };
+                write!(f, "its destination is {}{}", dest, custom)
+            },
+            Step::DestExcluded(dest) => write!(f, "its destination {} is excluded", dest),
+            Step::Requirement { command, met } => {
+                let result = if *met { "succeeds" } else { "fails" };
+                write!(f, "`{}` {}", command, result)
+            },
+        }
+    }
+}
+
+/// The checks `get_items` makes on its way to one dotfile, up to the first one
+/// which drops it (if any)
+#[derive(Debug, Clone)]
+pub struct Trace {
+    /// The dotfile, or the marked directory it's linked as part of (see
+    /// `DIR_MARKER`)
+    pub source: AbsolutePath,
+    pub steps: Vec<Step>,
+}
+
+impl Trace {
+    /// The dotfile's destination, if the search got far enough to find it
+    pub fn dest(&self) -> Option<&AbsolutePath> {
+        self.steps.iter().find_map(|step| match step {
+            Step::Dest { dest, .. } => Some(dest),
+            _ => None,
+        })
+    }
+
+    /// Checks whether `get_items` finds the dotfile. It may still conflict with
+    /// another dotfile linked to the same destination.
+    pub fn is_linked(&self) -> bool {
+        !self.steps.iter().any(Step::drops)
+    }
+}
+
+/// Splits `path` (relative to a dotfiles folder) into the prefixed directories
+/// it's in and the rest of it (see `linked_path`)
+fn split_origin<'a>(path: &'a Path, prefixes: &PrefixNames) -> (PathBuf, &'a Path) {
+    let mut origin = PathBuf::new();
+    let mut tail = path;
+    // The last component is the dotfile itself, whatever it's named
+    while tail.iter().count() > 1 {
+        let first = tail.iter().next().expect("tail must not be empty");
+        if prefixes.kind(first).is_none() {
+            break;
+        }
+        origin.push(first);
+        tail = tail
+            .strip_prefix(first)
+            .expect("first must be a prefix of tail");
+    }
+
+    (origin, tail)
+}
+
+/// Retraces the checks `get_items` makes on its way to `source`, which must be
+/// a file (or marked directory) within one of `config.dotfiles_paths`, checking
+/// whether requirements' commands succeed with `succeeds`.
+///
+/// Returns `None` if `source` isn't within any of the dotfiles folders, or is a
+/// directory which isn't linked itself.
+pub fn trace(
+    config: &Config,
+    source: &AbsolutePath,
+    succeeds: &mut dyn FnMut(&str) -> bool,
+) -> Result<Option<Trace>, Error> {
+    let dotfiles_path = match config
+        .dotfiles_paths
+        .iter()
+        .find(|dotfiles_path| source.starts_with(dotfiles_path) && source != *dotfiles_path)
+    {
+        Some(dotfiles_path) => dotfiles_path,
+        None => return Ok(None),
+    };
+    let relative_path = source
+        .strip_prefix(dotfiles_path)
+        .expect("dotfiles_path must be a prefix of source");
+
+    let prefixes = Prefixes::new(config);
+    let follow_links = config.follow_source_links;
+    let mut steps = vec![];
+    let done = |source: &AbsolutePath, steps| {
+        Ok(Some(Trace {
+            source: source.clone(),
+            steps,
+        }))
+    };
+
+    // Follow the search down from the dotfiles folder, first through prefixed
+    // directories (as in `find_items`), then within the folder linked from (as in
+    // `link_dir_contents`)
+    let mut path = dotfiles_path.to_path_buf();
+    let mut in_prefixed = true;
+    let mut item = None;
+    let components: Vec<_> = relative_path.iter().collect();
+    for (i, component) in components.iter().enumerate() {
+        path.push(component);
+        let entry = AbsolutePath::from(path.clone());
+        let name = Path::new(component);
+        let is_last = i + 1 == components.len();
+
+        if util::is_hidden(name.as_os_str()) {
+            steps.push(Step::Hidden(entry.clone()));
+            return done(source, steps);
+        }
+
+        // As in `find_items`, each entry down to the folder being linked from is
+        // checked against the excludes. Within that folder, only the dotfile is.
+        if in_prefixed && config.excludes.contains(&entry) {
+            steps.push(Step::Excluded(entry.clone()));
+            return done(source, steps);
+        }
+
+        // As in `find_items`, only symlinked directories are skipped
+        if !follow_links && util::file_type(&entry)? == util::FileType::Symlink && entry.is_dir() {
+            steps.push(Step::Symlink(entry.clone()));
+            return done(source, steps);
+        }
+
+        if in_prefixed {
+            if prefixes.is_prefixed(name) {
+                let active = prefixes.is_active(name);
+                steps.push(Step::Prefixed {
+                    path: entry.clone(),
+                    active,
+                });
+                if !active {
+                    return done(source, steps);
+                }
+                // Prefixed directories aren't linked themselves
+                if is_last {
+                    return Ok(None);
+                }
+                continue;
+            }
+            in_prefixed = false;
+        }
+
+        if entry.is_dir() && entry.join(DIR_MARKER).exists() {
+            steps.push(Step::Marked(entry.clone()));
+            item = Some(entry);
+            break;
+        }
+
+        if is_last {
+            item = Some(entry);
+        }
+    }
+    let item = match item {
+        Some(item) => item,
+        // Directories aren't linked themselves, only their contents are
+        None => return Ok(None),
+    };
+
+    if config.excludes.contains(&item) {
+        steps.push(Step::Excluded(item.clone()));
+        return done(&item, steps);
+    }
+
+    let origin = split_origin(relative_path, &config.prefixes).0;
+    let item_root = dotfiles_path.join(&origin);
+    let dest_tail = item
+        .strip_prefix(&item_root)
+        .expect("the prefixed directories must be a prefix of item");
+    let root = DestRoot::new(Some(&origin), &config.target, &config.prefix_roots);
+    let dest = item_dest(&item, dest_tail, root, &config.links, &config.no_dot);
+    steps.push(Step::Dest {
+        dest: dest.clone(),
+        custom: config.links.iter().any(|link| link.matches(dest_tail)),
+    });
+
+    if let Some(includes) = &config.includes {
+        if !includes.iter().any(|include| item.starts_with(include)) {
+            steps.push(Step::NotIncluded);
+            return done(&item, steps);
+        }
+    }
+
+    if let Some(excluded) = dest
+        .ancestors()
+        .find(|dest| config.dest_excludes.is_match(dest))
+    {
+        steps.push(Step::DestExcluded(AbsolutePath::from(excluded)));
+        return done(&item, steps);
+    }
+
+    for requirement in &config.requirements {
+        if requirement.matches(dest_tail) {
+            let met = succeeds(&requirement.command);
+            steps.push(Step::Requirement {
+                command: requirement.command.clone(),
+                met,
+            });
+            if !met {
+                break;
+            }
+        }
+    }
+
+    done(&item, steps)
+}
+
+/// Finds every dotfile (or marked directory) which would be linked to `dest`
+/// if it were active, whether or not it is. They're sorted within each dotfiles
+/// folder.
+pub fn sources_linked_to(config: &Config, dest: &AbsolutePath) -> Result<Vec<AbsolutePath>, Error> {
+    let mut res = vec![];
+    for dotfiles_path in config.dotfiles_paths.iter().filter(|path| path.is_dir()) {
+        let mut entries = WalkDir::new(dotfiles_path)
+            .follow_links(config.follow_source_links)
+            .sort_by(|x, y| x.file_name().cmp(y.file_name()))
+            .into_iter()
+            .filter_entry(|entry| entry.depth() == 0 || !util::is_hidden(entry.file_name()));
+        while let Some(entry) = entries.next() {
+            let entry = match entry {
+                Ok(entry) => entry,
+                Err(ref err) if err.loop_ancestor().is_some() => continue,
+                Err(err) => return Err(err.into()),
+            };
+
+            let path = AbsolutePath::from(entry.path());
+            let is_marked_dir = entry.file_type().is_dir() && path.join(DIR_MARKER).exists();
+            if entry.depth() == 0 || !(entry.file_type().is_file() || is_marked_dir) {
+                continue;
+            }
+
+            let relative_path = path
+                .strip_prefix(dotfiles_path)
+                .expect("dotfiles_path must be a prefix of entry");
+            let (origin, dest_tail) = split_origin(relative_path, &config.prefixes);
+            let root = DestRoot::new(Some(&origin), &config.target, &config.prefix_roots);
+            if item_dest(&path, dest_tail, root, &config.links, &config.no_dot) == *dest {
+                res.push(path);
+            }
+            if is_marked_dir {
+                entries.skip_current_dir();
+            }
+        }
+    }
+
+    Ok(res)
+}
+
+/// The prefixed folder (relative to the dotfiles folder) an item was found in,
+/// if any, for error messages
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Origin(pub Option<PathBuf>);
+
+impl Display for Origin {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.0 {
+            Some(origin) => write!(f, " (from {})", origin.display()),
+            None => Ok(()),
+        }
+    }
+}
+
+/// The active dotfiles sharing a destination
+#[derive(Debug)]
+pub struct Collision {
+    pub dest: AbsolutePath,
+    /// Each dotfile linked to `dest`, in the order they were found
+    pub sources: Vec<(AbsolutePath, Origin)>,
+}
+
+impl Display for Collision {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: ", self.dest)?;
+        for (i, (source, origin)) in self.sources.iter().enumerate() {
+            if i == self.sources.len() - 1 {
+                write!(f, " and ")?;
+            } else if i > 0 {
+                write!(f, ", ")?;
+            }
+            write!(f, "{}{}", source, origin)?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Every destination with more than one active dotfile, ordered by destination
+#[derive(Debug)]
+pub struct Collisions(pub Vec<Collision>);
+
+impl Display for Collisions {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.0.as_slice() {
+            [collision] => write!(
+                f,
+                "multiple source files for destination {}. Exclude all but one of them, or make \
+                 sure only one is active.",
+                collision
+            ),
+            collisions => {
+                write!(
+                    f,
+                    "multiple source files for {} destinations. For each, exclude all but one of \
+                     them, or make sure only one is active:",
+                    collisions.len()
+                )?;
+                for collision in collisions {
+                    write!(f, "\n    {}", collision)?;
+                }
+
+                Ok(())
+            },
+        }
+    }
+}
+
 #[derive(Debug, From, Fail)]
 pub enum Error {
     /// Indicates when there are multiple active sources pointing to the same
-    /// destination.
-    #[fail(display = "multiple source files for destination {}", dest)]
-    DuplicateFiles { dest: AbsolutePath },
+    /// destination. Every such destination is reported at once.
+    #[fail(display = "{}", _0)]
+    DuplicateFiles(Collisions),
+
+    #[fail(
+        display = "{} isn't a hidden path in {}, so it can't be linked there by default",
+        path, target
+    )]
+    NotHiddenInTarget {
+        path: AbsolutePath,
+        target: AbsolutePath,
+    },
+
+    #[fail(
+        display = "dotfiles folder {} doesn't exist. Run `dot init` to create one, or pass \
+                   --dotfiles-path to use another folder.",
+        _0
+    )]
+    DotfilesDirMissing(AbsolutePath),
 
     #[fail(display = "error reading from dotfiles directory ({})", _0)]
     IoError(#[fail(cause)] io::Error),
@@ -182,3 +1157,1334 @@ pub enum Error {
     WalkdirError(#[fail(cause)] walkdir::Error),
 }
 use Error::*;
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        all_prefixed_dirs, cache, default_source, get_items, get_items_with, prefixed_dirs,
+        source_matches, sources_linked_to, trace, Collision, Collisions, Error, Origin, PrefixKind,
+        PrefixNames, Prefixes, Step,
+    };
+    use crate::{
+        common::{util, AbsolutePath, Platform},
+        config::{cli::Command, Config, Link, PrefixRoot, RawGlobs, Requirement, Sources},
+    };
+    use globset::{Glob, GlobSet, GlobSetBuilder};
+    use pretty_assertions::assert_eq;
+    use std::{
+        fs,
+        path::{Path, PathBuf},
+    };
+    use tempfile::TempDir;
+
+    /// Creates a dotfiles directory containing (empty) files at each of `paths`
+    fn dotfiles(paths: &[&str]) -> TempDir {
+        let dir = TempDir::new().unwrap();
+        for path in paths {
+            let path = dir.path().join(path);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, "").unwrap();
+        }
+
+        dir
+    }
+
+    fn config(dotfiles: &TempDir) -> Config {
+        Config {
+            raw_globs: RawGlobs::default(),
+            excludes: vec![],
+            dead_excludes: vec![],
+            dest_excludes: GlobSet::empty(),
+            includes: None,
+            tags: vec![],
+            dotfiles_paths: vec![AbsolutePath::from(dotfiles.path())],
+            remote_clones: vec![],
+            hostnames: vec![String::from("hostname")],
+            platform: Platform::Linux,
+            arch: String::from("x86_64"),
+            target: AbsolutePath::from(util::home_dir()),
+            links: vec![],
+            prefix_roots: vec![],
+            prefixes: PrefixNames::default(),
+            no_dot: GlobSet::empty(),
+            link_first: GlobSet::empty(),
+            link_last: GlobSet::empty(),
+            requirements: vec![],
+            modes: vec![],
+            follow_source_links: false,
+            pre_link_hooks: vec![],
+            post_link_hooks: vec![],
+            auto_pull: false,
+            conflict_policy: None,
+            cache: false,
+            sources: Sources::default(),
+            command: Command::Status,
+        }
+    }
+
+    #[test]
+    fn dest_excludes() {
+        let dir = dotfiles(&["ssh/config", "tag-work/ssh/known_hosts", "vimrc", "zshrc"]);
+        let target = PathBuf::from("/home/me");
+        let glob = |glob: &str| Glob::new(&target.join(glob).to_string_lossy()).unwrap();
+        let mut builder = GlobSetBuilder::new();
+        builder.add(glob(".ssh")).add(glob(".z*"));
+        let config = Config {
+            tags: vec![String::from("work")],
+            target: AbsolutePath::from(target.clone()),
+            dest_excludes: builder.build().unwrap(),
+            ..config(&dir)
+        };
+
+        // Whatever folder they come from, nothing is linked into `~/.ssh`
+        assert_eq!(
+            items(&config),
+            vec![(PathBuf::from("vimrc"), target.join(".vimrc"))]
+        );
+    }
+
+    #[test]
+    fn requirements() {
+        let dir = dotfiles(&[
+            "config/nvim/init.vim",
+            "config/nvim/plugins.vim",
+            "tag-work/bin/tool",
+            "vimrc",
+        ]);
+        let config = Config {
+            tags: vec![String::from("work")],
+            requirements: vec![
+                Requirement::new("config/nvim/**", "command -v nvim").unwrap(),
+                Requirement::new("vimrc", "command -v vim").unwrap(),
+                // Matched relative to the prefixed folder
+                Requirement::new("bin/*", "command -v nvim").unwrap(),
+            ],
+            ..config(&dir)
+        };
+
+        let mut checked = vec![];
+        let mut succeeds = |command: &str| {
+            checked.push(String::from(command));
+            command == "command -v vim"
+        };
+        let items = get_items_with(&config, &mut succeeds).unwrap();
+
+        let sources: Vec<_> = items
+            .iter()
+            .map(|item| item.source.strip_prefix(dir.path()).unwrap())
+            .collect();
+        assert_eq!(sources, vec![Path::new("vimrc")]);
+        // Each command is only run once
+        checked.sort();
+        assert_eq!(checked, vec!["command -v nvim", "command -v vim"]);
+    }
+
+    #[test]
+    fn traces() {
+        let dir = dotfiles(&[
+            "host-laptop/zshrc",
+            "tag-work/.hidden/gitconfig",
+            "tag-work/vim/vimrc",
+            "nvim/.dotman-dir",
+            "nvim/init.vim",
+            "bashrc",
+        ]);
+        let path = |path: &str| AbsolutePath::from(dir.path().join(path));
+        let config = Config {
+            tags: vec![String::from("work")],
+            excludes: vec![path("bashrc")],
+            requirements: vec![Requirement::new("nvim", "command -v nvim").unwrap()],
+            ..config(&dir)
+        };
+        let steps = |source: &str| {
+            let trace = trace(&config, &path(source), &mut |_| false)
+                .unwrap()
+                .unwrap();
+            (trace.source.clone(), trace.steps)
+        };
+
+        assert_eq!(
+            steps("host-laptop/zshrc"),
+            (
+                path("host-laptop/zshrc"),
+                vec![Step::Prefixed {
+                    path: path("host-laptop"),
+                    active: false,
+                }]
+            )
+        );
+        assert_eq!(
+            steps("tag-work/.hidden/gitconfig").1,
+            vec![
+                Step::Prefixed {
+                    path: path("tag-work"),
+                    active: true,
+                },
+                Step::Hidden(path("tag-work/.hidden")),
+            ]
+        );
+        assert_eq!(
+            steps("tag-work/vim/vimrc").1,
+            vec![
+                Step::Prefixed {
+                    path: path("tag-work"),
+                    active: true,
+                },
+                Step::Dest {
+                    dest: AbsolutePath::from(util::home_dir().join(".vim/vimrc")),
+                    custom: false,
+                },
+            ]
+        );
+        // Files in marked directories are explained as the directory
+        assert_eq!(
+            steps("nvim/init.vim"),
+            (
+                path("nvim"),
+                vec![
+                    Step::Marked(path("nvim")),
+                    Step::Dest {
+                        dest: AbsolutePath::from(util::home_dir().join(".nvim")),
+                        custom: false,
+                    },
+                    Step::Requirement {
+                        command: String::from("command -v nvim"),
+                        met: false,
+                    },
+                ]
+            )
+        );
+        assert_eq!(steps("bashrc").1, vec![Step::Excluded(path("bashrc"))]);
+
+        // Directories aren't dotfiles themselves, and paths outside the dotfiles
+        // folder aren't dotfiles at all
+        assert!(trace(&config, &path("tag-work"), &mut |_| true)
+            .unwrap()
+            .is_none());
+        assert!(trace(&config, &config.target, &mut |_| true)
+            .unwrap()
+            .is_none());
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn trace_symlinks() {
+        let dir = dotfiles(&["elsewhere/gitconfig", "elsewhere/vim/vimrc", "git/ignore"]);
+        let link = |target: &str, link: &str| {
+            std::os::unix::fs::symlink(dir.path().join(target), dir.path().join(link)).unwrap()
+        };
+        link("elsewhere/gitconfig", "gitconfig");
+        link("elsewhere/gitconfig", "git/config");
+        link("elsewhere/vim", "vim");
+        let path = |path: &str| AbsolutePath::from(dir.path().join(path));
+        let config = Config {
+            excludes: vec![path("elsewhere")],
+            ..config(&dir)
+        };
+        let steps = |source: &str| {
+            trace(&config, &path(source), &mut |_| true)
+                .unwrap()
+                .unwrap()
+                .steps
+        };
+
+        // Symlinked files are linked like any other file
+        assert_eq!(
+            steps("gitconfig"),
+            vec![Step::Dest {
+                dest: AbsolutePath::from(util::home_dir().join(".gitconfig")),
+                custom: false,
+            }]
+        );
+        assert_eq!(
+            items(&config),
+            vec![
+                (
+                    PathBuf::from("git/config"),
+                    util::home_dir().join(".git/config")
+                ),
+                (
+                    PathBuf::from("git/ignore"),
+                    util::home_dir().join(".git/ignore")
+                ),
+                (
+                    PathBuf::from("gitconfig"),
+                    util::home_dir().join(".gitconfig")
+                ),
+            ]
+        );
+        // Symlinked folders aren't searched
+        assert_eq!(steps("vim/vimrc"), vec![Step::Symlink(path("vim"))]);
+    }
+
+    #[test]
+    fn sources_linked_to_dest() {
+        let dir = dotfiles(&[
+            "vim/vimrc",
+            "tag-work/vim/vimrc",
+            "host-laptop/tag-home/vim/vimrc",
+            "config/vim/vimrc",
+            ".git/vim/vimrc",
+        ]);
+        let config = config(&dir);
+        let dest = AbsolutePath::from(util::home_dir().join(".vim/vimrc"));
+
+        let mut sources: Vec<_> = sources_linked_to(&config, &dest)
+            .unwrap()
+            .into_iter()
+            .map(|source| source.strip_prefix(dir.path()).unwrap().to_path_buf())
+            .collect();
+        sources.sort();
+        // Even inactive prefixed folders are searched
+        assert_eq!(
+            sources,
+            paths(&[
+                "host-laptop/tag-home/vim/vimrc",
+                "tag-work/vim/vimrc",
+                "vim/vimrc",
+            ])
+        );
+    }
+
+    #[test]
+    fn only() {
+        let dir = dotfiles(&[
+            "zsh/zshrc",
+            "zsh/zprofile",
+            "tag-work/zsh/zshenv",
+            "bashrc",
+            "vim/vimrc",
+        ]);
+        let config = Config {
+            tags: vec![String::from("work")],
+            ..config(&dir)
+        };
+        let only = |globs: &[&str]| {
+            let mut builder = GlobSetBuilder::new();
+            for glob in globs {
+                builder.add(Glob::new(glob).unwrap());
+            }
+            let only = builder.build().unwrap();
+
+            let mut sources: Vec<_> = get_items(&config)
+                .unwrap()
+                .into_iter()
+                .filter(|item| source_matches(item, &only, &config.dotfiles_paths))
+                .map(|item| item.source.strip_prefix(dir.path()).unwrap().to_path_buf())
+                .collect();
+            sources.sort();
+
+            sources
+        };
+
+        // Folders match as well as files, and the globs are relative to the
+        // dotfiles folder, so `zsh` doesn't match `tag-work/zsh`
+        assert_eq!(only(&["zsh"]), paths(&["zsh/zprofile", "zsh/zshrc"]));
+        assert_eq!(
+            only(&["**/zsh", "bashrc"]),
+            paths(&["bashrc", "tag-work/zsh/zshenv", "zsh/zprofile", "zsh/zshrc",])
+        );
+        assert_eq!(only(&["*.conf"]), paths(&[]));
+    }
+
+    #[test]
+    fn sorted_by_dest() {
+        let dir = dotfiles(&[
+            "zshrc",
+            "tag-work/bashrc",
+            "vim/vimrc",
+            "config/nvim/init.vim",
+        ]);
+        let config = Config {
+            tags: vec![String::from("work")],
+            ..config(&dir)
+        };
+
+        let dests: Vec<_> = get_items(&config)
+            .unwrap()
+            .into_iter()
+            .map(|item| {
+                item.dest
+                    .strip_prefix(util::home_dir())
+                    .unwrap()
+                    .to_path_buf()
+            })
+            .collect();
+        assert_eq!(
+            dests,
+            paths(&[".bashrc", ".config/nvim/init.vim", ".vim/vimrc", ".zshrc"])
+        );
+    }
+
+    #[test]
+    fn cached() {
+        let dir = dotfiles(&["vimrc", ".git/HEAD"]);
+        let cache_dir = TempDir::new().unwrap();
+        let cache_path = cache_dir.path().join("resolved.json");
+        let cached_sources = |config: &Config| -> Vec<PathBuf> {
+            let mut sources: Vec<_> = cache::get_items_in(config, &cache_path)
+                .unwrap()
+                .into_iter()
+                .map(|item| item.source.strip_prefix(dir.path()).unwrap().to_path_buf())
+                .collect();
+            sources.sort();
+
+            sources
+        };
+
+        assert_eq!(cached_sources(&config(&dir)), paths(&["vimrc"]));
+
+        // While nothing changes, the cached items are used as they are
+        let cache = fs::read_to_string(&cache_path).unwrap();
+        fs::write(&cache_path, cache.replace("vimrc", "gvimrc")).unwrap();
+        assert_eq!(cached_sources(&config(&dir)), paths(&["gvimrc"]));
+
+        // Nothing in hidden folders is linked, so changes there don't matter
+        fs::write(dir.path().join(".git/HEAD"), "ref: refs/heads/main\n").unwrap();
+        assert_eq!(cached_sources(&config(&dir)), paths(&["gvimrc"]));
+
+        // A cache with relative paths in it can't have been written by dotman, so
+        // it isn't used
+        let mut cache: serde_json::Value =
+            serde_json::from_slice(&fs::read(&cache_path).unwrap()).unwrap();
+        cache["items"][0]["source"] = serde_json::Value::from("gvimrc");
+        fs::write(&cache_path, cache.to_string()).unwrap();
+        assert_eq!(cached_sources(&config(&dir)), paths(&["vimrc"]));
+
+        // Changing a file busts the cache
+        fs::write(dir.path().join("vimrc"), "set number\n").unwrap();
+        assert_eq!(cached_sources(&config(&dir)), paths(&["vimrc"]));
+
+        // As does adding one
+        fs::write(dir.path().join("zshrc"), "").unwrap();
+        assert_eq!(cached_sources(&config(&dir)), paths(&["vimrc", "zshrc"]));
+
+        // Or changing the configuration
+        let config = Config {
+            raw_globs: RawGlobs {
+                excludes: paths(&["zshrc"]),
+                ..RawGlobs::default()
+            },
+            excludes: vec![AbsolutePath::from(dir.path().join("zshrc"))],
+            ..config(&dir)
+        };
+        assert_eq!(cached_sources(&config), paths(&["vimrc"]));
+    }
+
+    #[test]
+    fn cached_expansions() {
+        let dir = dotfiles(&["vimrc", "zshrc"]);
+        let cache_dir = TempDir::new().unwrap();
+        let cache_path = cache_dir.path().join("expanded.json");
+        let config = Config {
+            raw_globs: RawGlobs {
+                excludes: paths(&["*rc"]),
+                ..RawGlobs::default()
+            },
+            ..config(&dir)
+        };
+        let cached_excludes = || -> Vec<PathBuf> {
+            let mut excludes: Vec<_> = cache::get_expansions_in(&config, &cache_path)
+                .unwrap()
+                .excludes
+                .into_iter()
+                .map(|exclude| exclude.strip_prefix(dir.path()).unwrap().to_path_buf())
+                .collect();
+            excludes.sort();
+
+            excludes
+        };
+
+        assert_eq!(cached_excludes(), paths(&["vimrc", "zshrc"]));
+
+        // While nothing changes, the dotfiles folder isn't walked to expand the
+        // globs again
+        let cache = fs::read_to_string(&cache_path).unwrap();
+        fs::write(&cache_path, cache.replace("zshrc", "bashrc")).unwrap();
+        assert_eq!(cached_excludes(), paths(&["bashrc", "vimrc"]));
+
+        // Ignore files are hidden, but still bust the cache
+        fs::write(dir.path().join(".dotignore"), "!vimrc\n").unwrap();
+        assert_eq!(cached_excludes(), paths(&["zshrc"]));
+    }
+
+    #[test]
+    fn pinned_order() {
+        let dir = dotfiles(&[
+            "aliases",
+            "profile",
+            "tag-work/profile_work",
+            "vimrc",
+            "zshrc",
+        ]);
+        let glob_set = |globs: &[&str]| {
+            let mut builder = GlobSetBuilder::new();
+            for glob in globs {
+                builder.add(Glob::new(glob).unwrap());
+            }
+
+            builder.build().unwrap()
+        };
+        let config = Config {
+            tags: vec![String::from("work")],
+            // Matched relative to the prefixed folder
+            link_first: glob_set(&["profile_work", "profile"]),
+            link_last: glob_set(&["aliases"]),
+            ..config(&dir)
+        };
+
+        let sources: Vec<_> = get_items(&config)
+            .unwrap()
+            .into_iter()
+            .map(|item| item.source.strip_prefix(dir.path()).unwrap().to_path_buf())
+            .collect();
+        assert_eq!(
+            sources,
+            paths(&[
+                "tag-work/profile_work",
+                "profile",
+                "vimrc",
+                "zshrc",
+                "aliases"
+            ])
+        );
+    }
+
+    #[test]
+    fn missing_dotfiles_dir() {
+        let dir = dotfiles(&[]);
+        let missing = AbsolutePath::from(dir.path().join("missing"));
+        let config = Config {
+            dotfiles_paths: vec![missing.clone()],
+            ..config(&dir)
+        };
+
+        match get_items(&config) {
+            Err(Error::DotfilesDirMissing(path)) => assert_eq!(path, missing),
+            res => panic!("unexpected result {:?}", res),
+        }
+    }
+
+    /// The sources of the items found with `config`, relative to (and sorted
+    /// within) the dotfiles directory
+    fn sources(config: &Config) -> Vec<PathBuf> {
+        let mut sources: Vec<_> = get_items(config)
+            .unwrap()
+            .into_iter()
+            .map(|item| {
+                item.source
+                    .strip_prefix(&config.dotfiles_paths[0])
+                    .unwrap()
+                    .to_path_buf()
+            })
+            .collect();
+        sources.sort();
+
+        sources
+    }
+
+    fn paths(paths: &[&str]) -> Vec<PathBuf> {
+        paths.iter().map(Path::new).map(Path::to_path_buf).collect()
+    }
+
+    #[test]
+    fn single_tag() {
+        let dir = dotfiles(&["tag-work/vim/vimrc", "tag-home/zsh/zshrc"]);
+        let config = Config {
+            tags: vec![String::from("work")],
+            ..config(&dir)
+        };
+
+        assert_eq!(sources(&config), paths(&["tag-work/vim/vimrc"]));
+    }
+
+    #[test]
+    fn arch() {
+        let dir = dotfiles(&["arch-aarch64/bin/tool", "arch-x86_64/bin/tool"]);
+
+        let aarch64 = Config {
+            arch: String::from("aarch64"),
+            ..config(&dir)
+        };
+        assert_eq!(sources(&aarch64), paths(&["arch-aarch64/bin/tool"]));
+
+        let x86_64 = Config {
+            arch: String::from("x86_64"),
+            ..config(&dir)
+        };
+        assert_eq!(sources(&x86_64), paths(&["arch-x86_64/bin/tool"]));
+    }
+
+    #[test]
+    fn origins() {
+        let dir = dotfiles(&[
+            "vim/vimrc",
+            "tag-work/zsh/zshrc",
+            "tag-work/platform-linux/bashrc",
+        ]);
+        let config = Config {
+            tags: vec![String::from("work")],
+            platform: Platform::Linux,
+            ..config(&dir)
+        };
+
+        let mut origins: Vec<_> = get_items(&config)
+            .unwrap()
+            .into_iter()
+            .map(|item| {
+                let source = item.source.strip_prefix(dir.path()).unwrap().to_path_buf();
+                (source, item.origin)
+            })
+            .collect();
+        origins.sort();
+
+        let expected = vec![
+            (
+                PathBuf::from("tag-work/platform-linux/bashrc"),
+                Some(PathBuf::from("tag-work/platform-linux")),
+            ),
+            (
+                PathBuf::from("tag-work/zsh/zshrc"),
+                Some(PathBuf::from("tag-work")),
+            ),
+            (PathBuf::from("vim/vimrc"), None),
+        ];
+        assert_eq!(origins, expected);
+    }
+
+    #[test]
+    fn nested_prefixed_dirs() {
+        let dir = dotfiles(&[
+            "tag-work/host-hostname/platform-linux/gitconfig",
+            "tag-work/host-hostname/ssh/config",
+        ]);
+        let config = Config {
+            tags: vec![String::from("work")],
+            platform: Platform::Linux,
+            ..config(&dir)
+        };
+
+        // Nested prefixed folders aren't linked as folders themselves
+        assert_eq!(
+            items(&config),
+            vec![
+                (
+                    PathBuf::from("tag-work/host-hostname/platform-linux/gitconfig"),
+                    util::home_dir().join(".gitconfig"),
+                ),
+                (
+                    PathBuf::from("tag-work/host-hostname/ssh/config"),
+                    util::home_dir().join(".ssh/config"),
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn nested_inactive_prefixed_dirs() {
+        let dir = dotfiles(&[
+            "tag-work/host-laptop/ssh/config",
+            "tag-work/platform-macos/zshrc",
+            "tag-work/platform-linux/bashrc",
+            "host-hostname/tag-home/vimrc",
+        ]);
+        let config = Config {
+            tags: vec![String::from("work")],
+            platform: Platform::Linux,
+            ..config(&dir)
+        };
+
+        // Every prefixed folder along the way has to be active
+        assert_eq!(sources(&config), paths(&["tag-work/platform-linux/bashrc"]));
+    }
+
+    #[test]
+    fn multiple_hostnames() {
+        let dir = dotfiles(&[
+            "host-laptop/vimrc",
+            "host-laptop.local/zshrc",
+            "host-desktop/bashrc",
+        ]);
+        let config = Config {
+            hostnames: vec![String::from("laptop"), String::from("laptop.local")],
+            ..config(&dir)
+        };
+
+        assert_eq!(
+            sources(&config),
+            paths(&["host-laptop/vimrc", "host-laptop.local/zshrc"])
+        );
+    }
+
+    #[test]
+    fn multiple_tags() {
+        let dir = dotfiles(&["tag-work+linux/vim/vimrc", "tag-work/zsh/zshrc"]);
+        let config = Config {
+            tags: vec![String::from("linux"), String::from("work")],
+            ..config(&dir)
+        };
+
+        assert_eq!(
+            sources(&config),
+            paths(&["tag-work/zsh/zshrc", "tag-work+linux/vim/vimrc"])
+        );
+    }
+
+    #[test]
+    fn multiple_tags_partially_enabled() {
+        let dir = dotfiles(&["tag-work+linux/vim/vimrc", "tag-work/zsh/zshrc"]);
+        let config = Config {
+            tags: vec![String::from("work")],
+            ..config(&dir)
+        };
+
+        assert_eq!(sources(&config), paths(&["tag-work/zsh/zshrc"]));
+    }
+
+    #[test]
+    fn includes() {
+        let dir = dotfiles(&["vim/vimrc", "vim/gvimrc", "zsh/zshrc"]);
+        let config = Config {
+            includes: Some(vec![AbsolutePath::from(dir.path().join("vim"))]),
+            ..config(&dir)
+        };
+
+        assert_eq!(sources(&config), paths(&["vim/gvimrc", "vim/vimrc"]));
+    }
+
+    #[test]
+    fn includes_and_excludes() {
+        let dir = dotfiles(&["vim/vimrc", "vim/gvimrc", "zsh/zshrc"]);
+        let config = Config {
+            includes: Some(vec![AbsolutePath::from(dir.path().join("vim"))]),
+            excludes: vec![AbsolutePath::from(dir.path().join("vim/gvimrc"))],
+            ..config(&dir)
+        };
+
+        assert_eq!(sources(&config), paths(&["vim/vimrc"]));
+    }
+
+    #[test]
+    fn empty_includes() {
+        let dir = dotfiles(&["vim/vimrc", "zsh/zshrc"]);
+        let config = Config {
+            includes: Some(vec![]),
+            ..config(&dir)
+        };
+
+        assert_eq!(sources(&config), paths(&[]));
+    }
+
+    #[test]
+    fn multiple_dotfiles_paths() {
+        let public = dotfiles(&["vim/vimrc"]);
+        let private = dotfiles(&["ssh/config"]);
+        let config = Config {
+            dotfiles_paths: vec![
+                AbsolutePath::from(public.path()),
+                AbsolutePath::from(private.path()),
+            ],
+            ..config(&public)
+        };
+
+        let sources: Vec<_> = get_items(&config)
+            .unwrap()
+            .into_iter()
+            .map(|item| item.source)
+            .collect();
+        // Sorted by destination, rather than by folder
+        assert_eq!(
+            sources,
+            vec![
+                AbsolutePath::from(private.path().join("ssh/config")),
+                AbsolutePath::from(public.path().join("vim/vimrc")),
+            ]
+        );
+    }
+
+    #[test]
+    fn multiple_dotfiles_paths_conflict() {
+        let public = dotfiles(&["vim/vimrc"]);
+        let private = dotfiles(&["vim/vimrc"]);
+        let config = Config {
+            dotfiles_paths: vec![
+                AbsolutePath::from(public.path()),
+                AbsolutePath::from(private.path()),
+            ],
+            ..config(&public)
+        };
+
+        match get_items(&config) {
+            Err(Error::DuplicateFiles(Collisions(collisions))) => assert_eq!(
+                collisions[0].sources,
+                vec![
+                    (
+                        AbsolutePath::from(public.path().join("vim/vimrc")),
+                        Origin(None)
+                    ),
+                    (
+                        AbsolutePath::from(private.path().join("vim/vimrc")),
+                        Origin(None)
+                    ),
+                ]
+            ),
+            res => panic!("unexpected result {:?}", res),
+        }
+    }
+
+    #[test]
+    fn prefixed_dirs_conflict() {
+        let dir = dotfiles(&["platform-linux/vimrc", "tag-work/vimrc"]);
+        let config = Config {
+            tags: vec![String::from("work")],
+            platform: Platform::Linux,
+            ..config(&dir)
+        };
+
+        let err = get_items(&config).unwrap_err();
+        match &err {
+            Error::DuplicateFiles(Collisions(collisions)) => {
+                assert_eq!(collisions.len(), 1);
+                let Collision { dest, sources } = &collisions[0];
+                assert_eq!(*dest, AbsolutePath::from(util::home_dir().join(".vimrc")));
+                let mut sources = sources.clone();
+                sources.sort_by(|(x, _), (y, _)| x.as_path().cmp(y.as_path()));
+                assert_eq!(
+                    sources,
+                    vec![
+                        (
+                            AbsolutePath::from(dir.path().join("platform-linux/vimrc")),
+                            Origin(Some(PathBuf::from("platform-linux"))),
+                        ),
+                        (
+                            AbsolutePath::from(dir.path().join("tag-work/vimrc")),
+                            Origin(Some(PathBuf::from("tag-work"))),
+                        ),
+                    ]
+                );
+            },
+            err => panic!("unexpected error {:?}", err),
+        }
+        // Both prefixed folders are named
+        let message = err.to_string();
+        assert!(message.contains("(from platform-linux)"));
+        assert!(message.contains("(from tag-work)"));
+    }
+
+    #[test]
+    fn differently_spelled_dests_conflict() {
+        let dir = dotfiles(&["vim/vimrc", "vimrc"]);
+        let target = TempDir::new().unwrap();
+        let target = AbsolutePath::from(target.path());
+        let config = Config {
+            links: vec![Link::new("vimrc", "~/.config/./../.vim//vimrc", &target).unwrap()],
+            target: target.clone(),
+            ..config(&dir)
+        };
+
+        match get_items(&config) {
+            Err(Error::DuplicateFiles(Collisions(collisions))) => {
+                assert_eq!(
+                    collisions[0].dest,
+                    AbsolutePath::from(target.join(".vim/vimrc"))
+                )
+            },
+            res => panic!("unexpected result {:?}", res),
+        }
+    }
+
+    #[test]
+    fn all_conflicts() {
+        let dir = dotfiles(&[
+            "platform-linux/vimrc",
+            "tag-work/vimrc",
+            "vimrc",
+            "platform-linux/zshrc",
+            "tag-work/zshrc",
+            "bashrc",
+        ]);
+        let config = Config {
+            tags: vec![String::from("work")],
+            platform: Platform::Linux,
+            ..config(&dir)
+        };
+
+        let err = get_items(&config).unwrap_err();
+        match &err {
+            Error::DuplicateFiles(Collisions(collisions)) => {
+                let dests: Vec<_> = collisions
+                    .iter()
+                    .map(|collision| (collision.dest.clone(), collision.sources.len()))
+                    .collect();
+                assert_eq!(
+                    dests,
+                    vec![
+                        (AbsolutePath::from(util::home_dir().join(".vimrc")), 3),
+                        (AbsolutePath::from(util::home_dir().join(".zshrc")), 2),
+                    ]
+                );
+            },
+            err => panic!("unexpected error {:?}", err),
+        }
+        assert!(err
+            .to_string()
+            .starts_with("multiple source files for 2 destinations"));
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn symlink_loop() {
+        let dir = dotfiles(&["tag-work/vim/vimrc"]);
+        std::os::unix::fs::symlink(dir.path(), dir.path().join("tag-work/tag-loop")).unwrap();
+        let config = Config {
+            tags: vec![String::from("work"), String::from("loop")],
+            ..config(&dir)
+        };
+
+        assert_eq!(sources(&config), paths(&["tag-work/vim/vimrc"]));
+        assert_eq!(
+            prefixed_dirs(&config.dotfiles_paths[0], &Prefixes::new(&config), &[])
+                .unwrap()
+                .len(),
+            2
+        );
+    }
+
+    #[test]
+    fn default_sources() {
+        let target = AbsolutePath::from("/home/me");
+        let dotfiles_path = AbsolutePath::from("/home/me/.dotfiles");
+        let no_dot = no_dot(&["bin"]);
+        let source = |dest: &str| {
+            default_source(&AbsolutePath::from(dest), &target, &dotfiles_path, &no_dot)
+        };
+
+        assert_eq!(
+            source("/home/me/.config/nvim/init.vim").unwrap(),
+            AbsolutePath::from("/home/me/.dotfiles/config/nvim/init.vim")
+        );
+        assert_eq!(
+            source("/home/me/bin/tool").unwrap(),
+            AbsolutePath::from("/home/me/.dotfiles/bin/tool")
+        );
+        assert!(source("/home/me/Documents/notes").is_err());
+        assert!(source("/etc/hosts").is_err());
+    }
+
+    fn no_dot(globs: &[&str]) -> GlobSet {
+        let mut builder = GlobSetBuilder::new();
+        for glob in globs {
+            builder.add(Glob::new(glob).unwrap());
+        }
+
+        builder.build().unwrap()
+    }
+
+    #[test]
+    fn no_dot_dests() {
+        let dir = dotfiles(&[
+            "bin/tool",
+            "host-hostname/bin/other",
+            "vim/vimrc",
+            "bin.conf",
+        ]);
+        let target = TempDir::new().unwrap();
+        let target = AbsolutePath::from(target.path());
+        let config = Config {
+            no_dot: no_dot(&["bin"]),
+            target: target.clone(),
+            ..config(&dir)
+        };
+
+        assert_eq!(
+            items(&config),
+            vec![
+                (PathBuf::from("bin/tool"), target.join("bin/tool")),
+                (PathBuf::from("bin.conf"), target.join(".bin.conf")),
+                (
+                    PathBuf::from("host-hostname/bin/other"),
+                    target.join("bin/other")
+                ),
+                (PathBuf::from("vim/vimrc"), target.join(".vim/vimrc")),
+            ]
+        );
+    }
+
+    #[test]
+    fn prefixed_dirs_activity() {
+        let dir = dotfiles(&[
+            "host-hostname/tag-work/vim/vimrc",
+            "host-other/tag-work/zsh/zshrc",
+            "tag-home/bash/bashrc",
+            "git/gitconfig",
+        ]);
+        let config = Config {
+            tags: vec![String::from("work")],
+            ..config(&dir)
+        };
+
+        let dirs: Vec<_> = prefixed_dirs(&config.dotfiles_paths[0], &Prefixes::new(&config), &[])
+            .unwrap()
+            .into_iter()
+            .map(|(path, active)| (path.strip_prefix(dir.path()).unwrap().to_path_buf(), active))
+            .collect();
+        assert_eq!(
+            dirs,
+            vec![
+                (PathBuf::from("host-hostname"), true),
+                (PathBuf::from("host-hostname/tag-work"), true),
+                (PathBuf::from("host-other"), false),
+                (PathBuf::from("tag-home"), false),
+            ]
+        );
+    }
+
+    #[test]
+    fn all_prefixed_dirs_activity() {
+        let dir = dotfiles(&[
+            "host-other/tag-work/zsh/zshrc",
+            "tag-home/bash/bashrc",
+            "tag-work+linux",
+            "git/gitconfig",
+        ]);
+        let config = Config {
+            tags: vec![String::from("work")],
+            ..config(&dir)
+        };
+
+        let dirs: Vec<_> =
+            all_prefixed_dirs(&config.dotfiles_paths[0], &Prefixes::new(&config), &[])
+                .unwrap()
+                .into_iter()
+                .map(|(path, active)| {
+                    (path.strip_prefix(dir.path()).unwrap().to_path_buf(), active)
+                })
+                .collect();
+        // Inactive folders are searched too, but files aren't folders
+        assert_eq!(
+            dirs,
+            vec![
+                (PathBuf::from("host-other"), false),
+                (PathBuf::from("host-other/tag-work"), true),
+                (PathBuf::from("tag-home"), false),
+            ]
+        );
+    }
+
+    #[test]
+    fn prefix_kinds() {
+        let prefixes = PrefixNames::default();
+        let split = |name| prefixes.split(Path::new(name));
+        assert_eq!(split("host-laptop"), Some((PrefixKind::Host, "laptop")));
+        assert_eq!(
+            split("tag-work+linux"),
+            Some((PrefixKind::Tag, "work+linux"))
+        );
+        assert_eq!(
+            split("platform-macos"),
+            Some((PrefixKind::Platform, "macos"))
+        );
+        assert_eq!(split("arch-aarch64"), Some((PrefixKind::Arch, "aarch64")));
+        assert_eq!(split("vim"), None);
+    }
+
+    #[test]
+    fn custom_prefixes() {
+        let dir = dotfiles(&[
+            "tag_work/vimrc",
+            "tag_home/zshrc",
+            "@laptop/tag_work/gitconfig",
+            "tag-work/bashrc",
+        ]);
+        let config = Config {
+            tags: vec![String::from("work")],
+            hostnames: vec![String::from("laptop")],
+            prefixes: PrefixNames {
+                host: String::from("@"),
+                tag: String::from("tag_"),
+                ..PrefixNames::default()
+            },
+            ..config(&dir)
+        };
+
+        // `tag-work` is just a folder now, so it's linked as one
+        assert_eq!(
+            sources(&config),
+            paths(&[
+                "@laptop/tag_work/gitconfig",
+                "tag-work/bashrc",
+                "tag_work/vimrc"
+            ])
+        );
+        let items = get_items(&config).unwrap();
+        let dest = |source: &str| {
+            let source = dir.path().join(source);
+            items
+                .iter()
+                .find(|item| *item.source == source)
+                .map(|item| item.dest.clone())
+                .unwrap()
+        };
+        assert_eq!(
+            dest("@laptop/tag_work/gitconfig"),
+            AbsolutePath::from(util::home_dir().join(".gitconfig"))
+        );
+        assert_eq!(
+            dest("tag-work/bashrc"),
+            AbsolutePath::from(util::home_dir().join(".tag-work/bashrc"))
+        );
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn non_unicode_names() {
+        use std::{
+            ffi::{OsStr, OsString},
+            os::unix::ffi::OsStrExt,
+        };
+
+        // "été" in Latin-1
+        let name = OsStr::from_bytes(b"\xe9t\xe9");
+        let dir = dotfiles(&[]);
+        let write = |path: &Path| {
+            let path = dir.path().join(path);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, "").unwrap();
+        };
+        write(Path::new(name));
+        write(&Path::new("vim").join(name));
+        write(&Path::new(".hidden").join(name));
+        let mut hidden = OsString::from(".");
+        hidden.push(name);
+        write(Path::new(&hidden));
+        let mut prefixed = OsString::from("tag-");
+        prefixed.push(name);
+        write(&Path::new(&prefixed).join("vimrc"));
+
+        let config = config(&dir);
+        let mut dests: Vec<_> = get_items(&config)
+            .unwrap()
+            .into_iter()
+            .map(|item| {
+                item.dest
+                    .strip_prefix(&config.target)
+                    .unwrap()
+                    .to_path_buf()
+            })
+            .collect();
+        dests.sort();
+        // Hidden files are still skipped, and the `tag-` folder is still prefixed
+        // (and can't be active)
+        assert_eq!(
+            dests,
+            vec![Path::new(".vim").join(name), PathBuf::from(&hidden)]
+        );
+
+        // The reverse works too
+        assert_eq!(
+            default_source(
+                &AbsolutePath::from(config.target.join(&hidden)),
+                &config.target,
+                &config.dotfiles_paths[0],
+                &GlobSet::empty(),
+            )
+            .unwrap(),
+            AbsolutePath::from(dir.path().join(name))
+        );
+    }
+
+    #[test]
+    fn longest_prefix() {
+        // Both prefixes match, but `tag-` is longer
+        let prefixes = PrefixNames {
+            host: String::from("t"),
+            ..PrefixNames::default()
+        };
+        let split = |name| prefixes.split(Path::new(name));
+        assert_eq!(split("tag-work"), Some((PrefixKind::Tag, "work")));
+        assert_eq!(split("tlaptop"), Some((PrefixKind::Host, "laptop")));
+    }
+
+    #[test]
+    fn platform_dirs() {
+        let dir = TempDir::new().unwrap();
+        let is_active = |platform, name: &str| {
+            let config = Config {
+                platform,
+                ..config(&dir)
+            };
+            Prefixes::new(&config).is_active(Path::new(name))
+        };
+
+        assert!(is_active(Platform::Macos, "platform-macos"));
+        assert!(!is_active(Platform::Wsl, "platform-linux"));
+
+        // Most aliases only apply to `--platform` and the dotrc, so folders for
+        // distributions (and such) don't clash
+        for name in &["platform-ubuntu", "platform-debian", "platform-fedora"] {
+            assert!(!is_active(Platform::Linux, name));
+        }
+        assert!(!is_active(Platform::Macos, "platform-osx"));
+        assert!(!is_active(Platform::FreeBsd, "platform-bsd"));
+        // Folders could always be named with these, though
+        assert!(is_active(Platform::Macos, "platform-mac"));
+        assert!(is_active(Platform::Windows, "platform-win"));
+        assert!(!is_active(Platform::Linux, "platform-mac"));
+
+        // Combined platforms apply if any of them does
+        assert!(is_active(Platform::Macos, "platform-macos+linux"));
+        assert!(is_active(Platform::Linux, "platform-macos+linux"));
+        assert!(!is_active(Platform::Windows, "platform-macos+linux"));
+        assert!(is_active(Platform::Windows, "platform-linux+windows"));
+
+        // `unix` covers everything but Windows
+        for &platform in &[
+            Platform::Macos,
+            Platform::Linux,
+            Platform::Wsl,
+            Platform::FreeBsd,
+            Platform::Unix,
+        ] {
+            assert!(is_active(platform, "platform-unix"));
+        }
+        assert!(!is_active(Platform::Windows, "platform-unix"));
+        assert!(is_active(Platform::Windows, "platform-unix+windows"));
+    }
+
+    #[test]
+    fn platform_aliases_arent_folders() {
+        let dir = dotfiles(&[
+            "platform-linux/vimrc",
+            "platform-ubuntu/vimrc",
+            "platform-fedora/vimrc",
+        ]);
+        let config = Config {
+            platform: Platform::Linux,
+            ..config(&dir)
+        };
+
+        assert_eq!(
+            items(&config),
+            vec![(
+                PathBuf::from("platform-linux/vimrc"),
+                util::home_dir().join(".vimrc")
+            )]
+        );
+    }
+
+    /// The items found with `config`, as their sources (relative to the
+    /// dotfiles directory) and destinations, sorted
+    fn items(config: &Config) -> Vec<(PathBuf, PathBuf)> {
+        let mut items: Vec<_> = get_items(config)
+            .unwrap()
+            .into_iter()
+            .map(|item| {
+                let source = item.source.strip_prefix(&config.dotfiles_paths[0]).unwrap();
+                (source.to_path_buf(), item.dest.to_path_buf())
+            })
+            .collect();
+        items.sort();
+
+        items
+    }
+
+    #[test]
+    fn link_one_to_one() {
+        let dir = dotfiles(&["foo/bar.conf", "vim/vimrc"]);
+        let target = TempDir::new().unwrap();
+        let target = AbsolutePath::from(target.path());
+        let config = Config {
+            links: vec![Link::new("foo/bar.conf", "~/.config/foo/bar.conf", &target).unwrap()],
+            target: target.clone(),
+            ..config(&dir)
+        };
+
+        assert_eq!(
+            items(&config),
+            vec![
+                (
+                    PathBuf::from("foo/bar.conf"),
+                    target.join(".config/foo/bar.conf")
+                ),
+                (PathBuf::from("vim/vimrc"), target.join(".vim/vimrc")),
+            ]
+        );
+    }
+
+    #[test]
+    fn link_glob_into_dir() {
+        let dir = dotfiles(&["bin/a", "host-hostname/bin/b", "vim/vimrc"]);
+        let target = TempDir::new().unwrap();
+        let target = AbsolutePath::from(target.path());
+        let config = Config {
+            links: vec![Link::new("bin/*", "~/.local/bin/", &target).unwrap()],
+            target: target.clone(),
+            ..config(&dir)
+        };
+
+        assert_eq!(
+            items(&config),
+            vec![
+                (PathBuf::from("bin/a"), target.join(".local/bin/a")),
+                (
+                    PathBuf::from("host-hostname/bin/b"),
+                    target.join(".local/bin/b")
+                ),
+                (PathBuf::from("vim/vimrc"), target.join(".vim/vimrc")),
+            ]
+        );
+    }
+
+    #[test]
+    fn prefix_roots() {
+        let dir = dotfiles(&[
+            "foo",
+            "tag-bin/foo",
+            "tag-bin/scripts/deploy",
+            "host-hostname/tag-bin/bar",
+            "tag-bin/host-hostname/baz",
+            "tag-work/gitconfig",
+        ]);
+        let target = TempDir::new().unwrap();
+        let target = AbsolutePath::from(target.path());
+        let config = Config {
+            tags: vec![String::from("bin"), String::from("work")],
+            prefix_roots: vec![
+                PrefixRoot::new("tag-bin", "~/bin", false, &target, &PrefixNames::default())
+                    .unwrap(),
+                PrefixRoot::new("tag-work", "work", true, &target, &PrefixNames::default())
+                    .unwrap(),
+            ],
+            target: target.clone(),
+            ..config(&dir)
+        };
+
+        assert_eq!(
+            items(&config),
+            vec![
+                (PathBuf::from("foo"), target.join(".foo")),
+                // The root applies within nested prefixed folders too
+                (
+                    PathBuf::from("host-hostname/tag-bin/bar"),
+                    target.join("bin/bar")
+                ),
+                (PathBuf::from("tag-bin/foo"), target.join("bin/foo")),
+                (
+                    PathBuf::from("tag-bin/host-hostname/baz"),
+                    target.join("bin/baz")
+                ),
+                (
+                    PathBuf::from("tag-bin/scripts/deploy"),
+                    target.join("bin/scripts/deploy")
+                ),
+                (
+                    PathBuf::from("tag-work/gitconfig"),
+                    target.join("work/.gitconfig")
+                ),
+            ]
+        );
+
+        // Only prefixed folders can have roots
+        assert!(PrefixRoot::new("bin", "~/bin", false, &target, &PrefixNames::default()).is_err());
+    }
+}
@@ -0,0 +1,259 @@
+use crate::{
+    common::{util, AbsolutePath},
+    verbose_println,
+};
+use derive_more::From;
+use failure::Fail;
+use std::{
+    env,
+    ffi::OsStr,
+    fmt::{self, Display},
+    fs::{self, OpenOptions},
+    io::{self, Write},
+    path::{Path, PathBuf},
+    process::{Command, ExitStatus, Stdio},
+};
+
+/// The environment variable naming the identity file `age` decrypts with
+const AGE_IDENTITY_VAR: &str = "DOTMAN_AGE_IDENTITY";
+
+/// A tool which dotfiles can be encrypted with. Files with the tool's extension
+/// are decrypted into their destinations (with the extension stripped) instead
+/// of being linked.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Cipher {
+    Age,
+    Gpg,
+}
+
+impl Cipher {
+    /// The cipher `path` is encrypted with, if any
+    pub fn of(path: &Path) -> Option<Self> {
+        if !path.is_file() {
+            return None;
+        }
+
+        match path.extension().and_then(OsStr::to_str) {
+            Some("age") => Some(Cipher::Age),
+            Some("gpg") => Some(Cipher::Gpg),
+            _ => None,
+        }
+    }
+
+    /// Creates a `Command` which decrypts `path` to stdout
+    fn command(self, path: &Path) -> Command {
+        let mut res = Command::new(self.to_string());
+        match self {
+            Cipher::Age => {
+                res.arg("--decrypt");
+                if let Some(identity) = env::var_os(AGE_IDENTITY_VAR) {
+                    res.arg("--identity").arg(identity);
+                }
+            },
+            Cipher::Gpg => {
+                res.args(["--quiet", "--decrypt"]);
+            },
+        }
+        res.arg(path);
+
+        res
+    }
+}
+
+impl Display for Cipher {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let s = match self {
+            Cipher::Age => "age",
+            Cipher::Gpg => "gpg",
+        };
+
+        f.pad(s)
+    }
+}
+
+/// Checks whether `path` is an encrypted dotfile
+pub fn is_encrypted(path: &Path) -> bool {
+    Cipher::of(path).is_some()
+}
+
+/// Decrypts `source` in memory.
+///
+/// The decryptor can still prompt for a passphrase, since only its stdout is
+/// captured.
+pub fn decrypt(source: &AbsolutePath) -> Result<Vec<u8>, Error> {
+    let cipher = Cipher::of(source).ok_or_else(|| NotEncrypted(source.clone()))?;
+    verbose_println!("Decrypting {} with {}", source, cipher);
+
+    let output = match cipher
+        .command(source)
+        .stdin(Stdio::inherit())
+        .stderr(Stdio::inherit())
+        .output()
+    {
+        Ok(output) => output,
+        Err(ref err) if err.kind() == io::ErrorKind::NotFound => {
+            return Err(MissingDecryptor(cipher))
+        },
+        Err(err) => return Err(err.into()),
+    };
+    if !output.status.success() {
+        return Err(DecryptFailed {
+            path: source.clone(),
+            status: output.status,
+        });
+    }
+
+    Ok(output.stdout)
+}
+
+/// Decrypts `source` into a new file at `dest`, which must be free.
+///
+/// The plaintext is written straight to `dest`, readable only by its owner, and
+/// never to a temporary file. `dest` must lie outside `dotfiles_paths`, so the
+/// plaintext can't end up committed alongside its ciphertext. That includes
+/// destinations inside a symlink into a dotfiles folder (as when a whole folder
+/// is linked).
+pub fn decrypt_to(
+    source: &AbsolutePath,
+    dest: &AbsolutePath,
+    dotfiles_paths: &[AbsolutePath],
+) -> Result<(), Error> {
+    let real_dest = real_path(dest);
+    if dotfiles_paths.iter().any(|dotfiles_path| {
+        dest.starts_with(dotfiles_path) || real_dest.starts_with(real_path(dotfiles_path))
+    }) {
+        return Err(PlaintextInDotfiles(dest.clone()));
+    }
+
+    let plaintext = decrypt(source)?;
+
+    let mut options = OpenOptions::new();
+    // `create_new` also refuses to follow a symlink at `dest`
+    options.write(true).create_new(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+        options.mode(0o600);
+    }
+    let mut file = options.open(dest)?;
+    // The file was created here, so a partial one (which would otherwise be
+    // taken for the whole thing) can safely be removed
+    if let Err(err) = file.write_all(&plaintext) {
+        drop(file);
+        let _ = fs::remove_file(dest);
+        return Err(err.into());
+    }
+
+    Ok(())
+}
+
+/// Finds where `path` really is, resolving symlinks among whichever of it and
+/// its ancestors exist
+fn real_path(path: &Path) -> PathBuf {
+    path.ancestors()
+        .find_map(|ancestor| {
+            let real_ancestor = ancestor.canonicalize().ok()?;
+            let rest = path.strip_prefix(ancestor).ok()?;
+
+            Some(real_ancestor.join(rest))
+        })
+        .unwrap_or_else(|| path.to_path_buf())
+}
+
+/// Checks whether the file at `dest` holds the decrypted contents of `source`
+pub fn is_decrypted_at(source: &AbsolutePath, dest: &Path) -> Result<bool, Error> {
+    if util::file_type(dest)? != util::FileType::File {
+        return Ok(false);
+    }
+
+    Ok(decrypt(source)? == std::fs::read(dest)?)
+}
+
+#[derive(Debug, From, Fail)]
+pub enum Error {
+    #[fail(display = "{} isn't encrypted", _0)]
+    NotEncrypted(AbsolutePath),
+
+    #[fail(
+        display = "couldn't find {} to decrypt with (is it installed and on your PATH?)",
+        _0
+    )]
+    MissingDecryptor(Cipher),
+
+    #[fail(display = "error decrypting {} ({})", path, status)]
+    DecryptFailed {
+        path: AbsolutePath,
+        status: ExitStatus,
+    },
+
+    #[fail(
+        display = "refusing to decrypt into {}, which is inside a dotfiles folder",
+        _0
+    )]
+    #[from(ignore)]
+    PlaintextInDotfiles(AbsolutePath),
+
+    #[fail(display = "error decrypting ({})", _0)]
+    IoError(#[fail(cause)] io::Error),
+}
+use Error::*;
+
+#[cfg(test)]
+mod tests {
+    use super::{decrypt_to, Cipher, Error};
+    use crate::common::AbsolutePath;
+    use pretty_assertions::assert_eq;
+    use std::fs;
+    use tempfile::TempDir;
+
+    #[test]
+    fn ciphers() {
+        let dir = TempDir::new().unwrap();
+        for name in &["netrc.age", "netrc.gpg", "netrc", "netrc.tmpl"] {
+            fs::write(dir.path().join(name), "").unwrap();
+        }
+        fs::create_dir(dir.path().join("keys.gpg")).unwrap();
+
+        let cipher = |name: &str| Cipher::of(&dir.path().join(name));
+        assert_eq!(cipher("netrc.age"), Some(Cipher::Age));
+        assert_eq!(cipher("netrc.gpg"), Some(Cipher::Gpg));
+        assert_eq!(cipher("netrc"), None);
+        assert_eq!(cipher("netrc.tmpl"), None);
+        // Only files are decrypted
+        assert_eq!(cipher("keys.gpg"), None);
+    }
+
+    #[test]
+    fn plaintext_in_dotfiles() {
+        let dotfiles = TempDir::new().unwrap();
+        let source = AbsolutePath::from(dotfiles.path().join("netrc.age"));
+        fs::write(&source, "").unwrap();
+        let dest = AbsolutePath::from(dotfiles.path().join("netrc"));
+
+        match decrypt_to(&source, &dest, &[AbsolutePath::from(dotfiles.path())]) {
+            Err(Error::PlaintextInDotfiles(path)) => assert_eq!(path, dest),
+            res => panic!("unexpected result {:?}", res),
+        }
+        assert!(!dest.exists());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn plaintext_in_symlinked_dotfiles() {
+        let dotfiles = TempDir::new().unwrap();
+        let home = TempDir::new().unwrap();
+        let source = AbsolutePath::from(dotfiles.path().join("netrc.age"));
+        fs::write(&source, "").unwrap();
+        // As if `config` were linked wholesale to `~/.config`
+        fs::create_dir(dotfiles.path().join("config")).unwrap();
+        std::os::unix::fs::symlink(dotfiles.path().join("config"), home.path().join(".config"))
+            .unwrap();
+        let dest = AbsolutePath::from(home.path().join(".config/netrc"));
+
+        match decrypt_to(&source, &dest, &[AbsolutePath::from(dotfiles.path())]) {
+            Err(Error::PlaintextInDotfiles(path)) => assert_eq!(path, dest),
+            res => panic!("unexpected result {:?}", res),
+        }
+        assert!(!dotfiles.path().join("config/netrc").exists());
+    }
+}
@@ -0,0 +1,129 @@
+use crate::common::{util, FormattedItem, FormattedItems};
+use derive_more::From;
+use failure::Fail;
+use itertools::Itertools;
+use std::{
+    fmt::{self, Display},
+    io,
+};
+use strum::IntoEnumIterator;
+use strum_macros::EnumIter;
+
+/// The health of a single item's link
+#[derive(Debug, Clone, Copy, PartialEq, Eq, EnumIter)]
+pub enum State {
+    /// `dest` is a symlink to `source`
+    Linked,
+
+    /// Nothing exists at `dest`
+    Missing,
+
+    /// Something other than a symlink to `source` exists at `dest`
+    Conflict,
+
+    /// `dest` is a symlink whose target doesn't exist
+    Broken,
+}
+use State::*;
+
+impl Display for State {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let s = match self {
+            Linked => "Linked",
+            Missing => "Missing",
+            Conflict => "Conflict",
+            Broken => "Broken",
+        };
+
+        f.pad(s)
+    }
+}
+
+impl State {
+    /// Determines the state of `item` by inspecting its destination
+    fn of(item: &FormattedItem) -> Result<Self, Error> {
+        let (source, dest) = (&item.item().source, &item.item().dest);
+
+        let file_type = match util::file_type(dest) {
+            Ok(file_type) => file_type,
+            Err(ref err) if err.kind() == io::ErrorKind::NotFound => return Ok(Missing),
+            Err(err) => return Err(err.into()),
+        };
+
+        Ok(match file_type {
+            // `exists` follows the symlink, so this checks the target
+            util::FileType::Symlink if !dest.exists() => Broken,
+            util::FileType::Symlink
+                if util::read_link_absolute(dest)?.as_path() == source.as_path() =>
+            {
+                Linked
+            },
+            util::FileType::Symlink | util::FileType::File | util::FileType::Directory => Conflict,
+        })
+    }
+
+    /// Whether this state needs the user's attention
+    pub fn is_problem(self) -> bool {
+        match self {
+            Linked | Missing => false,
+            Conflict | Broken => true,
+        }
+    }
+}
+
+/// The state of every active item
+#[derive(Debug)]
+pub struct Status {
+    entries: Vec<(State, FormattedItem)>,
+}
+
+impl Status {
+    /// Classifies each of `items` according to its destination
+    pub fn get(items: FormattedItems) -> Result<Self, Error> {
+        let entries = items
+            .into_iter()
+            .map(|item| Ok((State::of(&item)?, item)))
+            .collect::<Result<_, Error>>()?;
+
+        Ok(Status { entries })
+    }
+
+    /// Whether any item is in a state that needs the user's attention
+    pub fn has_problems(&self) -> bool {
+        self.entries.iter().any(|(state, _)| state.is_problem())
+    }
+}
+
+impl Display for Status {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let groups = State::iter()
+            .filter_map(|state| {
+                let items: Vec<_> = self
+                    .entries
+                    .iter()
+                    .filter(|(item_state, _)| *item_state == state)
+                    .map(|(_, item)| format!("    {}", item))
+                    .collect();
+
+                if items.is_empty() {
+                    None
+                } else {
+                    Some(format!(
+                        "{} ({}):\n{}",
+                        state,
+                        items.len(),
+                        items.join("\n")
+                    ))
+                }
+            })
+            .join("\n\n");
+
+        f.pad(&groups)
+    }
+}
+
+#[derive(Debug, From, Fail)]
+pub enum Error {
+    #[fail(display = "error reading destination ({})", _0)]
+    IoError(#[fail(cause)] io::Error),
+}
@@ -0,0 +1,78 @@
+use crate::{
+    config::Config,
+    resolver::{self, PrefixKind, PrefixNames, Prefixes},
+};
+use derive_more::From;
+use failure::Fail;
+use std::{
+    collections::BTreeMap,
+    fmt::{self, Display},
+    path::Path,
+};
+
+/// The prefixed folders (`host-`, `tag-`, `platform-`, and `arch-`, unless the
+/// dotrc renames them) which exist in the dotfiles folders, for discovering
+/// which tags and such are worth enabling
+#[derive(Debug)]
+pub struct Report {
+    prefixes: PrefixNames,
+    /// The names (without their prefix) of each kind of prefixed folder found,
+    /// along with whether folders with that name are active
+    names: BTreeMap<PrefixKind, BTreeMap<String, bool>>,
+}
+
+impl Report {
+    /// Searches every dotfiles folder in `config`, including inactive prefixed
+    /// folders (see `resolver::all_prefixed_dirs`)
+    pub fn get(config: &Config) -> Result<Self, Error> {
+        let prefixes = Prefixes::new(config);
+        let mut names: BTreeMap<_, BTreeMap<_, _>> = BTreeMap::new();
+        for dotfiles_path in config.dotfiles_paths.iter().filter(|path| path.is_dir()) {
+            let dirs = resolver::all_prefixed_dirs(dotfiles_path, &prefixes, &config.excludes)?;
+            for (dir, active) in dirs {
+                let name = dir
+                    .file_name()
+                    .map(Path::new)
+                    .and_then(|name| config.prefixes.split(name));
+                if let Some((kind, name)) = name {
+                    names
+                        .entry(kind)
+                        .or_default()
+                        .insert(String::from(name), active);
+                }
+            }
+        }
+
+        Ok(Report {
+            prefixes: config.prefixes.clone(),
+            names,
+        })
+    }
+}
+
+impl Display for Report {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.names.is_empty() {
+            return write!(f, "No prefixed folders found");
+        }
+
+        for (i, (kind, names)) in self.names.iter().enumerate() {
+            if i > 0 {
+                write!(f, "\n\n")?;
+            }
+            write!(f, "{} folders:", self.prefixes.get(*kind))?;
+            for (name, active) in names {
+                let state = if *active { "active" } else { "inactive" };
+                write!(f, "\n    {:8}  {}", state, name)?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[derive(Debug, From, Fail)]
+pub enum Error {
+    #[fail(display = "error searching dotfiles folders ({})", _0)]
+    ResolverError(#[fail(cause)] resolver::Error),
+}
@@ -0,0 +1,173 @@
+use crate::{
+    common::{util, AbsolutePath, Item},
+    config::Config,
+    verbose_println,
+};
+use derive_more::From;
+use failure::Fail;
+use std::{collections::HashMap, ffi::OsStr, fs, io, path::Path};
+
+/// Files with this extension are rendered as templates before being linked.
+/// The extension is stripped from their destinations.
+pub const TEMPLATE_EXTENSION: &str = "tmpl";
+
+/// The folder (within the cache directory) which rendered templates are
+/// written to
+const RENDERED_DIR: &str = "templates";
+
+/// Checks whether `path` is a template
+pub fn is_template(path: &Path) -> bool {
+    path.is_file() && path.extension() == Some(OsStr::new(TEMPLATE_EXTENSION))
+}
+
+/// The variables available to templates
+fn variables(config: &Config) -> HashMap<&'static str, String> {
+    let mut variables = HashMap::new();
+    variables.insert("hostname", config.hostnames[0].clone());
+    variables.insert("platform", config.platform.to_string());
+    variables.insert("home", util::home_dir().display().to_string());
+
+    variables
+}
+
+/// Substitutes each `{{ name }}` in `contents` with the value of the variable
+/// `name`.
+fn render(contents: &str, variables: &HashMap<&str, String>) -> Result<String, RenderError> {
+    let mut res = String::with_capacity(contents.len());
+    let mut rest = contents;
+    while let Some(start) = rest.find("{{") {
+        res.push_str(&rest[..start]);
+        rest = &rest[start + 2..];
+
+        let end = rest.find("}}").ok_or(RenderError::Unterminated)?;
+        let name = rest[..end].trim();
+        match variables.get(name) {
+            Some(value) => res.push_str(value),
+            None => return Err(RenderError::UnknownVariable(name.to_owned())),
+        }
+        rest = &rest[end + 2..];
+    }
+    res.push_str(rest);
+
+    Ok(res)
+}
+
+/// Replaces the source of each template in `items` with the rendered version
+/// of that template.
+///
+/// The rendered files are only written if `write` is set. Otherwise, only
+/// their paths are computed.
+pub fn render_items(items: Vec<Item>, config: &Config, write: bool) -> Result<Vec<Item>, Error> {
+    let variables = variables(config);
+    let rendered_dir = util::cache_dir().join(RENDERED_DIR);
+
+    items
+        .into_iter()
+        .map(|item| {
+            if !is_template(&item.source) {
+                return Ok(item);
+            }
+
+            let relative_source = config
+                .dotfiles_paths
+                .iter()
+                .find_map(|dotfiles_path| item.source.strip_prefix(dotfiles_path).ok())
+                .unwrap_or(&item.source);
+            let rendered =
+                AbsolutePath::from(rendered_dir.join(relative_source).with_extension(""));
+
+            if write {
+                verbose_println!("Rendering {} to {}", item.source, rendered);
+                let contents = fs::read_to_string(&item.source)?;
+                let contents = render(&contents, &variables).map_err(|err| Render {
+                    path: item.source.clone(),
+                    err,
+                })?;
+
+                fs::create_dir_all(rendered.parent().unwrap_or(&rendered))?;
+                fs::write(&rendered, contents)?;
+            }
+
+            Ok(Item {
+                source: rendered,
+                ..item
+            })
+        })
+        .collect()
+}
+
+/// Checks that the template at `source` renders under `config`, without
+/// writing the result anywhere
+pub fn check(source: &AbsolutePath, config: &Config) -> Result<(), Error> {
+    let contents = fs::read_to_string(source)?;
+    render(&contents, &variables(config)).map_err(|err| Render {
+        path: source.clone(),
+        err,
+    })?;
+
+    Ok(())
+}
+
+#[derive(Debug, Fail)]
+pub enum RenderError {
+    #[fail(display = "unknown variable \"{}\"", _0)]
+    UnknownVariable(String),
+
+    #[fail(display = "unterminated \"{{{{\"")]
+    Unterminated,
+}
+
+#[derive(Debug, From, Fail)]
+pub enum Error {
+    #[fail(display = "error rendering template {} ({})", path, err)]
+    Render {
+        path: AbsolutePath,
+        #[fail(cause)]
+        err: RenderError,
+    },
+
+    #[fail(display = "error rendering templates ({})", _0)]
+    IoError(#[fail(cause)] io::Error),
+}
+use Error::*;
+
+#[cfg(test)]
+mod tests {
+    use super::{render, RenderError};
+    use pretty_assertions::assert_eq;
+    use std::collections::HashMap;
+
+    fn variables() -> HashMap<&'static str, String> {
+        let mut variables = HashMap::new();
+        variables.insert("hostname", String::from("laptop"));
+        variables.insert("home", String::from("/home/me"));
+
+        variables
+    }
+
+    #[test]
+    fn substitutes_variables() {
+        let contents = "host = {{hostname}}\npath = {{ home }}/bin\n";
+
+        assert_eq!(
+            render(contents, &variables()).unwrap(),
+            "host = laptop\npath = /home/me/bin\n"
+        );
+    }
+
+    #[test]
+    fn unknown_variable() {
+        match render("{{ username }}", &variables()) {
+            Err(RenderError::UnknownVariable(name)) => assert_eq!(name, "username"),
+            res => panic!("unexpected result {:?}", res),
+        }
+    }
+
+    #[test]
+    fn unterminated() {
+        match render("{{ hostname ", &variables()) {
+            Err(RenderError::Unterminated) => (),
+            res => panic!("unexpected result {:?}", res),
+        }
+    }
+}
@@ -0,0 +1,149 @@
+use crate::{
+    config::Config,
+    resolver::{self, PrefixKind, Prefixes, TAG_SEPARATOR},
+    template,
+};
+use derive_more::From;
+use failure::Fail;
+use std::{
+    collections::HashSet,
+    fmt::{self, Display},
+    path::{Path, PathBuf},
+};
+
+/// Something which would keep the dotfiles from being linked as configured
+#[derive(Debug)]
+pub enum Problem {
+    /// The dotfiles couldn't be resolved, as when two of them share a
+    /// destination
+    Unresolved(resolver::Error),
+
+    /// A template which doesn't render
+    Template(template::Error),
+
+    /// An enabled tag which no `tag-` folder uses
+    UnusedTag(String),
+
+    /// An exclude glob (as written) which doesn't match anything
+    DeadExclude(PathBuf),
+}
+
+impl Display for Problem {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Problem::Unresolved(err) => write!(f, "{}", err),
+            Problem::Template(err) => write!(f, "{}", err),
+            Problem::UnusedTag(tag) => write!(f, "tag \"{}\" isn't used by any tag- folder", tag),
+            Problem::DeadExclude(exclude) => {
+                write!(f, "exclude {} doesn't match anything", exclude.display())
+            },
+        }
+    }
+}
+
+/// The result of checking that the dotfiles resolve cleanly, for running
+/// non-interactively (as in CI). Unlike `doctor::Report`, this only reports
+/// what's wrong.
+#[derive(Debug)]
+pub struct Report {
+    /// How many dotfiles would be linked, if they could be resolved
+    items: Option<usize>,
+    problems: Vec<Problem>,
+}
+
+impl Report {
+    /// Runs every check under `config`. Nothing is linked, and nothing outside
+    /// the dotfiles folders is touched (templates are rendered in memory, and
+    /// encrypted dotfiles aren't decrypted), so the target folder needn't hold
+    /// any dotfiles yet.
+    ///
+    /// Requirements' commands are run in the system shell (see
+    /// `hook::succeeds`).
+    pub fn get(config: &Config) -> Result<Self, Error> {
+        let mut problems = vec![];
+
+        let items = match resolver::get_items(config) {
+            Ok(items) => {
+                for item in items
+                    .iter()
+                    .filter(|item| template::is_template(&item.source))
+                {
+                    if let Err(err) = template::check(&item.source, config) {
+                        problems.push(Problem::Template(err));
+                    }
+                }
+
+                Some(items.len())
+            },
+            Err(err) => {
+                problems.push(Problem::Unresolved(err));
+                None
+            },
+        };
+
+        let prefixes = Prefixes::new(config);
+        let mut used_tags = HashSet::new();
+        for dotfiles_path in config.dotfiles_paths.iter().filter(|path| path.is_dir()) {
+            let dirs = resolver::all_prefixed_dirs(dotfiles_path, &prefixes, &config.excludes)?;
+            for (dir, _) in dirs {
+                let name = dir
+                    .file_name()
+                    .map(Path::new)
+                    .and_then(|name| config.prefixes.split(name));
+                if let Some((PrefixKind::Tag, tags)) = name {
+                    used_tags.extend(tags.split(TAG_SEPARATOR).map(String::from));
+                }
+            }
+        }
+        problems.extend(
+            config
+                .tags
+                .iter()
+                .filter(|tag| !used_tags.contains(*tag))
+                .cloned()
+                .map(Problem::UnusedTag),
+        );
+
+        problems.extend(
+            config
+                .dead_excludes
+                .iter()
+                .cloned()
+                .map(Problem::DeadExclude),
+        );
+
+        Ok(Report { items, problems })
+    }
+
+    pub fn problems(&self) -> &[Problem] {
+        &self.problems
+    }
+
+    pub fn has_problems(&self) -> bool {
+        !self.problems.is_empty()
+    }
+}
+
+impl Display for Report {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if !self.has_problems() {
+            let items = self.items.unwrap_or_default();
+            let s = if items == 1 { "" } else { "s" };
+            return write!(f, "Everything resolves cleanly ({} dotfile{})", items, s);
+        }
+
+        let s = if self.problems.len() == 1 { "" } else { "s" };
+        write!(f, "Found {} problem{}:", self.problems.len(), s)?;
+        for problem in &self.problems {
+            write!(f, "\n    {}", problem)?;
+        }
+
+        Ok(())
+    }
+}
+
+#[derive(Debug, From, Fail)]
+pub enum Error {
+    #[fail(display = "error searching dotfiles folders ({})", _0)]
+    ResolverError(#[fail(cause)] resolver::Error),
+}
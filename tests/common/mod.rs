@@ -1,3 +1,12 @@
-pub fn setup() -> tempfile::TempDir {
-    unimplemented!()
+use std::fs;
+use tempfile::TempDir;
+
+/// Creates a temporary directory holding an empty `dotfiles` folder and an
+/// empty `target` folder to link into
+pub fn setup() -> TempDir {
+    let dir = TempDir::new().unwrap();
+    fs::create_dir(dir.path().join("dotfiles")).unwrap();
+    fs::create_dir(dir.path().join("target")).unwrap();
+
+    dir
 }
@@ -2,7 +2,227 @@ extern crate lib;
 
 mod common;
 
+use globset::GlobSet;
+use lib::{
+    common::{util, AbsolutePath, FormattedItems, Platform},
+    config::{cli::Command, Config, RawGlobs, Sources},
+    linker,
+    resolver::{self, PrefixNames},
+};
+use std::{
+    fs,
+    io::Write,
+    path::{Path, PathBuf},
+    process,
+};
+
+fn options() -> linker::Options {
+    linker::Options {
+        dry_run: false,
+        strategy: linker::Strategy::Symlink,
+        backup: false,
+        on_conflict: None,
+        relative: false,
+        parallel: false,
+        diff: false,
+        atomic: false,
+        progress: false,
+        answer: None,
+    }
+}
+
+/// A configuration linking everything in `dotfiles` into `target`
+fn config(dotfiles: &Path, target: &Path) -> Config {
+    Config {
+        raw_globs: RawGlobs::default(),
+        excludes: vec![],
+        dead_excludes: vec![],
+        dest_excludes: GlobSet::empty(),
+        includes: None,
+        tags: vec![],
+        dotfiles_paths: vec![AbsolutePath::from(dotfiles)],
+        remote_clones: vec![],
+        hostnames: vec![String::from("hostname")],
+        platform: Platform::Linux,
+        arch: String::from("x86_64"),
+        target: AbsolutePath::from(target),
+        links: vec![],
+        prefix_roots: vec![],
+        prefixes: PrefixNames::default(),
+        no_dot: GlobSet::empty(),
+        link_first: GlobSet::empty(),
+        link_last: GlobSet::empty(),
+        requirements: vec![],
+        modes: vec![],
+        follow_source_links: false,
+        pre_link_hooks: vec![],
+        post_link_hooks: vec![],
+        auto_pull: false,
+        conflict_policy: None,
+        cache: false,
+        sources: Sources::default(),
+        command: Command::Link {
+            options: options(),
+            pull: false,
+            interactive: false,
+            only: vec![],
+            from_stdin: false,
+            prune: false,
+        },
+    }
+}
+
+#[test]
+fn link_into_target() {
+    let dir = common::setup();
+    let (dotfiles, target) = (dir.path().join("dotfiles"), dir.path().join("target"));
+    fs::create_dir(dotfiles.join("vim")).unwrap();
+    fs::write(dotfiles.join("vim/vimrc"), "").unwrap();
+
+    let config = config(&dotfiles, &target);
+    let items = resolver::get_items(&config).unwrap();
+    linker::link_items(
+        FormattedItems::from_items(items),
+        &config.dotfiles_paths,
+        options(),
+    )
+    .unwrap();
+
+    assert_eq!(
+        fs::read_link(target.join(".vim/vimrc")).unwrap(),
+        dotfiles.join("vim/vimrc")
+    );
+}
+
+// The home directory can only be overridden once per process, so this is the
+// only test which does
+#[test]
+fn link_into_home() {
+    let dir = common::setup();
+    let (dotfiles, home) = (dir.path().join("dotfiles"), dir.path().join("target"));
+    fs::write(dotfiles.join("bashrc"), "").unwrap();
+    util::set_home_dir(&home).unwrap();
+    // Nothing should end up in the actual cache either
+    assert_eq!(util::cache_dir(), home.join(".cache/dotman"));
+
+    let config = config(&dotfiles, util::home_dir());
+    let items = resolver::get_items(&config).unwrap();
+    assert_eq!(
+        util::home_to_tilde(&items[0].dest),
+        PathBuf::from("~/.bashrc")
+    );
+    linker::link_items(
+        FormattedItems::from_items(items),
+        &config.dotfiles_paths,
+        options(),
+    )
+    .unwrap();
+
+    assert_eq!(
+        fs::read_link(util::tilde_to_home("~/.bashrc")).unwrap(),
+        dotfiles.join("bashrc")
+    );
+}
+
 #[test]
-fn test() {
-    // let dir = common::setup();
+fn json_errors() {
+    let dir = common::setup();
+    let dotfiles = dir.path().join("dotfiles");
+    fs::write(dotfiles.join("dotrc"), "platform: plan9\n").unwrap();
+
+    let output = process::Command::new(env!("CARGO_BIN_EXE_dot"))
+        .arg("--json-errors")
+        .arg("--dotfiles-path")
+        .arg(&dotfiles)
+        .arg("ls")
+        .env("HOME", dir.path().join("target"))
+        .output()
+        .unwrap();
+    assert_eq!(output.status.code(), Some(1));
+
+    let err: serde_json::Value = serde_json::from_slice(&output.stderr).unwrap();
+    let message = "unsupported platform \"plan9\" (valid platforms are windows, macos, linux, \
+                   wsl, freebsd, unix)";
+    assert_eq!(
+        err,
+        serde_json::json!({
+            "kind": "InvalidPlatform",
+            "message": message,
+            "causes": [{ "kind": "PlatformParseError", "message": message }],
+        })
+    );
+}
+
+#[test]
+fn link_from_stdin() {
+    let dir = common::setup();
+    let (dotfiles, target) = (dir.path().join("dotfiles"), dir.path().join("target"));
+    let (source, dest) = (dir.path().join("plan/vimrc"), target.join(".config/vimrc"));
+    fs::create_dir(dir.path().join("plan")).unwrap();
+    fs::write(&source, "").unwrap();
+
+    let mut child = process::Command::new(env!("CARGO_BIN_EXE_dot"))
+        .arg("--dotfiles-path")
+        .arg(&dotfiles)
+        .args(["link", "--from-stdin"])
+        .env("HOME", &target)
+        .stdin(process::Stdio::piped())
+        .stdout(process::Stdio::null())
+        .spawn()
+        .unwrap();
+    let plan = format!("{}\t{}\n", source.display(), dest.display());
+    child
+        .stdin
+        .take()
+        .unwrap()
+        .write_all(plan.as_bytes())
+        .unwrap();
+    assert_eq!(child.wait().unwrap().code(), Some(0));
+
+    assert_eq!(fs::read_link(&dest).unwrap(), source);
+}
+
+#[test]
+fn verify() {
+    let dir = common::setup();
+    let (dotfiles, target) = (dir.path().join("dotfiles"), dir.path().join("target"));
+    fs::create_dir(dotfiles.join("tag-work")).unwrap();
+    fs::write(dotfiles.join("tag-work/vimrc"), "").unwrap();
+    fs::write(
+        dotfiles.join("gitconfig.tmpl"),
+        "[user]\n    name = {{ name }}\n",
+    )
+    .unwrap();
+
+    let verify = |tags: &[&str]| {
+        let mut command = process::Command::new(env!("CARGO_BIN_EXE_dot"));
+        command.arg("--dotfiles-path").arg(&dotfiles);
+        for tag in tags {
+            command.arg("--tag").arg(tag);
+        }
+
+        command.arg("verify").env("HOME", &target).output().unwrap()
+    };
+
+    let output = verify(&["work", "wrok"]);
+    assert_eq!(output.status.code(), Some(2));
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert!(stdout.starts_with("Found 2 problems:"));
+    assert!(stdout.contains("unknown variable \"name\""));
+    assert!(stdout.contains("tag \"wrok\" isn't used by any tag- folder"));
+
+    fs::write(
+        dotfiles.join("gitconfig.tmpl"),
+        "[core]\n    editor = vim\n",
+    )
+    .unwrap();
+    let output = verify(&["work"]);
+    assert_eq!(output.status.code(), Some(0));
+    assert_eq!(
+        String::from_utf8(output.stdout).unwrap(),
+        "Everything resolves cleanly (2 dotfiles)\n"
+    );
+
+    // Nothing is linked
+    assert_eq!(fs::read_dir(&target).unwrap().count(), 0);
 }